@@ -1,5 +1,6 @@
 use core::panic;
 use std::{collections::VecDeque, fmt::Debug};
+use log::info;
 use crate::{cop0::{Cop0, Exception}, mmu::Mmu};
 
 const OPCODES_SPEC: [(u32, &'static str); 29] = [
@@ -198,9 +199,9 @@ impl Cpu {
     let exe_size = u32::from_le_bytes(exe[0x1C..0x20].try_into().unwrap());
     let initial_sp   = u32::from_le_bytes(exe[0x30..0x34].try_into().unwrap());
   
-    println!("Exe start: {exe_ram_addr}");
-    println!("Exe size: {exe_size}");
-    println!("Exe actual size: {}", exe.len());
+    info!("Exe start: {exe_ram_addr}");
+    info!("Exe size: {exe_size}");
+    info!("Exe actual size: {}", exe.len());
 
     self.mmu.ram[exe_ram_addr as usize .. (exe_ram_addr + exe_size) as usize]
       .copy_from_slice(&exe[2048..2048 + exe_size as usize]);
@@ -215,7 +216,7 @@ impl Cpu {
     self.pc = initial_pc;
     self.next_pc = self.pc + 4;
 
-    println!("Exe sideloaded!");
+    info!("Exe sideloaded!");
   }
 
   fn reg(&self, reg: Reg) -> u32 {
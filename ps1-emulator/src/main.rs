@@ -1,6 +1,8 @@
 use ps1_emulator::{cpu::Cpu, mmu::{Bios, Mmu}};
 
 fn main() {
+  env_logger::init();
+
   let bios = Bios::new("ps-22a.bin").unwrap();
   let mmu = Mmu::new(bios);
   let mut cpu = Cpu::new(mmu);
@@ -1,4 +1,5 @@
 use std::{fs, io::{self, Read}};
+use log::debug;
 
 fn read8(data: &[u8], offset: u32) -> u32 {
   let offset = offset as usize;
@@ -168,19 +169,19 @@ impl Mmu {
     } else if let Some(offset) = Self::RAM.contains(addr) {
       access(&self.ram, offset % (2048*1024))
     } else if let Some(offset) = Self::EXP1.contains(addr) {
-      eprintln!("unhandled read to EXP1: {:08x}", offset);
+      debug!("unhandled read to EXP1: {:08x}", offset);
       0xff
     } else if let Some(offset) = Self::IRQ_CTRL.contains(addr) {
-      eprintln!("unhandled write to IRQ_CTRL: {:08x}", offset);
+      debug!("unhandled write to IRQ_CTRL: {:08x}", offset);
       0
     } else if let Some(offset) = Self::DMA.contains(addr) {
-      eprintln!("unhandled write to DMA: {:08x}", offset);
+      debug!("unhandled write to DMA: {:08x}", offset);
       0
     } else if let Some(offset) = Self::SPU.contains(addr) {
-      eprintln!("unhandled write to SPU: {:08x}", offset);
+      debug!("unhandled write to SPU: {:08x}", offset);
       0
     } else if let Some(offset) = Self::GPU.contains(addr) {
-      eprintln!("unhandled write to GPU: {:08x}", offset);
+      debug!("unhandled write to GPU: {:08x}", offset);
       0
     } else {
       // panic!("unhandled address range read: {:08x}", addr)
@@ -196,23 +197,23 @@ impl Mmu {
     if let Some(offset) = Self::RAM.contains(addr) {
       access(&mut self.ram, offset % (2048*1024), val);
     } else if let Some(offset) = Self::SYS_CTRL.contains(addr) {
-      eprintln!("unhandled write to MEM_CTRL {:08x}", offset);
+      debug!("unhandled write to MEM_CTRL {:08x}", offset);
     } else if let Some(offset) = Self::RAM_CTRL.contains(addr) {
-      eprintln!("unhandled write to RAM_CTRL {:08x}", offset)
+      debug!("unhandled write to RAM_CTRL {:08x}", offset)
     } else if let Some(offset) = Self::CACHE_CTRL.contains(addr) {
-      eprintln!("unhandled write to CACHE_CTRL {:08x}", offset)
+      debug!("unhandled write to CACHE_CTRL {:08x}", offset)
     } else if let Some(offset) = Self::SPU.contains(addr) {
-      eprintln!("unhandled write to SPU {:08x}", offset)
+      debug!("unhandled write to SPU {:08x}", offset)
     } else if let Some(offset) = Self::EXP2.contains(addr) {
-      eprintln!("unhandled write to EXP2 {:08x}", offset)
+      debug!("unhandled write to EXP2 {:08x}", offset)
     } else if let Some(offset) = Self::IRQ_CTRL.contains(addr) {
-      eprintln!("unhandled write to IRQ_CTRL: {:08x}", offset);
+      debug!("unhandled write to IRQ_CTRL: {:08x}", offset);
     } else if let Some(offset) = Self::TIMERS.contains(addr) {
-      eprintln!("unhandled write to TIMERS: {:08x}", offset);
+      debug!("unhandled write to TIMERS: {:08x}", offset);
     } else if let Some(offset) = Self::DMA.contains(addr) {
-      eprintln!("unhandled write to DMA: {:08x}", offset);
+      debug!("unhandled write to DMA: {:08x}", offset);
     } else if let Some(offset) = Self::GPU.contains(addr) {
-      eprintln!("unhandled write to GPU: {:08x}", offset);
+      debug!("unhandled write to GPU: {:08x}", offset);
     } else {
       // panic!("unhandled address range write: {:08x} {:x}", addr, val);
     }
@@ -0,0 +1,158 @@
+use std::{path::{Path, PathBuf}, sync::mpsc::{Receiver, TryRecvError}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+
+use log::error;
+
+use crate::{emu_thread::EmuThread, hash::fnv1a, storage};
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Keeps the battery save on disk in step with the cartridge RAM.
+/// Every few seconds, the RAM is taken if it changed since the last time, and written from a background thread if it differs from the file.
+pub struct Battery {
+  // hash of what's on disk
+  saved: Option<u64>,
+  last_check: Instant,
+  // cartridge RAM asked to the emulation thread, arrives once it's between frames
+  request: Option<Receiver<Option<Vec<u8>>>>,
+  writer: Option<JoinHandle<Result<(), String>>>,
+}
+
+impl Default for Battery {
+  fn default() -> Self {
+    Self { saved: None, last_check: Instant::now(), request: None, writer: None }
+  }
+}
+
+/// Tells when the cartridge RAM changed, by comparing it with a copy of how it was last taken.
+// the cores don't say when the game writes to it, comparing a few KiB every few seconds costs next to nothing
+#[derive(Default)]
+pub struct SramTracker {
+  snapshot: Option<Vec<u8>>,
+}
+
+impl SramTracker {
+  /// A copy of the RAM, when it differs from the last one taken.
+  pub fn take_changed(&mut self, sram: Option<&[u8]>) -> Option<Vec<u8>> {
+    let sram = sram?;
+    if self.snapshot.as_deref() == Some(sram) { return None; }
+    let snapshot = self.snapshot.get_or_insert_with(Vec::new);
+    snapshot.clear();
+    snapshot.extend_from_slice(sram);
+    Some(sram.to_vec())
+  }
+
+  /// Forgets the last copy, for RAM that may have been replaced: the next take returns it whatever it is.
+  pub fn forget(&mut self) {
+    self.snapshot = None;
+  }
+}
+
+fn write(path: &Path, sram: &[u8]) -> Result<(), String> {
+  storage::write(path, sram).map_err(|e| format!("Couldn't write battery save: {e}"))
+}
+
+impl Battery {
+  /// Starts tracking a new game, whose cartridge RAM is already on disk as `sram`.
+  pub fn reset(&mut self, sram: Option<&[u8]>) {
+    self.wait_writer();
+    self.request = None;
+    self.saved = sram.map(fnv1a);
+    self.last_check = Instant::now();
+  }
+
+  // a failed write is tried again on the next check
+  fn wait_writer(&mut self) {
+    let Some(writer) = self.writer.take() else { return };
+    let result = writer.join().unwrap_or_else(|_| Err("the battery save writer panicked".into()));
+    if let Err(e) = result {
+      error!("{e}");
+      self.saved = None;
+    }
+  }
+
+  /// Called every frame, never blocks: collects the requested RAM and hands it to a writer when it changed.
+  pub fn poll(&mut self, emu: &EmuThread, path: PathBuf) {
+    if self.writer.as_ref().is_some_and(JoinHandle::is_finished) {
+      self.wait_writer();
+    }
+
+    if let Some(request) = &self.request {
+      match request.try_recv() {
+        Ok(sram) => {
+          self.request = None;
+          let Some(sram) = sram else { return };
+          let hash = fnv1a(&sram);
+          if self.saved != Some(hash) && self.writer.is_none() {
+            self.saved = Some(hash);
+            self.writer = Some(thread::spawn(move || write(&path, &sram)));
+          }
+        }
+        Err(TryRecvError::Empty) => {}
+        Err(TryRecvError::Disconnected) => self.request = None,
+      }
+      return;
+    }
+
+    if self.writer.is_none() && self.last_check.elapsed() >= FLUSH_INTERVAL {
+      self.last_check = Instant::now();
+      self.request = Some(emu.request_dirty_sram());
+    }
+  }
+
+  /// Writes the cartridge RAM right away if it changed, after any write still in flight.
+  pub fn flush(&mut self, sram: Option<Vec<u8>>, path: &Path) -> Result<(), String> {
+    self.wait_writer();
+    self.request = None;
+    let Some(sram) = sram else { return Ok(()) };
+
+    let hash = fnv1a(&sram);
+    if self.saved == Some(hash) { return Ok(()); }
+    write(path, &sram)?;
+    self.saved = Some(hash);
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+
+  #[test]
+  fn takes_the_ram_only_when_it_changed() {
+    let mut tracker = SramTracker::default();
+    let mut sram = vec![0; 0x2000];
+    assert_eq!(tracker.take_changed(Some(&sram)).as_deref(), Some(&sram[..]));
+    assert_eq!(tracker.take_changed(Some(&sram)), None);
+
+    sram[0x100] = 0x42;
+    assert_eq!(tracker.take_changed(Some(&sram)).as_deref(), Some(&sram[..]));
+    assert_eq!(tracker.take_changed(Some(&sram)), None);
+
+    // a state load or a new game may have replaced it
+    tracker.forget();
+    assert!(tracker.take_changed(Some(&sram)).is_some());
+    assert_eq!(tracker.take_changed(None), None);
+  }
+
+  #[test]
+  fn flushes_only_what_differs_from_the_file() {
+    let path = std::env::temp_dir().join(format!("cmbemu-test-{}-battery.srm", std::process::id()));
+    let mut battery = Battery::default();
+
+    // what was loaded from disk isn't written back
+    battery.reset(Some(&[1, 2, 3]));
+    battery.flush(Some(vec![1, 2, 3]), &path).unwrap();
+    assert!(!path.exists());
+
+    battery.flush(Some(vec![1, 2, 4]), &path).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), [1, 2, 4]);
+
+    // unchanged since the last write, the file is left alone
+    fs::remove_file(&path).unwrap();
+    battery.flush(Some(vec![1, 2, 4]), &path).unwrap();
+    assert!(!path.exists());
+    battery.flush(None, &path).unwrap();
+    assert!(!path.exists());
+  }
+}
@@ -0,0 +1,91 @@
+use std::{fs, path::{Path, PathBuf}, sync::mpsc::{self, Receiver}, thread};
+
+use crate::{menu::ListMenu, rom::openable_extensions};
+
+// how many folders deep the scan goes below the ROM directory
+const MAX_DEPTH: usize = 4;
+
+fn is_openable(path: &Path) -> bool {
+  path.extension()
+    .and_then(|ext| ext.to_str())
+    .is_some_and(|ext| openable_extensions().contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+fn scan(dir: &Path, depth: usize, found: &mut Vec<PathBuf>) {
+  let Ok(entries) = fs::read_dir(dir) else { return };
+  for entry in entries.flatten() {
+    let path = entry.path();
+    // hidden folders are mostly caches and settings
+    if entry.file_name().to_string_lossy().starts_with('.') { continue; }
+
+    match entry.file_type() {
+      Ok(kind) if kind.is_dir() && depth < MAX_DEPTH => scan(&path, depth + 1, found),
+      Ok(kind) if kind.is_file() && is_openable(&path) => found.push(path),
+      _ => {}
+    }
+  }
+}
+
+/// On-screen list of the recent ROMs, then of the ROMs found under the ROM directory.
+/// Shown while no game is loaded. The directory is scanned in the background.
+pub struct RomBrowser {
+  pub menu: ListMenu,
+  dir: Option<PathBuf>,
+  recent: Vec<PathBuf>,
+  found: Vec<PathBuf>,
+  scan: Option<Receiver<Vec<PathBuf>>>,
+}
+
+impl RomBrowser {
+  pub fn new(dir: Option<PathBuf>, recent: Vec<PathBuf>) -> Self {
+    let recent = recent.into_iter().filter(|path| path.exists()).collect();
+    let mut browser = Self { menu: ListMenu::new("", Vec::new()), dir, recent, found: Vec::new(), scan: None };
+    browser.rescan();
+    browser
+  }
+
+  pub fn rescan(&mut self) {
+    if let Some(dir) = self.dir.clone() {
+      let (tx, rx) = mpsc::channel();
+      thread::spawn(move || {
+        let mut found = Vec::new();
+        scan(&dir, 0, &mut found);
+        found.sort();
+        let _ = tx.send(found);
+      });
+      self.scan = Some(rx);
+    }
+    self.update_menu();
+  }
+
+  /// Picks up the result of a finished scan.
+  pub fn poll(&mut self) {
+    let Some(found) = self.scan.as_ref().and_then(|scan| scan.try_recv().ok()) else { return };
+    self.found = found;
+    self.scan = None;
+    self.update_menu();
+  }
+
+  fn update_menu(&mut self) {
+    self.menu.title = match (&self.dir, &self.scan) {
+      (None, _) => "Open a ROM, or set rom_dir in the config".to_string(),
+      (Some(dir), Some(_)) => format!("Looking for ROMs in {}...", dir.display()),
+      (Some(dir), None) => format!("{} ROMs in {} (F5 rescans)", self.found.len(), dir.display()),
+    };
+
+    let name = |path: &PathBuf| path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().into_owned();
+    let relative = |path: &PathBuf| match self.dir.as_ref().and_then(|dir| path.strip_prefix(dir).ok()) {
+      Some(relative) => relative.display().to_string(),
+      None => name(path),
+    };
+    self.menu.items = self.recent.iter().map(|path| format!("* {}", name(path)))
+      .chain(self.found.iter().map(relative))
+      .collect();
+    self.menu.selected = self.menu.selected.min(self.menu.items.len().saturating_sub(1));
+  }
+
+  /// The ROM at the `i`th line of the menu.
+  pub fn path(&self, i: usize) -> Option<&PathBuf> {
+    self.recent.iter().chain(&self.found).nth(i)
+  }
+}
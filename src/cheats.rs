@@ -0,0 +1,99 @@
+use std::{fs, path::Path};
+
+use serde::Deserialize;
+use log::warn;
+
+use crate::{emu::Emulator, savestate::CoreId};
+
+/// A value written to memory after every frame.
+#[derive(Clone, Copy)]
+pub struct RamPatch {
+  pub addr: u16,
+  pub value: u8,
+}
+
+#[derive(Deserialize)]
+struct CheatEntry {
+  code: String,
+  #[serde(default)]
+  description: String,
+  #[serde(default = "enabled_default")]
+  enabled: bool,
+}
+fn enabled_default() -> bool { true }
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct CheatFile {
+  cheat: Vec<CheatEntry>,
+}
+
+// "AAAA:VV", for any core
+fn parse_raw(code: &str) -> Option<RamPatch> {
+  let (addr, value) = code.split_once(':')?;
+  Some(RamPatch { addr: u16::from_str_radix(addr, 16).ok()?, value: u8::from_str_radix(value, 16).ok()? })
+}
+
+// "01VVLLHH": type 01 writes VV to the little endian address HHLL
+fn parse_gameshark(code: &str) -> Option<RamPatch> {
+  if code.len() != 8 || !code.is_ascii() { return None; }
+  let byte = |i: usize| u8::from_str_radix(&code[i..i + 2], 16).ok();
+  if byte(0)? != 0x01 { return None; }
+  Some(RamPatch { addr: u16::from_le_bytes([byte(4)?, byte(6)?]), value: byte(2)? })
+}
+
+fn parse(code: &str, core: CoreId) -> Option<RamPatch> {
+  let code = code.trim().replace('-', "");
+  parse_raw(&code).or_else(|| match core {
+    CoreId::Gameboy => parse_gameshark(&code),
+    // Game Genie codes patch the cartridge ROM, which no core takes
+    CoreId::Nes => None,
+  })
+}
+
+/// The enabled cheats of the loaded game, read from its cheats.toml.
+#[derive(Default)]
+pub struct Cheats {
+  pub ram: Vec<RamPatch>,
+}
+
+impl Cheats {
+  /// Parses the cheat file at `path`. Codes that don't parse are reported and skipped.
+  pub fn load(path: &Path, core: CoreId) -> Self {
+    let mut cheats = Self::default();
+    let Ok(content) = fs::read_to_string(path) else { return cheats };
+
+    let file: CheatFile = match toml::from_str(&content) {
+      Ok(file) => file,
+      Err(e) => {
+        warn!("Invalid cheat file {}, ignoring it: {e}", path.display());
+        return cheats;
+      }
+    };
+
+    for entry in file.cheat.iter().filter(|entry| entry.enabled) {
+      match parse(&entry.code, core) {
+        Some(patch) => cheats.ram.push(patch),
+        None => warn!("Invalid cheat code '{}' ({}), skipping it", entry.code, entry.description),
+      }
+    }
+    cheats
+  }
+
+  pub fn len(&self) -> usize {
+    self.ram.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Called after every emulated frame. Only changed bytes are written, codes outside the core's RAM do nothing.
+  pub fn apply_ram(&self, emu: &mut Emulator) {
+    for patch in &self.ram {
+      if emu.peek(patch.addr as u32) != Some(patch.value) {
+        emu.poke(patch.addr as u32, patch.value);
+      }
+    }
+  }
+}
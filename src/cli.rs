@@ -0,0 +1,215 @@
+use std::path::PathBuf;
+
+use log::LevelFilter;
+
+use crate::renderer::RendererKind;
+
+pub const HELP: &str = "\
+Usage: cmbemu [options] [rom]
+
+Options:
+  --state <path>             load a save state right after the ROM
+  --scale <n>                window size, as a multiple of the game's
+  --renderer <name>          pick the renderer backend
+  --software-renderer        skip GPU acceleration
+  --audio-device <name>      output device, --list-audio-devices lists them
+  --watch                    reload the game whenever its ROM file changes
+  --host <port>              host a netplay session
+  --connect <addr>           join a netplay session
+  --input-delay <frames>     netplay input delay
+  --verify <rom> <frames> [hash]  headless run printing or checking the frame hash
+  --log-level <level>        off, error, warn, info, debug or trace
+  --log-file                 log to a file in the data directory
+
+Default hotkeys (rebound in the config file):
+  WASD, K, L, I, O           d-pad, A, B, Select, Start
+  J, ;                       turbo A, turbo B
+  Space                      pause
+  Tab (hold)                 fast-forward
+  Backspace (hold)           rewind
+  \\                          step a frame
+  9, Shift+0 (hold)          save, load (the slot picker while held)
+  [, ]                       previous, next slot
+  Shift+1-9, Ctrl+0-9        select a slot, save to a slot
+  Shift+R, Ctrl+Shift+R      reset, hard reset
+  -, =                       volume down, up
+  M                          mute
+  Alt+-, Alt+=               audio latency down, up
+  F1                         input display
+  F2                         reload the ROM
+  F3                         stats
+  P, Alt+B                   next palette, next background
+  F4, F5                     next filter, NTSC
+  F6                         rotate
+  F7                         cheats
+  F9, F10                    VRAM viewer, audio scope
+  F11, Alt+Enter             fullscreen
+  Alt+1-4                    window scale
+  Alt+O                      hide the overscan
+  F12                        screenshot
+  Ctrl+O, Ctrl+B, Ctrl+L     open a ROM, rebind, next layout
+";
+
+/// Headless run for catching emulation regressions: `--verify <rom> <frames> [expected hash]`.
+pub struct Verify {
+  pub rom: PathBuf,
+  pub frames: u32,
+  // Err with the argument as given when it isn't a hash, so a typo fails the run instead of skipping the check
+  pub expected: Result<Option<u64>, String>,
+  // state the run starts from, instead of power on
+  pub state: Option<PathBuf>,
+}
+
+/// Command line options.
+#[derive(Default)]
+pub struct Args {
+  // game loaded at launch
+  pub rom: Option<PathBuf>,
+  // state loaded right after it, the launch fails if it doesn't fit the game
+  pub state: Option<PathBuf>,
+  pub log_level: Option<LevelFilter>,
+  // log to a file in the data directory instead of stderr
+  pub log_file: bool,
+  // window size for this run, instead of the configured one
+  pub scale: Option<f32>,
+  pub renderer: RendererKind,
+  // skip straight to the fallback for when there's no GPU acceleration
+  pub software_renderer: bool,
+  // by name, as --list-audio-devices prints them
+  pub audio_device: Option<String>,
+  pub list_audio_devices: bool,
+  // reload the game whenever its ROM file changes
+  pub watch: bool,
+  pub verify: Option<Verify>,
+  // netplay: port to host on, or address to join
+  pub host: Option<u16>,
+  pub connect: Option<String>,
+  pub input_delay: Option<u32>,
+  // print the usage and quit
+  help: bool,
+}
+
+impl Args {
+  pub fn parse() -> Self {
+    let parsed = Self::parse_from(std::env::args().skip(1));
+    if parsed.help {
+      print!("{HELP}");
+      std::process::exit(0);
+    }
+    parsed
+  }
+
+  // the logger isn't up yet, so problems go straight to stderr
+  fn parse_from(args: impl IntoIterator<Item = String>) -> Self {
+    let mut parsed = Self::default();
+    let mut args = args.into_iter().peekable();
+
+    while let Some(arg) = args.next() {
+      match arg.as_str() {
+        "--log-level" => match args.next().map(|level| level.parse()) {
+          Some(Ok(level)) => parsed.log_level = Some(level),
+          _ => eprintln!("--log-level expects one of off, error, warn, info, debug, trace\n"),
+        }
+        "--help" | "-h" => parsed.help = true,
+        "--log-file" => parsed.log_file = true,
+        "--watch" => parsed.watch = true,
+        "--software-renderer" => parsed.software_renderer = true,
+        "--list-audio-devices" => parsed.list_audio_devices = true,
+        "--audio-device" => match args.next() {
+          Some(name) => parsed.audio_device = Some(name),
+          None => eprintln!("--audio-device expects a device name, --list-audio-devices lists them\n"),
+        }
+        "--state" => match args.next() {
+          Some(path) => parsed.state = Some(path.into()),
+          None => eprintln!("--state expects the path of a save state\n"),
+        }
+        "--scale" => match args.next().and_then(|scale| scale.parse().ok()) {
+          Some(scale) => parsed.scale = Some(scale),
+          None => eprintln!("--scale expects a number, like 3\n"),
+        }
+        "--renderer" => match args.next().as_deref().and_then(RendererKind::from_name) {
+          Some(renderer) => parsed.renderer = renderer,
+          None => eprintln!("--renderer expects one of {}\n", RendererKind::NAMES.join(", ")),
+        }
+        "--host" => match args.next().and_then(|port| port.parse().ok()) {
+          Some(port) => parsed.host = Some(port),
+          None => eprintln!("--host expects a port number\n"),
+        }
+        "--connect" => match args.next() {
+          Some(addr) => parsed.connect = Some(addr),
+          None => eprintln!("--connect expects an address, like 192.168.1.2:7777\n"),
+        }
+        "--input-delay" => match args.next().and_then(|frames| frames.parse().ok()) {
+          Some(frames) => parsed.input_delay = Some(frames),
+          None => eprintln!("--input-delay expects a number of frames\n"),
+        }
+        "--verify" => {
+          let rom = args.next();
+          let frames = args.next().and_then(|frames| frames.parse().ok());
+          // the expected hash is optional, without it the hash is just printed
+          let expected = match args.next_if(|arg| !arg.starts_with("--")) {
+            Some(hash) => u64::from_str_radix(hash.trim_start_matches("0x"), 16).map(Some).map_err(|_| hash),
+            None => Ok(None),
+          };
+
+          match (rom, frames) {
+            (Some(rom), Some(frames)) => parsed.verify = Some(Verify { rom: rom.into(), frames, expected, state: None }),
+            _ => eprintln!("--verify expects a ROM path, a frame count and optionally the expected hash\n"),
+          }
+        }
+        _ if !arg.starts_with("--") && parsed.rom.is_none() => parsed.rom = Some(arg.into()),
+        _ => eprintln!("Unknown argument '{arg}', ignoring it\n"),
+      }
+    }
+
+    if let Some(verify) = &mut parsed.verify {
+      verify.state = parsed.state.clone();
+    }
+    parsed
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(args: &[&str]) -> Args {
+    Args::parse_from(args.iter().map(|arg| arg.to_string()))
+  }
+
+  #[test]
+  fn help_is_asked_for_either_way() {
+    assert!(parse(&["--help"]).help);
+    assert!(parse(&["game.nes", "-h"]).help);
+    assert!(!parse(&["game.nes"]).help);
+  }
+
+  #[test]
+  fn parses_the_options() {
+    let args = parse(&["game.nes", "--scale", "2", "--watch", "--state", "game.state", "--input-delay", "3"]);
+    assert_eq!(args.rom, Some("game.nes".into()));
+    assert_eq!(args.scale, Some(2.0));
+    assert!(args.watch);
+    assert_eq!(args.state, Some("game.state".into()));
+    assert_eq!(args.input_delay, Some(3));
+
+    // bad values are skipped, the rest is still read
+    let args = parse(&["--scale", "big", "--host", "7777"]);
+    assert_eq!(args.scale, None);
+    assert_eq!(args.host, Some(7777));
+  }
+
+  #[test]
+  fn verify_takes_an_optional_hash_and_the_state() {
+    let verify = parse(&["--verify", "game.nes", "600", "0xabc", "--state", "game.state"]).verify.unwrap();
+    assert_eq!((verify.rom, verify.frames, verify.expected), ("game.nes".into(), 600, Ok(Some(0xabc))));
+    assert_eq!(verify.state, Some("game.state".into()));
+
+    let verify = parse(&["--verify", "game.nes", "600", "--watch"]).verify.unwrap();
+    assert_eq!(verify.expected, Ok(None));
+
+    // a typo is kept to fail the run, not taken as no hash
+    let verify = parse(&["--verify", "game.nes", "600", "0xabg"]).verify.unwrap();
+    assert_eq!(verify.expected, Err("0xabg".to_string()));
+  }
+}
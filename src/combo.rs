@@ -0,0 +1,110 @@
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+use crate::input::{GameInput, InputKind};
+
+// how long the game waits for the rest of the combo after one of its buttons is pressed
+const COMBO_WINDOW: Duration = Duration::from_millis(150);
+
+pub enum ComboEvent {
+  // a press or release held back by the combo, for the game to hear now
+  Input(GameInput, InputKind),
+  OpenMenu,
+}
+
+// the combo buttons a controller holds, with whether the game heard their press
+struct PadCombo {
+  held: HashMap<GameInput, bool>,
+  first_press: Instant,
+  // all of them held since
+  complete_since: Option<Instant>,
+  // the menu was opened, the releases are kept from the game too
+  fired: bool,
+}
+
+/// Opens the pause menu when a controller holds the combo buttons long enough, for pads whose Guide the OS takes.
+/// Their presses are held back for a moment, so the game doesn't hear the ones the combo uses.
+pub struct MenuCombo {
+  buttons: Vec<GameInput>,
+  hold: Duration,
+  pads: HashMap<u32, PadCombo>,
+}
+
+impl MenuCombo {
+  /// No buttons turns the combo off.
+  pub fn new(buttons: Vec<GameInput>, hold: Duration) -> Self {
+    Self { buttons, hold, pads: HashMap::new() }
+  }
+
+  /// Takes a game input of a controller. Returns None when it isn't part of the combo, the game hears it as usual.
+  /// Otherwise returns what the game should hear instead, which can be nothing for now.
+  pub fn input(&mut self, which: u32, input: GameInput, kind: &InputKind, now: Instant) -> Option<Vec<ComboEvent>> {
+    if !self.buttons.contains(&input) { return None; }
+
+    match kind {
+      InputKind::Press => {
+        let pad = self.pads.entry(which).or_insert(PadCombo { held: HashMap::new(), first_press: now, complete_since: None, fired: false });
+        pad.held.insert(input, false);
+        if self.buttons.iter().all(|button| pad.held.contains_key(button)) {
+          pad.complete_since = Some(now);
+        }
+        Some(Vec::new())
+      }
+      InputKind::Release => {
+        let pad = self.pads.get_mut(&which)?;
+        let heard = pad.held.remove(&input)?;
+        pad.complete_since = None;
+
+        let mut events = Vec::new();
+        if !pad.fired {
+          // the combo is broken, what it held back goes through late
+          for (held, heard) in pad.held.iter_mut().filter(|(_, heard)| !**heard) {
+            events.push(ComboEvent::Input(*held, InputKind::Press));
+            *heard = true;
+          }
+          if !heard { events.push(ComboEvent::Input(input, InputKind::Press)); }
+          events.push(ComboEvent::Input(input, InputKind::Release));
+        }
+        if pad.held.is_empty() { self.pads.remove(&which); }
+        Some(events)
+      }
+    }
+  }
+
+  /// Lets through the presses nothing followed within the window, and opens the menu for a combo held long enough.
+  pub fn poll(&mut self, now: Instant) -> Vec<ComboEvent> {
+    let mut events = Vec::new();
+    for pad in self.pads.values_mut().filter(|pad| !pad.fired) {
+      match pad.complete_since {
+        Some(since) if now - since >= self.hold => {
+          pad.fired = true;
+          // whatever went through before the combo was complete is let go of, its own release won't be heard
+          for (held, heard) in pad.held.iter_mut().filter(|(_, heard)| **heard) {
+            events.push(ComboEvent::Input(*held, InputKind::Release));
+            *heard = false;
+          }
+          events.push(ComboEvent::OpenMenu);
+        }
+        None if now - pad.first_press >= COMBO_WINDOW => {
+          for (held, heard) in pad.held.iter_mut().filter(|(_, heard)| !**heard) {
+            events.push(ComboEvent::Input(*held, InputKind::Press));
+            *heard = true;
+          }
+        }
+        _ => {}
+      }
+    }
+    events
+  }
+
+  pub fn buttons(&self) -> &[GameInput] { &self.buttons }
+  pub fn hold(&self) -> Duration { self.hold }
+
+  pub fn controller_removed(&mut self, which: u32) {
+    self.pads.remove(&which);
+  }
+
+  /// Forgets everything held back, for when the game is told everything was let go of.
+  pub fn clear(&mut self) {
+    self.pads.clear();
+  }
+}
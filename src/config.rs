@@ -0,0 +1,325 @@
+use std::{collections::{BTreeMap, HashMap}, error::Error, fs, path::{Path, PathBuf}};
+
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use log::warn;
+
+use crate::{filter::FilterKind, input::{GameInput, InputEvent, Keymaps}, palette::{BACKGROUNDS, GB_PALETTES}, storage};
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+  pub last_dir: Option<PathBuf>,
+  // listed by the ROM browser when no game is loaded, the last dir when unset
+  pub rom_dir: Option<PathBuf>,
+  // archive path -> entry picked the last time it held several ROMs
+  pub archive_choices: HashMap<String, String>,
+  // most recent first
+  pub recent_roms: Vec<RecentRom>,
+  pub video: VideoConfig,
+  pub audio: AudioConfig,
+  pub turbo: TurboConfig,
+  pub emulation: EmulationConfig,
+  pub netplay: NetplayConfig,
+  pub input: InputConfig,
+}
+impl Default for Config {
+  fn default() -> Self {
+    Self {
+      last_dir: None,
+      rom_dir: None,
+      archive_choices: HashMap::new(),
+      recent_roms: Vec::new(),
+      video: VideoConfig::default(),
+      audio: AudioConfig::default(),
+      turbo: TurboConfig::default(),
+      emulation: EmulationConfig::default(),
+      netplay: NetplayConfig::default(),
+      input: Keymaps::default().to_config(),
+    }
+  }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RecentRom {
+  pub path: PathBuf,
+  pub crc32: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct VideoConfig {
+  pub scale: f32,
+  pub pacing: PacingMode,
+  // name of one of the GB_PALETTES
+  pub gb_palette: String,
+  pub filter: FilterKind,
+  // composite video look, for the NES core
+  pub ntsc: bool,
+  // linear filtering when stretching the image to the window, instead of sharp pixels
+  pub smooth: bool,
+  // a controller diagram of the buttons the core is given
+  pub input_display: bool,
+  // crops the edges most TVs cut, see EmuInterface::visible_region
+  pub hide_overscan: bool,
+  pub fullscreen: FullscreenMode,
+  // around the image, RGB
+  pub background: [u8; 3],
+  // let the screensaver start while a game is playing too
+  pub screensaver: bool,
+  // degrees clockwise, by ROM CRC32
+  pub rotation: HashMap<String, u16>,
+}
+impl Default for VideoConfig {
+  fn default() -> Self {
+    Self { scale: 3.0, pacing: PacingMode::Auto, gb_palette: GB_PALETTES[0].0.to_string(), filter: FilterKind::None, ntsc: false, smooth: false, input_display: false, hide_overscan: false, fullscreen: FullscreenMode::Desktop, background: BACKGROUNDS[0].1, screensaver: false, rotation: HashMap::new() }
+  }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FullscreenMode {
+  // a borderless window covering the desktop
+  Desktop,
+  // switches the display to the mode whose refresh suits the game best, for lower latency on some setups
+  Exclusive,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PacingMode {
+  // vsync when the display refresh matches the core's fps or a multiple of it, timer otherwise
+  Auto,
+  Timer,
+  // vsync unless the display refresh is known not to match
+  Vsync,
+  // the audio device's clock decides how much is emulated, and frames are shown as they complete.
+  // no drift between sound and emulation, but frames come unevenly. Timer pacing while there's no sound
+  Audio,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+  pub muted: bool,
+  pub volume: f32,
+  // how much audio is kept queued, more survives hiccups without crackling
+  pub latency_ms: u32,
+}
+impl Default for AudioConfig {
+  fn default() -> Self {
+    Self { muted: false, volume: 1.0, latency_ms: 50 }
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct TurboConfig {
+  // press and release cycles per second
+  pub rate: u32,
+}
+impl Default for TurboConfig {
+  fn default() -> Self {
+    Self { rate: 15 }
+  }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmulationConfig {
+  // frames shown ahead of the emulated one, up to 2
+  pub run_ahead: u8,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetplayConfig {
+  // frames local input is delayed by, to hide the network latency
+  pub input_delay: u32,
+}
+impl Default for NetplayConfig {
+  fn default() -> Self {
+    Self { input_delay: 2 }
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputConfig {
+  // the layout used by games that don't pin one, the default one when empty
+  pub layout: String,
+  // ROM CRC32 -> layout that game always starts with
+  pub game_layouts: HashMap<String, String>,
+  // the default layout
+  #[serde(flatten)]
+  pub bindings: LayoutConfig,
+  // named layouts the hotkey cycles through, anything they leave out is taken from the default one
+  pub layouts: BTreeMap<String, LayoutConfig>,
+  // how far a bound analog trigger is pulled to press, and let go of to release, from 0 to 1
+  pub trigger_press: f32,
+  pub trigger_release: f32,
+  // controller buttons that open the pause menu when held together for menu_combo_ms, none turns it off
+  pub menu_combo: Vec<GameInput>,
+  pub menu_combo_ms: u64,
+  // whether the second keyboard bindings play as player 2
+  pub keyboard_player2: KeyboardPlayer2,
+  // Left+Right or Up+Down held together glitch some games, the direction pressed last wins instead
+  pub block_opposing: bool,
+}
+impl Default for InputConfig {
+  fn default() -> Self {
+    Self {
+      layout: String::new(),
+      game_layouts: HashMap::new(),
+      bindings: LayoutConfig::default(),
+      layouts: BTreeMap::new(),
+      trigger_press: 0.6,
+      trigger_release: 0.4,
+      menu_combo: vec![GameInput::Start, GameInput::Select],
+      menu_combo_ms: 1000,
+      keyboard_player2: KeyboardPlayer2::Auto,
+      block_opposing: true,
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyboardPlayer2 {
+  Off,
+  On,
+  // while a controller is connected, as it plays player 1 then
+  Auto,
+}
+
+// Bindings are stored by SDL key/button name, so the file stays human editable
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LayoutConfig {
+  // key name with the modifiers held with it, e.g. "Shift+R"
+  #[serde(deserialize_with = "lenient_bindings")]
+  pub keyboard: HashMap<String, InputEvent>,
+  // from before the modifiers were part of the key names, only read
+  #[serde(skip_serializing_if = "HashMap::is_empty")]
+  #[serde(deserialize_with = "lenient_bindings")]
+  pub keyboard_ctrl: HashMap<String, InputEvent>,
+  #[serde(skip_serializing_if = "HashMap::is_empty")]
+  #[serde(deserialize_with = "lenient_bindings")]
+  pub keyboard_alt: HashMap<String, InputEvent>,
+  #[serde(skip_serializing_if = "HashMap::is_empty")]
+  #[serde(deserialize_with = "lenient_bindings")]
+  pub keyboard_shift: HashMap<String, InputEvent>,
+  // key name -> game input of player 2, modifiers aren't part of these
+  #[serde(deserialize_with = "lenient_bindings")]
+  pub keyboard_player2: HashMap<String, GameInput>,
+  // controller bindings per mapping profile
+  pub controller: HashMap<String, PadConfig>,
+  // bindings of the joysticks SDL has no controller mapping for
+  pub joystick: JoyConfig,
+  // core name -> game inputs of that core only, in place of the game inputs above
+  pub cores: HashMap<String, CoreBindingsConfig>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CoreBindingsConfig {
+  #[serde(deserialize_with = "lenient_bindings")]
+  pub keyboard: HashMap<String, GameInput>,
+  #[serde(deserialize_with = "lenient_bindings")]
+  pub buttons: HashMap<String, GameInput>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PadConfig {
+  #[serde(deserialize_with = "lenient_bindings")]
+  pub buttons: HashMap<String, InputEvent>,
+  // axis name with its direction, e.g. "leftx-"
+  #[serde(deserialize_with = "lenient_bindings")]
+  pub axes: HashMap<String, GameInput>,
+  // axis name -> how far it must be pushed to count, out of 32767
+  pub dead_zones: HashMap<String, i16>,
+  // trigger axis name, e.g. "righttrigger", like a button
+  #[serde(deserialize_with = "lenient_bindings")]
+  pub triggers: HashMap<String, InputEvent>,
+}
+
+// Joysticks only have numbered buttons and axes
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JoyConfig {
+  // button index, e.g. "0"
+  #[serde(deserialize_with = "lenient_bindings")]
+  pub buttons: HashMap<String, InputEvent>,
+  // axis index with its direction, e.g. "1-"
+  #[serde(deserialize_with = "lenient_bindings")]
+  pub axes: HashMap<String, GameInput>,
+}
+
+// A binding to an action this version doesn't know, from an older or newer config, is skipped
+// instead of the whole file being thrown away
+fn lenient_bindings<'de, D: Deserializer<'de>, V: DeserializeOwned>(deserializer: D) -> Result<HashMap<String, V>, D::Error> {
+  let bindings = HashMap::<String, toml::Value>::deserialize(deserializer)?;
+  Ok(bindings.into_iter()
+    .filter_map(|(name, value)| match value.clone().try_into() {
+      Ok(input) => Some((name, input)),
+      Err(_) => { warn!("Unknown action {value} bound to '{name}' in config, skipping"); None }
+    })
+    .collect())
+}
+
+impl Config {
+  const MAX_RECENT_ROMS: usize = 10;
+
+  pub fn add_recent(&mut self, path: &Path, crc32: u32) {
+    self.recent_roms.retain(|rom| rom.path != path);
+    self.recent_roms.insert(0, RecentRom { path: path.into(), crc32 });
+    self.recent_roms.truncate(Self::MAX_RECENT_ROMS);
+  }
+
+  pub fn path() -> Option<PathBuf> {
+    storage::config_dir().map(|dir| dir.join("config.toml"))
+  }
+
+  /// Loads the config file, falling back to the defaults if it is missing or invalid.
+  pub fn load() -> Self {
+    let Some(path) = Self::path() else { return Self::default() };
+
+    match fs::read_to_string(&path) {
+      Ok(content) => toml::from_str(&content)
+        .inspect_err(|e| warn!("Invalid config file {}, using defaults: {e}", path.display()))
+        .unwrap_or_default(),
+      Err(_) => Self::default(),
+    }
+  }
+
+  pub fn save(&self) -> Result<(), Box<dyn Error>> {
+    let path = Self::path().ok_or("No config directory available")?;
+    let content = toml::to_string_pretty(self)?;
+    storage::write(&path, content.as_bytes())?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn unknown_actions_are_skipped() {
+    let layout: LayoutConfig = toml::from_str(r#"
+      [keyboard]
+      K = "TurboA"
+      Tab = "FastForward"
+      "Alt+1" = { Scale = 1 }
+      X = "Teleport"
+      [keyboard_player2]
+      Up = "Up"
+      Q = "Jump"
+    "#).unwrap();
+    assert_eq!(layout.keyboard.len(), 3);
+    assert!(matches!(layout.keyboard["Tab"], InputEvent::FastForward));
+    assert!(matches!(layout.keyboard["Alt+1"], InputEvent::Scale(1)));
+    assert!(!layout.keyboard.contains_key("X"));
+    assert_eq!(layout.keyboard_player2, HashMap::from([("Up".to_string(), GameInput::Up)]));
+  }
+}
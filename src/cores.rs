@@ -0,0 +1,58 @@
+use std::path::Path;
+
+use nen_emulator::{cart::is_nes_rom, Nes};
+use tomboy_emulator::{cart::is_gb_rom, gb::Gameboy};
+
+use crate::emu::Emulator;
+
+/// How the frontend recognizes a core's games and boots them.
+pub struct CoreDescriptor {
+  pub name: &'static str,
+  // of its ROM files, lowercase. Also how formats without magic bytes are recognized
+  pub extensions: &'static [&'static str],
+  pub probe: fn(&[u8]) -> bool,
+  pub boot: fn(&[u8]) -> Result<Emulator, String>,
+}
+
+/// Every core, in the order they're tried. Earlier extensions are preferred when an archive holds several ROMs.
+pub const CORES: &[CoreDescriptor] = &[
+  CoreDescriptor {
+    name: "NES",
+    extensions: &["nes"],
+    probe: is_nes_rom,
+    boot: |bytes| Nes::boot_from_bytes(bytes).map(|emu| Box::new(emu) as Emulator),
+  },
+  CoreDescriptor {
+    name: "Game Boy",
+    extensions: &["gb", "gbc"],
+    probe: is_gb_rom,
+    boot: |bytes| Gameboy::boot_from_bytes(bytes).map(|emu| Box::new(emu) as Emulator),
+  },
+];
+
+/// Whether any core recognizes the bytes as one of its ROMs.
+pub fn is_rom(bytes: &[u8]) -> bool {
+  CORES.iter().any(|core| (core.probe)(bytes))
+}
+
+/// Boots the core the ROM is for: the first one recognizing its bytes, or else the ones its extension is for.
+/// The error names each core tried and why it refused the file.
+pub fn boot(bytes: &[u8], path: &Path) -> Result<Emulator, String> {
+  let ext = path.extension().and_then(|ext| ext.to_str()).map(str::to_ascii_lowercase);
+  let by_extension = |core: &&CoreDescriptor| ext.as_deref().is_some_and(|ext| core.extensions.contains(&ext));
+
+  let recognized = |core: &&CoreDescriptor| (core.probe)(bytes);
+  let by_extension_only = |core: &&CoreDescriptor| !recognized(core) && by_extension(core);
+
+  let mut reasons = Vec::new();
+  for core in CORES.iter().filter(recognized).chain(CORES.iter().filter(by_extension_only)) {
+    match (core.boot)(bytes) {
+      Ok(emu) => return Ok(emu),
+      Err(msg) => reasons.push(format!("{}: {msg}", core.name)),
+    }
+  }
+  for core in CORES.iter().filter(|core| !recognized(core) && !by_extension(core)) {
+    reasons.push(format!("{}: not one of its ROMs", core.name));
+  }
+  Err(format!("No core could run it ({})", reasons.join(", ")))
+}
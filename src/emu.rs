@@ -1,44 +1,284 @@
-use std::{fs, io::{Read, Write}, path::{Path, PathBuf}};
+use std::{error::Error, fmt, io, path::Path};
+
+use flate2::read::GzDecoder;
+use serde::{de::DeserializeOwned, Serialize};
 
 use nen_emulator::{Nes, joypad::JoypadButton as NesButton};
 use tomboy_emulator::{gb::Gameboy, joypad::Flags as GbButton};
-use sdl2::audio::AudioSpecDesired;
+use sdl2::pixels::PixelFormatEnum;
+
+use crate::{hash, vram::{DebugVram, MapCell, TileMap, View, TILE_SIZE}, input::{GameInput, InputKind}, palette::{GbPalette, GB_PALETTES}, savestate::{self, CoreId, StateHeader, LEGACY_VERSION}};
+
+pub type Emulator = Box<dyn EmuInterface + Send>;
+
+/// The part of the frame to show: the visible region with the overscan hidden, all of it otherwise.
+pub fn shown_region(emu: &dyn EmuInterface, hide_overscan: bool) -> (usize, usize, usize, usize) {
+  let (width, height) = emu.resolution();
+  match hide_overscan {
+    true => emu.visible_region(),
+    false => (0, 0, width, height),
+  }
+}
+
+/// Layout of the pixels in a core's framebuffer, uploaded as is so no frame has to be converted.
+// no core draws in the others yet
+#[allow(dead_code)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum EmuPixelFormat {
+  #[default]
+  Rgba32,
+  Bgra32,
+  Rgb24,
+  // 5 bits per channel in a native endian u16, red in the high bits and the top one unused
+  Rgb555,
+}
+
+impl EmuPixelFormat {
+  pub fn bytes_per_pixel(self) -> usize {
+    match self {
+      EmuPixelFormat::Rgba32 | EmuPixelFormat::Bgra32 => 4,
+      EmuPixelFormat::Rgb24 => 3,
+      EmuPixelFormat::Rgb555 => 2,
+    }
+  }
+
+  pub fn sdl_format(self) -> PixelFormatEnum {
+    match self {
+      EmuPixelFormat::Rgba32 => PixelFormatEnum::RGBA32,
+      EmuPixelFormat::Bgra32 => PixelFormatEnum::BGRA32,
+      EmuPixelFormat::Rgb24 => PixelFormatEnum::RGB24,
+      EmuPixelFormat::Rgb555 => PixelFormatEnum::RGB555,
+    }
+  }
+
+  /// Color of the pixel `px` starts with.
+  pub fn rgb(self, px: &[u8]) -> [u8; 3] {
+    match self {
+      EmuPixelFormat::Rgba32 | EmuPixelFormat::Rgb24 => [px[0], px[1], px[2]],
+      EmuPixelFormat::Bgra32 => [px[2], px[1], px[0]],
+      EmuPixelFormat::Rgb555 => {
+        let px = u16::from_ne_bytes([px[0], px[1]]);
+        // the low bits are filled from the high ones, so white stays 0xff
+        [10, 5, 0].map(|shift| {
+          let c = (px >> shift) as u8 & 0x1f;
+          (c << 3) | (c >> 2)
+        })
+      }
+    }
+  }
+
+  /// Writes `rgb` to the pixel `px` starts with, opaque.
+  pub fn set_rgb(self, px: &mut [u8], [r, g, b]: [u8; 3]) {
+    match self {
+      EmuPixelFormat::Rgba32 => px[..4].copy_from_slice(&[r, g, b, 0xff]),
+      EmuPixelFormat::Bgra32 => px[..4].copy_from_slice(&[b, g, r, 0xff]),
+      EmuPixelFormat::Rgb24 => px[..3].copy_from_slice(&[r, g, b]),
+      EmuPixelFormat::Rgb555 => {
+        let value = ((r as u16 >> 3) << 10) | ((g as u16 >> 3) << 5) | (b as u16 >> 3);
+        px[..2].copy_from_slice(&value.to_ne_bytes());
+      }
+    }
+  }
+}
+
+/// The audio a core makes, as it makes it. The frontend resamples it to what the device runs at.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AudioSource {
+  pub rate: u32,
+  pub channels: u8,
+}
+
+/// What a core can do, so the frontend leaves out what it can't.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct EmuCaps {
+  pub audio: bool,
+  pub save_states: bool,
+  // of the loaded cartridge
+  pub battery_ram: bool,
+  pub second_player: bool,
+  // peek and poke reach the RAM
+  pub memory: bool,
+  // debug_vram gives the tiles and maps
+  pub vram: bool,
+  // set_channel_enabled mutes the channels audio_channels names
+  pub channel_mute: bool,
+}
+
+/// Why a state couldn't be saved or loaded.
+#[derive(Debug)]
+pub enum EmuError {
+  Io(io::Error),
+  // the state couldn't be encoded, or its data doesn't decode
+  Serde(String),
+  // the state is fine, but not for this core, game or build
+  Incompatible(String),
+}
+
+impl fmt::Display for EmuError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      EmuError::Io(e) => write!(f, "{e}"),
+      EmuError::Serde(msg) => write!(f, "state data is unreadable: {msg}"),
+      EmuError::Incompatible(msg) => write!(f, "{msg}"),
+    }
+  }
+}
+
+impl Error for EmuError {}
+
+impl From<io::Error> for EmuError {
+  fn from(e: io::Error) -> Self { EmuError::Io(e) }
+}
+
+// the address as the core's bus takes it, when it's in one of `ranges`
+fn ram_addr(ranges: &[(u32, u32)], addr: u32) -> Option<u16> {
+  ranges.iter().any(|(first, last)| (*first..=*last).contains(&addr)).then_some(addr as u16)
+}
+
+// states from before the versioned header were gzipped bincode
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
 
-use crate::input::{GameInput, InputKind};
+// a state is the whole emulator in bincode. The cores skip the ROM when serializing,
+// so a decoded one gets the cartridge of the running emulator back with load_from_emu
+fn encode_emu<T: Serialize>(emu: &T) -> Result<Vec<u8>, EmuError> {
+  bincode::serialize(emu).map_err(|e| EmuError::Serde(e.to_string()))
+}
+
+fn decode_emu<T: DeserializeOwned>(data: &[u8]) -> Result<T, EmuError> {
+  bincode::deserialize(data).map_err(|e| EmuError::Serde(e.to_string()))
+}
 
-pub type Emulator = Box<dyn EmuInterface>;
 pub trait EmuInterface {
   fn step_one_frame(&mut self);
+  // steps until `n` more samples, all channels counted, are in `out`, for audio-clocked pacing.
+  // framebuf keeps the last completed frame meanwhile. Cores that can't stop mid-frame run whole ones
+  fn step_samples(&mut self, n: usize, out: &mut Vec<f32>) {
+    let target = out.len() + n;
+    while out.len() < target {
+      let before = out.len();
+      self.step_one_frame();
+      self.samples(out);
+      // a core without audio would never get there
+      if out.len() == before { break; }
+    }
+  }
   fn framebuf(&mut self) -> (&[u8], usize);
-  fn samples(&mut self) -> Vec<f32>;
+  // digest of the visible frame, for the determinism checks. Cores that track their frame's content can do cheaper
+  fn frame_hash(&mut self) -> u64 {
+    let (resolution, bpp) = (self.resolution(), self.pixel_format().bytes_per_pixel());
+    let (framebuf, pitch) = self.framebuf();
+    hash::frame_hash(framebuf, pitch, resolution, bpp)
+  }
+  // of framebuf, both cores draw in RGBA32
+  fn pixel_format(&self) -> EmuPixelFormat { EmuPixelFormat::Rgba32 }
+  // appends the audio of the frames stepped since the last call, so the caller can reuse one buffer
+  fn samples(&mut self, out: &mut Vec<f32>);
+  // drops that audio instead, for when it would go unheard
+  fn discard_samples(&mut self) {
+    self.samples(&mut Vec::new());
+  }
   fn resolution(&self) -> (usize, usize);
+  // the part of the frame a TV shows, as (x, y, width, height). Games can leave garbage outside of it
+  fn visible_region(&self) -> (usize, usize, usize, usize) {
+    let (width, height) = self.resolution();
+    (0, 0, width, height)
+  }
   fn fps(&self) -> f32;
-  fn audio_spec(&self) -> (bool, AudioSpecDesired);
+  // what samples() gives, interleaving the channels. None without sound, a core can also give no samples at all
+  // for a while, the frontend then keeps the audio off
+  fn native_audio(&self) -> Option<AudioSource> { None }
   fn input_event(&mut self, button: &GameInput, kind: InputKind);
+  // player 0 is the one input_event controls, the others are only there on multiplayer cores
+  fn player_input_event(&mut self, player: usize, button: &GameInput, kind: InputKind) {
+    if player == 0 { self.input_event(button, kind); }
+  }
   fn reset(&mut self);
+  // power cycles from the ROM the game was booted from, keeping the cartridge RAM. Cores with a
+  // reset button of their own needn't, the frontend reboots them with a new emulator for that
+  fn hard_reset(&mut self, _rom: &[u8]) -> Result<(), String> {
+    self.reset();
+    Ok(())
+  }
+
+  fn core_id(&self) -> CoreId;
+  // only video, unless a core says otherwise
+  fn capabilities(&self) -> EmuCaps { EmuCaps::default() }
+  // as the cartridge names itself, None when it doesn't
+  fn game_title(&self) -> Option<String> { None }
+  // the sound channels that can be muted one by one, none when the core can't
+  fn audio_channels(&self) -> Vec<&'static str> { Vec::new() }
+  fn set_channel_enabled(&mut self, _idx: usize, _on: bool) {}
+
+  // the whole state as bytes, for the files and for features that restore it many times a second
+  fn save_state(&self) -> Result<Vec<u8>, EmuError> {
+    Err(EmuError::Incompatible("save states aren't supported by this core".into()))
+  }
+  fn load_state(&mut self, _data: &[u8]) -> Result<(), EmuError> {
+    Err(EmuError::Incompatible("save states aren't supported by this core".into()))
+  }
+  // states from before the versioned header, only the NES core had those
+  fn load_legacy_state(&mut self, _data: &[u8]) -> Result<(), EmuError> {
+    Err(EmuError::Incompatible("state was created by an older build".into()))
+  }
+
+  // the path is the exact state file, the frontend picks its name
+  fn save(&self, path: &Path, header: &StateHeader) -> Result<(), EmuError> {
+    let state = self.save_state()?;
+    savestate::write_file(path, header, |out| Ok(out.write_all(&state)?))
+  }
+  fn load(&mut self, path: &Path, header: &StateHeader) -> Result<(), EmuError> {
+    let state = savestate::read_file(path, header)?;
+    match state.version {
+      LEGACY_VERSION => self.load_legacy_state(&state.payload),
+      _ => self.load_state(&state.payload),
+    }
+  }
+
+  // only meaningful for monochrome cores
+  fn set_palette(&mut self, _palette: GbPalette) {}
+
+  // the memory peek and poke reach, as first and last CPU address. Registers are left out, so a read has no side effects
+  fn ram_ranges(&self) -> Vec<(u32, u32)> { Vec::new() }
+  // raw access to that memory, for cheats and RAM search. None or false outside of it
+  fn peek(&self, _addr: u32) -> Option<u8> { None }
+  fn poke(&mut self, _addr: u32, _val: u8) -> bool { false }
+  // replaces what cartridge ROM reads return, false when the core can't
 
-  fn save(&self, _path: &Path) {}
-  fn load(&mut self, _path: &Path) {}
+  // tiles and maps, for the VRAM viewer
+  fn debug_vram(&self) -> Option<DebugVram> { None }
+
+  // battery backed cartridge RAM, None when the cartridge has none
+  fn sram(&self) -> Option<&[u8]> { None }
+  fn load_sram(&mut self, _data: &[u8]) {}
 }
 
 impl EmuInterface for Nes {
   fn step_one_frame(&mut self) { self.step_until_vblank(); }
-
   fn framebuf(&mut self) -> (&[u8], usize) { (&self.get_screen().buffer, self.get_screen().pitch()) }
-  fn samples(&mut self) -> Vec<f32> { self.get_samples() }
+  fn samples(&mut self, out: &mut Vec<f32>) { out.append(&mut self.get_samples()); }
+  fn discard_samples(&mut self) { self.get_samples(); }
 
   fn resolution(&self) -> (usize, usize) { (32*8, 30*8) }
+  // most TVs cut the top and bottom 8 lines, where games scroll in new tiles
+  fn visible_region(&self) -> (usize, usize, usize, usize) { (0, 8, 32*8, 28*8) }
   fn fps(&self) -> f32 { self.get_fps() }
 
-  fn audio_spec(&self) -> (bool, AudioSpecDesired) {
-    let spec = AudioSpecDesired { freq: Some(44100), channels: Some(1), samples: None, };
-    (true, spec)
+  // the APU is resampled to this inside the core already
+  fn native_audio(&self) -> Option<AudioSource> {
+    Some(AudioSource { rate: 44100, channels: 1 })
   }
 
   fn input_event(&mut self, button: &GameInput, kind: InputKind) {
-    let method: fn(&mut Nes, NesButton) = match kind {
-      InputKind::Press   => |nes, btn| nes.get_joypad().buttons1.insert(btn),
-      InputKind::Release => |nes, btn| nes.get_joypad().buttons1.remove(btn),
+    self.player_input_event(0, button, kind);
+  }
+
+  fn player_input_event(&mut self, player: usize, button: &GameInput, kind: InputKind) {
+    let method: fn(&mut Nes, NesButton) = match (player, kind) {
+      (0, InputKind::Press)   => |nes, btn| nes.get_joypad().buttons1.insert(btn),
+      (0, InputKind::Release) => |nes, btn| nes.get_joypad().buttons1.remove(btn),
+      (1, InputKind::Press)   => |nes, btn| nes.get_joypad().buttons2.insert(btn),
+      (1, InputKind::Release) => |nes, btn| nes.get_joypad().buttons2.remove(btn),
+      _ => return,
     };
 
     match button {
@@ -50,40 +290,49 @@ impl EmuInterface for Nes {
         GameInput::B      => method(self, NesButton::b),
         GameInput::Start  => method(self, NesButton::start),
         GameInput::Select => method(self, NesButton::select),
+        // another core's buttons
+        _ => {}
     }
   }
 
   fn reset(&mut self) { self.reset(); }
-  
-  fn save(&self, path: &Path) {
-    let filename = PathBuf::from(path).with_extension("sav");
-    let mut file = fs::File::create(filename).unwrap();
 
-    // let _ = bincode::serialize_into(file, self)
-    //   .map_err(|msg| eprintln!("Couldn't save: {msg}\n"));
+  fn sram(&self) -> Option<&[u8]> { self.get_sram() }
+  fn load_sram(&mut self, data: &[u8]) { self.set_sram(data); }
 
-    let config = ron::ser::PrettyConfig::new().compact_arrays(true);
-    let ser = ron::ser::to_string_pretty(&self, config).unwrap();
-		file.write_fmt(format_args!("{ser}")).unwrap();
-  }
+  fn core_id(&self) -> CoreId { CoreId::Nes }
 
-  fn load(&mut self, path: &Path) {
-    let filename = PathBuf::from(path).with_extension("sav");
-    let file = fs::File::open(filename);
+  fn capabilities(&self) -> EmuCaps {
+    EmuCaps {
+      audio: true,
+      save_states: true,
+      battery_ram: self.sram().is_some(),
+      second_player: true,
+      // the core has no way to reach its bus from outside
+      memory: false,
+      // nor its video memory
+      vram: false,
+      // the APU mixes its channels with no way to leave one out
+      channel_mute: false,
+    }
+  }
 
-    match file {
-      Ok(mut file) => {
-        // let mut new_emu: Self = bincode::deserialize_from(file).unwrap();
+  fn save_state(&self) -> Result<Vec<u8>, EmuError> { encode_emu(self) }
 
-        let mut de = String::new();
-        file.read_to_string(&mut de).unwrap();
-        let new_emu: Self = ron::from_str(&de).unwrap(); 
+  fn load_state(&mut self, data: &[u8]) -> Result<(), EmuError> {
+    let new_emu: Self = decode_emu(data)?;
+    self.load_from_emu(new_emu);
+    Ok(())
+  }
 
-        self.load_from_emu(new_emu);
-      }
-      Err(e) => eprintln!("No save found: {e}\n"),
-    }
-    
+  fn load_legacy_state(&mut self, data: &[u8]) -> Result<(), EmuError> {
+    let new_emu: Self = match data.starts_with(&GZIP_MAGIC) {
+      true => bincode::deserialize_from(GzDecoder::new(data)).map_err(|e| e.to_string()),
+      // the oldest states are uncompressed RON
+      false => ron::from_str(&String::from_utf8_lossy(data)).map_err(|e| e.to_string()),
+    }.map_err(EmuError::Serde)?;
+    self.load_from_emu(new_emu);
+    Ok(())
   }
 }
 
@@ -95,14 +344,62 @@ impl EmuInterface for Gameboy {
     (&lcd.buffer, lcd.pitch())
   }
 
-  fn samples(&mut self) -> Vec<f32> { self.get_samples() }
+  fn samples(&mut self, out: &mut Vec<f32>) { out.append(&mut self.get_samples()); }
+  fn discard_samples(&mut self) { self.get_samples(); }
+  // VRAM, the cartridge's RAM, work RAM, OAM and high RAM
+  fn ram_ranges(&self) -> Vec<(u32, u32)> {
+    vec![(0x8000, 0x9fff), (0xa000, 0xbfff), (0xc000, 0xdfff), (0xfe00, 0xfe9f), (0xff80, 0xfffe)]
+  }
+  fn peek(&self, addr: u32) -> Option<u8> {
+    ram_addr(&self.ram_ranges(), addr).map(|addr| Gameboy::peek(self, addr))
+  }
+  fn poke(&mut self, addr: u32, val: u8) -> bool {
+    ram_addr(&self.ram_ranges(), addr).map(|addr| Gameboy::poke(self, addr, val)).is_some()
+  }
+
+  // None unless the MBC has a battery, so carts with plain RAM or none get no .srm
+  fn sram(&self) -> Option<&[u8]> { self.get_sram() }
+  fn load_sram(&mut self, data: &[u8]) { self.set_sram(data); }
+
+  // the 384 tiles of 0x8000-0x97ff, and both 32x32 maps with the background one showing the scroll
+  fn debug_vram(&self) -> Option<DebugVram> {
+    let peek = |addr: u16| Gameboy::peek(self, addr);
+    let tiles = (0..384u16).map(|t| {
+      let mut tile = [0; TILE_SIZE * TILE_SIZE];
+      for row in 0..8 {
+        let addr = 0x8000 + t * 16 + row * 2;
+        DebugVram::decode_row(&mut tile, row as usize, peek(addr), peek(addr + 1));
+      }
+      tile
+    }).collect();
+
+    // the raw shades for the tile sheet, then the same shades through BGP for the maps
+    let shades = GB_PALETTES[1].1;
+    let bgp = peek(0xff47);
+    let palettes = vec![shades, std::array::from_fn(|c| shades[(bgp >> (c * 2)) as usize & 3])];
+
+    let lcdc = peek(0xff40);
+    let maps = [0x9800u16, 0x9c00].into_iter().map(|base| {
+      let cells = (0..32 * 32).map(|i| {
+        let index = peek(base + i);
+        // with LCDC bit 4 clear, indices are signed and relative to tile 256
+        let tile = if lcdc & 0x10 != 0 { index as usize } else { (256 + index as i8 as isize) as usize };
+        MapCell { tile, palette: 1 }
+      }).collect();
+
+      let bg_base = if lcdc & 0x08 != 0 { 0x9c00 } else { 0x9800 };
+      let view = (base == bg_base).then(|| View { x: peek(0xff43) as usize, y: peek(0xff42) as usize, width: 160, height: 144 });
+      TileMap { width: 32, height: 32, cells, view }
+    }).collect();
+
+    Some(DebugVram { tiles, palettes, maps })
+  }
   fn resolution(&self) -> (usize, usize) { (160, 144) }
   fn fps(&self) -> f32 { 59.73 }
 
-  fn audio_spec(&self) -> (bool, AudioSpecDesired) {
-    let spec = AudioSpecDesired { channels: Some(2), freq: Some(44100), samples: None };
-    // TODO: as gb doesn't yet have sound functionality, it is disabled by default
-    (true, spec)
+  // left and right, as the core mixes them
+  fn native_audio(&self) -> Option<AudioSource> {
+    Some(AudioSource { rate: 44100, channels: 2 })
   }
 
   fn input_event(&mut self, button: &GameInput, kind: InputKind) {
@@ -124,8 +421,145 @@ impl EmuInterface for Gameboy {
         GameInput::B      => method_btn(self, GbButton::b_left),
         GameInput::Start  => method_btn(self, GbButton::start_down),
         GameInput::Select => method_btn(self, GbButton::select_up),
+        // another core's buttons
+        _ => {}
     }
   }
 
+  // there's no reset button on a Game Boy, the frontend power cycles it with hard_reset instead
   fn reset(&mut self) {}
-}
\ No newline at end of file
+
+  fn hard_reset(&mut self, rom: &[u8]) -> Result<(), String> {
+    let sram = self.sram().map(<[u8]>::to_vec);
+    *self = Gameboy::boot_from_bytes(rom)?;
+    if let Some(sram) = sram {
+      self.load_sram(&sram);
+    }
+    Ok(())
+  }
+
+  fn core_id(&self) -> CoreId { CoreId::Gameboy }
+
+  fn capabilities(&self) -> EmuCaps {
+    EmuCaps {
+      // samples are forwarded, the frontend keeps the audio off while the core gives none
+      audio: true,
+      // the core's types can't be serialized yet
+      save_states: false,
+      battery_ram: self.sram().is_some(),
+      second_player: false,
+      memory: true,
+      vram: true,
+      channel_mute: false,
+    }
+  }
+
+  // 0x134-0x143 of the header, padded with zeroes. Color games use the last byte as the CGB flag
+  fn game_title(&self) -> Option<String> {
+    let mut bytes: Vec<_> = (0x134..=0x143).map(|addr| Gameboy::peek(self, addr)).collect();
+    if bytes[15] & 0x80 != 0 {
+      bytes.pop();
+    }
+    let title: String = bytes.into_iter()
+      .take_while(|byte| *byte != 0)
+      .filter(|byte| byte.is_ascii_graphic() || *byte == b' ')
+      .map(char::from)
+      .collect();
+    Some(title.trim().to_string()).filter(|title| !title.is_empty())
+  }
+
+  fn set_palette(&mut self, palette: GbPalette) { self.get_ppu().set_palette(palette); }
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{mixer::ChannelMixer, resampler::Resampler, test_core::{allocations, TestCore, SAMPLES_PER_FRAME}};
+  use std::time::Duration;
+
+  #[test]
+  fn steady_state_audio_doesnt_allocate() {
+    let mut emu: Emulator = Box::new(TestCore::new());
+    let mut samples = Vec::new();
+    let mut mixer = ChannelMixer::new(1, 2);
+    let mut resampler = Resampler::new(44100, 48000, 2, Duration::from_millis(50));
+    let mut frame = |emu: &mut Emulator| {
+      emu.step_one_frame();
+      samples.clear();
+      emu.samples(&mut samples);
+      resampler.process(mixer.process(&samples));
+    };
+
+    // the buffers grow to a frame's worth first
+    for _ in 0..10 { frame(&mut emu); }
+    assert_eq!(allocations(|| for _ in 0..600 { frame(&mut emu) }), 0);
+    // muted, the samples are dropped without being copied out
+    assert_eq!(allocations(|| for _ in 0..600 { emu.step_one_frame(); emu.discard_samples(); }), 0);
+  }
+
+  #[test]
+  fn steps_whole_frames_for_samples() {
+    let mut emu: Emulator = Box::new(TestCore::new());
+    let mut out = Vec::new();
+    emu.step_samples(1000, &mut out);
+    assert_eq!(out.len(), 2 * SAMPLES_PER_FRAME);
+    emu.step_samples(SAMPLES_PER_FRAME, &mut out);
+    assert_eq!(out.len(), 3 * SAMPLES_PER_FRAME);
+  }
+
+  #[test]
+  fn frame_hash_is_the_same_across_boots() {
+    let run = |press: bool| {
+      let mut emu: Emulator = Box::new(TestCore::new());
+      for frame in 0..20 {
+        if press && frame == 10 { emu.input_event(&GameInput::A, InputKind::Press); }
+        emu.step_one_frame();
+      }
+      emu.frame_hash()
+    };
+    assert_eq!(run(false), run(false));
+    assert_ne!(run(false), run(true));
+  }
+
+  #[test]
+  fn ram_addresses_stay_in_range() {
+    let ranges = [(0x8000, 0x9fff), (0xff80, 0xfffe)];
+    assert_eq!(ram_addr(&ranges, 0x8000), Some(0x8000));
+    assert_eq!(ram_addr(&ranges, 0x9fff), Some(0x9fff));
+    assert_eq!(ram_addr(&ranges, 0xfffe), Some(0xfffe));
+    // registers and anything past the bus are left out
+    assert_eq!(ram_addr(&ranges, 0xff40), None);
+    assert_eq!(ram_addr(&ranges, 0xffff), None);
+    assert_eq!(ram_addr(&ranges, 0x1_8000), None);
+  }
+
+  #[test]
+  fn memory_access_defaults_to_none() {
+    let mut emu: Emulator = Box::new(TestCore::new());
+    assert!(emu.ram_ranges().is_empty());
+    assert_eq!(emu.peek(0), None);
+    assert!(!emu.poke(0, 1));
+    assert!(!emu.capabilities().memory);
+  }
+
+  #[test]
+  fn state_round_trips_through_bytes_and_files() {
+    let mut emu: Emulator = Box::new(TestCore::new());
+    for _ in 0..30 { emu.step_one_frame(); }
+    let (state, hash) = (emu.save_state().unwrap(), emu.frame_hash());
+
+    for _ in 0..10 { emu.step_one_frame(); }
+    assert_ne!(emu.frame_hash(), hash);
+    emu.load_state(&state).unwrap();
+    assert_eq!(emu.frame_hash(), hash);
+
+    // files are the same bytes behind a header
+    let path = std::env::temp_dir().join(format!("cmbemu-test-{}-emu.state", std::process::id()));
+    let header = StateHeader { core: emu.core_id(), rom_crc: 1 };
+    emu.save(&path, &header).unwrap();
+    for _ in 0..10 { emu.step_one_frame(); }
+    emu.load(&path, &header).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(emu.frame_hash(), hash);
+    assert_eq!(emu.save_state().unwrap(), state);
+  }
+}
@@ -0,0 +1,473 @@
+use std::{path::PathBuf, sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TryIter, TrySendError}, thread::{self, JoinHandle}, time::Duration};
+
+use log::error;
+
+use crate::{battery::SramTracker, cheats::Cheats, emu::{shown_region, Emulator, EmuPixelFormat}, vram::DebugVram, input::{GameInput, InputKind}, input_display::DeliveredInputs, netplay::Netplay, pacing::FramePacer, palette::GbPalette, rewind::Rewind, runahead::RunAhead, savestate::{CoreId, StateHeader}, turbo::Turbo};
+
+// how often a paused thread still checks netplay
+const IDLE_WAIT: Duration = Duration::from_millis(10);
+// ticks kept when the thread falls behind the display, more would only build up latency
+const MAX_TICKS: u32 = 2;
+// frames stepped per shown one while fast-forwarding
+const FAST_FORWARD_SPEED: u32 = 4;
+
+/// What the main thread asks of the emulation thread. Handled in order, between frames.
+pub enum Command {
+  /// Replaces the running game and unpauses, the ROM CRC32 starts a netplay session.
+  Load(Emulator, u32),
+  Input(GameInput, InputKind),
+  // of another player than the first, which the local inputs above are
+  PlayerInput(usize, GameInput, InputKind),
+  Turbo(GameInput, InputKind),
+  // while held, several frames are stepped per shown one, the ones in between aren't heard
+  FastForward(bool),
+  // while held, the game goes back through the last seconds of play
+  Rewind(bool),
+  Pause(bool),
+  // muted, the audio is dropped instead of sent
+  Mute(bool),
+  Reset,
+  // reboots the core in place from the game's ROM, for consoles without a reset button
+  HardReset(Vec<u8>),
+  SaveState(PathBuf, StateHeader),
+  LoadState(PathBuf, StateHeader, Sender<Result<(), String>>),
+  Sram(Sender<Option<Vec<u8>>>),
+  // same, but None unless the game wrote to it since the last time
+  DirtySram(Sender<Option<Vec<u8>>>),
+  SetPalette(GbPalette),
+  // crops the frames sent to the visible region, see emu::shown_region
+  HideOverscan(bool),
+  // a sound channel by index, see EmuInterface::audio_channels
+  ChannelEnabled(usize, bool),
+  // the loaded game's cheats, and whether they're on
+  Cheats(Cheats),
+  CheatsEnabled(bool),
+  // sends the video memory after every frame and command while on
+  Vram(bool),
+  // steps a single frame, even while paused
+  Step,
+  // with vsync, the main thread's present paces emulation: a frame per tick instead of the timer's
+  Vsync(bool),
+  // with audio clocking, the main thread asks for samples instead of frames
+  AudioClock(bool),
+  Samples(usize),
+  Tick,
+  Quit,
+}
+
+/// What the emulation thread reports back, besides frames and audio.
+pub enum Notice {
+  Message(String),
+  // the thread paused itself when it ended
+  NetplayEnded(String),
+  // None when the core has none to show
+  Vram(Option<DebugVram>),
+}
+
+/// A completed frame, possibly a run-ahead one.
+pub struct Frame {
+  pub buf: Vec<u8>,
+  pub pitch: usize,
+  pub resolution: (usize, usize),
+  // the part of it to show, see emu::shown_region
+  pub visible: (usize, usize, usize, usize),
+  pub format: EmuPixelFormat,
+  pub core: CoreId,
+  // of the emulation thread's pacing
+  pub jitter: Duration,
+  // what the core was given by the time it made the frame
+  pub inputs: DeliveredInputs,
+}
+
+/// Handle to the thread that owns the emulator and steps it on its own timer, or on the main thread's
+/// ticks under vsync, so a slow present on the main thread doesn't stall emulation and audio.
+pub struct EmuThread {
+  commands: Sender<Command>,
+  frames: Receiver<Frame>,
+  // frame buffers go back once shown, so the two sides swap the same pair
+  recycle: Sender<Vec<u8>>,
+  audio: Receiver<Vec<f32>>,
+  // same for audio buffers, so steady state frames don't allocate any
+  recycle_audio: Sender<Vec<f32>>,
+  notices: Receiver<Notice>,
+  frame: Option<Frame>,
+  handle: Option<JoinHandle<()>>,
+}
+
+impl EmuThread {
+  pub fn spawn(emu: Emulator, run_ahead: u8, turbo_rate: u32, block_opposing: bool, hide_overscan: bool, netplay: Option<Netplay>) -> Self {
+    let (commands, commands_rx) = mpsc::channel();
+    let (frames_tx, frames) = mpsc::sync_channel(1);
+    let (recycle, recycle_rx) = mpsc::channel();
+    let (audio_tx, audio) = mpsc::channel();
+    let (recycle_audio, recycle_audio_rx) = mpsc::channel();
+    let (notices_tx, notices) = mpsc::channel();
+
+    let mut turbo = Turbo::default();
+    turbo.set_rate(emu.fps(), turbo_rate);
+    let runner = Runner {
+      pacer: FramePacer::new(frame_duration(emu.fps())),
+      emu, paused: true, muted: true, vsync: false, ticks: 0, audio_clocked: false, samples_wanted: 0, turbo, turbo_rate, inputs: DeliveredInputs::new(block_opposing), cheats: Cheats::default(), cheats_enabled: true, vram: false, sram: SramTracker::default(), hide_overscan,
+      run_ahead: RunAhead::new(run_ahead), rewind: Rewind::default(), rewinding: false, fast_forward: false, netplay,
+      commands: commands_rx, frames: frames_tx, recycle: recycle_rx, spare: None, audio: audio_tx, recycle_audio: recycle_audio_rx, notices: notices_tx,
+    };
+    let handle = thread::Builder::new()
+      .name("emulation".into())
+      .spawn(move || runner.run())
+      .expect("couldn't start the emulation thread");
+
+    Self { commands, frames, recycle, audio, recycle_audio, notices, frame: None, handle: Some(handle) }
+  }
+
+  pub fn send(&self, command: Command) {
+    // only fails once the thread is gone, which is already logged
+    let _ = self.commands.send(command);
+  }
+
+  /// Loads a state file, waiting for the thread to finish the frame it's on.
+  pub fn load_state(&self, path: PathBuf, header: StateHeader) -> Result<(), String> {
+    let (tx, rx) = mpsc::channel();
+    self.send(Command::LoadState(path, header, tx));
+    rx.recv().unwrap_or_else(|_| Err("the emulation thread stopped".into()))
+  }
+
+  pub fn sram(&self) -> Option<Vec<u8>> {
+    let (tx, rx) = mpsc::channel();
+    self.send(Command::Sram(tx));
+    rx.recv().ok().flatten()
+  }
+
+  /// Asks for the cartridge RAM without waiting, the reply comes once the thread is between frames.
+  /// It's None when it's the same as the last time, so it's only copied out when it changed.
+  pub fn request_dirty_sram(&self) -> mpsc::Receiver<Option<Vec<u8>>> {
+    let (tx, rx) = mpsc::channel();
+    self.send(Command::DirtySram(tx));
+    rx
+  }
+
+  /// Takes the newest completed frame, waiting up to `timeout` for one to arrive.
+  pub fn update_frame(&mut self, timeout: Duration) {
+    let first = match timeout.is_zero() {
+      true => self.frames.try_recv().ok(),
+      false => self.frames.recv_timeout(timeout).ok(),
+    };
+    for frame in first.into_iter().chain(std::iter::from_fn(|| self.frames.try_recv().ok())) {
+      if let Some(old) = self.frame.replace(frame) {
+        let _ = self.recycle.send(old.buf);
+      }
+    }
+  }
+
+  /// The last frame taken by `update_frame`, None until the first one is stepped.
+  pub fn frame(&self) -> Option<&Frame> {
+    self.frame.as_ref()
+  }
+
+  /// Hands out the audio of the frames stepped since the last call, one buffer per frame.
+  pub fn audio(&self, mut queue: impl FnMut(&mut Vec<f32>)) {
+    for mut samples in self.audio.try_iter() {
+      queue(&mut samples);
+      let _ = self.recycle_audio.send(samples);
+    }
+  }
+
+  pub fn notices(&self) -> TryIter<'_, Notice> {
+    self.notices.try_iter()
+  }
+
+  /// Stops the thread once it's done with the commands sent before, and waits for it.
+  pub fn join(&mut self) {
+    self.send(Command::Quit);
+    if let Some(handle) = self.handle.take() {
+      if handle.join().is_err() {
+        error!("The emulation thread panicked");
+      }
+    }
+  }
+}
+
+fn frame_duration(fps: f32) -> Duration {
+  if fps > 0.0 { Duration::from_secs_f32(1.0 / fps) } else { Duration::ZERO }
+}
+
+struct Runner {
+  emu: Emulator,
+  paused: bool,
+  muted: bool,
+  vsync: bool,
+  audio_clocked: bool,
+  // samples the main thread asked for and that weren't made yet
+  samples_wanted: usize,
+  // frames the main thread asked for and that weren't stepped yet
+  ticks: u32,
+  pacer: FramePacer,
+  turbo: Turbo,
+  turbo_rate: u32,
+  // every input reaches the core through it
+  inputs: DeliveredInputs,
+  run_ahead: RunAhead,
+  rewind: Rewind,
+  rewinding: bool,
+  fast_forward: bool,
+  netplay: Option<Netplay>,
+  cheats: Cheats,
+  cheats_enabled: bool,
+  vram: bool,
+  // the cartridge RAM as the main thread last took it
+  sram: SramTracker,
+  // frames are sent cropped to the visible region
+  hide_overscan: bool,
+
+  commands: Receiver<Command>,
+  frames: SyncSender<Frame>,
+  recycle: Receiver<Vec<u8>>,
+  // a frame buffer the main thread didn't take in time
+  spare: Option<Vec<u8>>,
+  audio: Sender<Vec<f32>>,
+  recycle_audio: Receiver<Vec<f32>>,
+  notices: Sender<Notice>,
+}
+
+impl Runner {
+  fn run(mut self) {
+    loop {
+      while let Ok(command) = self.commands.try_recv() {
+        if !self.handle(command) { return; }
+      }
+      self.poll_netplay();
+
+      let idle = self.paused || (self.vsync && self.ticks == 0) || (self.audio_clocked && self.samples_wanted == 0);
+      if idle {
+        match self.commands.recv_timeout(IDLE_WAIT) {
+          Ok(command) => if !self.handle(command) { return; },
+          Err(RecvTimeoutError::Timeout) => {}
+          Err(RecvTimeoutError::Disconnected) => return,
+        }
+        continue;
+      }
+
+      if self.audio_clocked {
+        self.step_samples();
+        self.send_frame();
+        self.send_vram();
+        continue;
+      }
+      if self.step_frame() {
+        self.send_frame();
+        self.send_vram();
+      }
+      match self.vsync {
+        true => self.ticks -= 1,
+        false => self.pacer.wait(),
+      }
+    }
+  }
+
+  // returns false on quit
+  fn handle(&mut self, command: Command) -> bool {
+    match command {
+      Command::Load(emu, rom_crc) => {
+        self.emu = emu;
+        // an autosave may have replaced the RAM before the core got here, the battery's hash skips the write if not
+        self.sram.forget();
+        self.turbo = Turbo::default();
+        self.inputs = DeliveredInputs::new(self.inputs.block_opposing());
+        self.rewind.clear();
+        self.turbo.set_rate(self.emu.fps(), self.turbo_rate);
+        self.pacer.set_frame(frame_duration(self.emu.fps()));
+        // both players must start from power on
+        if let Some(netplay) = &mut self.netplay {
+          netplay.start(rom_crc);
+        }
+        self.run_ahead.probe(&mut self.emu);
+        self.paused = false;
+      }
+      // with netplay, inputs reach the core in lockstep with the other player's
+      Command::Input(input, kind) => match &mut self.netplay {
+        Some(netplay) => netplay.local_input(input, &kind),
+        None => {
+          self.turbo.normal_event(input, &kind);
+          self.inputs.send(&mut self.emu, 0, &input, kind);
+        }
+      },
+      // with netplay, the other players are on the other side
+      Command::PlayerInput(player, input, kind) => if self.netplay.is_none() {
+        self.inputs.send(&mut self.emu, player, &input, kind);
+      },
+      Command::Turbo(input, kind) => self.turbo.turbo_event(&mut self.emu, &mut self.inputs, input, kind),
+      Command::FastForward(on) => self.fast_forward = on,
+      Command::Rewind(on) => self.rewinding = on,
+      Command::Pause(paused) => self.paused = paused,
+      Command::Mute(muted) => self.muted = muted,
+      Command::Reset => {
+        self.emu.reset();
+        self.run_ahead.invalidate();
+        self.paused = false;
+      }
+      Command::HardReset(rom) => {
+        if let Err(msg) = self.emu.hard_reset(&rom) {
+          let _ = self.notices.send(Notice::Message(format!("Couldn't reset: {msg}")));
+        }
+        self.run_ahead.invalidate();
+        self.paused = false;
+      }
+      Command::SaveState(path, header) => if let Err(e) = self.emu.save(&path, &header) {
+        error!("Couldn't save state {}: {e}", path.display());
+        let _ = self.notices.send(Notice::Message(format!("Couldn't save state: {e}")));
+      },
+      Command::LoadState(path, header, reply) => {
+        self.run_ahead.invalidate();
+        let result = self.emu.load(&path, &header).map_err(|e| e.to_string());
+        // the state brings its own cartridge RAM
+        if result.is_ok() { self.sram.forget(); }
+        let _ = reply.send(result);
+      }
+      Command::Sram(reply) => { let _ = reply.send(self.emu.sram().map(<[u8]>::to_vec)); }
+      Command::DirtySram(reply) => { let _ = reply.send(self.sram.take_changed(self.emu.sram())); }
+      Command::SetPalette(palette) => self.emu.set_palette(palette),
+      Command::HideOverscan(hide) => {
+        self.hide_overscan = hide;
+        // so the crop shows while paused too
+        self.send_frame();
+      }
+      Command::ChannelEnabled(idx, on) => self.emu.set_channel_enabled(idx, on),
+      Command::Cheats(cheats) => self.cheats = cheats,
+      Command::CheatsEnabled(enabled) => self.cheats_enabled = enabled,
+      Command::Vram(vram) => self.vram = vram,
+      Command::Step => if self.step_frame() {
+        self.send_frame();
+      },
+      Command::Vsync(vsync) => {
+        self.vsync = vsync;
+        self.ticks = 0;
+      }
+      Command::AudioClock(clocked) => {
+        self.audio_clocked = clocked;
+        self.samples_wanted = 0;
+        // run-ahead works by whole frames
+        self.run_ahead.invalidate();
+      }
+      Command::Samples(n) => self.samples_wanted += n,
+      Command::Tick => if !self.paused {
+        self.ticks = (self.ticks + 1).min(MAX_TICKS);
+      },
+      Command::Quit => return false,
+    }
+    self.send_vram();
+    true
+  }
+
+  fn send_vram(&self) {
+    if self.vram {
+      let _ = self.notices.send(Notice::Vram(self.emu.debug_vram()));
+    }
+  }
+
+  fn poll_netplay(&mut self) {
+    let Some(netplay) = &mut self.netplay else { return };
+
+    match netplay.poll() {
+      Ok(Some(status)) => { let _ = self.notices.send(Notice::Message(status)); }
+      Ok(None) => {}
+      Err(msg) => {
+        self.netplay = None;
+        self.paused = true;
+        let _ = self.notices.send(Notice::NetplayEnded(msg));
+      }
+    }
+  }
+
+  // returns whether a frame was stepped, its audio is sent right away
+  fn step_frame(&mut self) -> bool {
+    // netplay steps only when the other player's input has arrived, the future one can't be run ahead
+    if let Some(netplay) = &mut self.netplay {
+      let stepped = netplay.step(&mut self.emu, &mut self.inputs);
+      self.run_ahead.invalidate();
+      self.send_audio();
+      return stepped;
+    }
+
+    // the frame after the snapshot is shown, the sound of going backwards isn't played
+    if self.rewinding {
+      if self.rewind.step_back(&mut self.emu) {
+        self.emu.step_one_frame();
+        self.emu.discard_samples();
+        self.run_ahead.invalidate();
+      }
+      return true;
+    }
+
+    if self.fast_forward {
+      for _ in 1..FAST_FORWARD_SPEED {
+        self.turbo.tick(&mut self.emu, &mut self.inputs);
+        self.emu.step_one_frame();
+        if self.cheats_enabled { self.cheats.apply_ram(&mut self.emu); }
+        self.emu.discard_samples();
+        self.rewind.frame(&self.emu);
+      }
+    }
+
+    self.turbo.tick(&mut self.emu, &mut self.inputs);
+    self.emu.step_one_frame();
+    if self.cheats_enabled { self.cheats.apply_ram(&mut self.emu); }
+    self.send_audio();
+    self.rewind.frame(&self.emu);
+
+    let cheats = self.cheats_enabled.then_some(&self.cheats);
+    self.run_ahead.run(&mut self.emu, |emu| if let Some(cheats) = cheats { cheats.apply_ram(emu); });
+    true
+  }
+
+  // runs until the samples asked for are made, turbo and cheats are applied once per call instead of per frame
+  fn step_samples(&mut self) {
+    let mut samples = self.recycle_audio.try_recv().unwrap_or_default();
+    samples.clear();
+    // the samples asked for are still made, the core plays on from the snapshot for a bit
+    if self.rewinding && self.rewind.step_back(&mut self.emu) {
+      self.run_ahead.invalidate();
+    }
+    self.turbo.tick(&mut self.emu, &mut self.inputs);
+    self.emu.step_samples(self.samples_wanted, &mut samples);
+    if self.cheats_enabled { self.cheats.apply_ram(&mut self.emu); }
+    if !self.rewinding { self.rewind.frame(&self.emu); }
+
+    // a core that stopped making sound would never catch up, the main thread switches back to frames
+    self.samples_wanted = match samples.is_empty() {
+      true => 0,
+      false => self.samples_wanted.saturating_sub(samples.len()),
+    };
+    let _ = self.audio.send(samples);
+  }
+
+  // the core gives its samples up the same either way, so muting can't change the emulation
+  fn send_audio(&mut self) {
+    if self.muted {
+      self.emu.discard_samples();
+      return;
+    }
+
+    let mut samples = self.recycle_audio.try_recv().unwrap_or_default();
+    samples.clear();
+    self.emu.samples(&mut samples);
+    let _ = self.audio.send(samples);
+  }
+
+  fn send_frame(&mut self) {
+    let mut buf = self.spare.take()
+      .or_else(|| self.recycle.try_recv().ok())
+      .unwrap_or_default();
+
+    let (framebuf, pitch) = match self.run_ahead.framebuf() {
+      Some(ahead) => ahead,
+      None => self.emu.framebuf(),
+    };
+    buf.clear();
+    buf.extend_from_slice(framebuf);
+
+    let frame = Frame { buf, pitch, resolution: self.emu.resolution(), visible: shown_region(&*self.emu, self.hide_overscan), format: self.emu.pixel_format(), core: self.emu.core_id(), jitter: self.pacer.jitter(), inputs: self.inputs };
+    // the main thread still has the last one queued, this one is dropped
+    if let Err(TrySendError::Full(frame)) = self.frames.try_send(frame) {
+      self.spare = Some(frame.buf);
+    }
+  }
+}
@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{emu::EmuPixelFormat, ntsc::{Ntsc, NTSC_WIDTH}, savestate::CoreId};
+
+// brightness of the darkened lines, out of 256
+const SCANLINE_LEVEL: u32 = 180;
+// brightness of the two dimmed channels of each mask column, out of 256
+const MASK_LEVEL: u32 = 215;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterKind {
+  #[default]
+  None,
+  Scanlines,
+  // scanlines with an RGB phosphor mask
+  Crt,
+}
+
+impl FilterKind {
+  pub fn next(self) -> Self {
+    match self {
+      FilterKind::None => FilterKind::Scanlines,
+      FilterKind::Scanlines => FilterKind::Crt,
+      FilterKind::Crt => FilterKind::None,
+    }
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      FilterKind::None => "none",
+      FilterKind::Scanlines => "scanlines",
+      FilterKind::Crt => "scanlines + mask",
+    }
+  }
+
+  fn scale(self) -> usize {
+    match self {
+      FilterKind::None => 1,
+      FilterKind::Scanlines | FilterKind::Crt => 2,
+    }
+  }
+}
+
+/// Post-processing applied to the core's framebuffer before it's uploaded:
+/// the NTSC signal (NES only) first, then scanlines on top.
+/// Filtering happens on pre-scaled buffers, reused across frames.
+pub struct Filter {
+  pub kind: FilterKind,
+  pub ntsc_enabled: bool,
+  ntsc: Ntsc,
+  out: Vec<u8>,
+}
+
+impl Filter {
+  pub fn new(kind: FilterKind, ntsc_enabled: bool) -> Self {
+    Self { kind, ntsc_enabled, ntsc: Ntsc::new(), out: Vec::new() }
+  }
+
+  fn ntsc_active(&self, core: CoreId) -> bool {
+    self.ntsc_enabled && core == CoreId::Nes
+  }
+
+  /// Size of the filtered image.
+  pub fn output_size(&self, (width, height): (usize, usize), core: CoreId) -> (usize, usize) {
+    let width = if self.ntsc_active(core) { NTSC_WIDTH } else { width };
+    (width * self.kind.scale(), height * self.kind.scale())
+  }
+
+  /// Logical size the visible region of the filtered image is shown at.
+  pub fn display_size(&self, resolution: (usize, usize), (_, _, width, height): (usize, usize, usize, usize), core: CoreId) -> (usize, usize) {
+    // lines are doubled, keeping the aspect of a TV
+    let (full_width, full_height) = if self.ntsc_active(core) { (NTSC_WIDTH, resolution.1 * 2) } else { resolution };
+    (full_width * width / resolution.0, full_height * height / resolution.1)
+  }
+
+  /// Where the visible region of the frame ends up in the filtered image.
+  pub fn visible_rect(&self, resolution: (usize, usize), (x, y, width, height): (usize, usize, usize, usize), core: CoreId) -> (usize, usize, usize, usize) {
+    let (out_width, out_height) = self.output_size(resolution, core);
+    let (scale_x, scale_y) = (|n: usize| n * out_width / resolution.0, |n: usize| n * out_height / resolution.1);
+    (scale_x(x), scale_y(y), scale_x(width), scale_y(height))
+  }
+
+  /// Filters `framebuf`, of pixels in `format`, returning the image to upload, its pitch and its format.
+  /// The scanlines keep the format they're given, the NTSC signal always comes out as RGBA32.
+  pub fn apply<'a>(&'a mut self, framebuf: &'a [u8], pitch: usize, resolution: (usize, usize), format: EmuPixelFormat, core: CoreId) -> (&'a [u8], usize, EmuPixelFormat) {
+    let (framebuf, pitch, resolution, format) = if self.ntsc_active(core) {
+      (self.ntsc.apply(framebuf, pitch, resolution, format), NTSC_WIDTH * 4, (NTSC_WIDTH, resolution.1), EmuPixelFormat::Rgba32)
+    } else {
+      (framebuf, pitch, resolution, format)
+    };
+
+    if self.kind == FilterKind::None {
+      return (framebuf, pitch, format);
+    }
+
+    let bpp = format.bytes_per_pixel();
+    let (width, height) = resolution;
+    let (out_width, out_height) = (width * 2, height * 2);
+    let out_pitch = out_width * bpp;
+    // only reallocates when the resolution changes
+    self.out.resize(out_pitch * out_height, 0);
+
+    let mask = self.kind == FilterKind::Crt;
+    for y in 0..height {
+      let src = &framebuf[y * pitch..y * pitch + width * bpp];
+      let (bright, dark) = self.out[y * 2 * out_pitch..(y * 2 + 2) * out_pitch].split_at_mut(out_pitch);
+
+      for (x, px) in src.chunks_exact(bpp).enumerate() {
+        let rgb = format.rgb(px);
+        for dx in 0..2 {
+          let out_x = x * 2 + dx;
+          let i = out_x * bpp;
+          let bright_rgb: [u8; 3] = std::array::from_fn(|ch| {
+            let value = rgb[ch] as u32;
+            (if mask && out_x % 3 != ch { value * MASK_LEVEL / 256 } else { value }) as u8
+          });
+          format.set_rgb(&mut bright[i..], bright_rgb);
+          format.set_rgb(&mut dark[i..], bright_rgb.map(|c| (c as u32 * SCANLINE_LEVEL / 256) as u8));
+        }
+      }
+    }
+
+    (&self.out, out_pitch, format)
+  }
+}
@@ -0,0 +1,59 @@
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME:  u64 = 0x0000_0100_0000_01b3;
+
+/// 64-bit FNV-1a, stable across runs and builds, so it can be written to disk.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+  fnv1a_extend(FNV_OFFSET, bytes)
+}
+
+// continues a hash with more bytes
+fn fnv1a_extend(hash: u64, bytes: &[u8]) -> u64 {
+  bytes.iter().fold(hash, |hash, byte| (hash ^ *byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// Hashes the visible pixels of a framebuffer with `bpp` bytes per pixel, skipping any row padding.
+pub fn frame_hash(framebuf: &[u8], pitch: usize, (width, height): (usize, usize), bpp: usize) -> u64 {
+  (0..height).fold(FNV_OFFSET, |hash, y| {
+    fnv1a_extend(hash, &framebuf[y * pitch..y * pitch + width * bpp])
+  })
+}
+
+/// Folds a frame's hash into the digest of the frames before it, for a digest of a whole run.
+pub fn chain_hash(digest: Option<u64>, frame_hash: u64) -> u64 {
+  fnv1a_extend(digest.unwrap_or(FNV_OFFSET), &frame_hash.to_le_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn fnv1a_reference_values() {
+    assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+    assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+  }
+
+  #[test]
+  fn frame_hash_skips_row_padding() {
+    // 2x2 pixels of 2 bytes, in rows of 6
+    let mut framebuf = [1, 2, 3, 4, 0, 0, 5, 6, 7, 8, 0, 0];
+    let hash = frame_hash(&framebuf, 6, (2, 2), 2);
+    assert_eq!(hash, fnv1a(&[1, 2, 3, 4, 5, 6, 7, 8]));
+
+    framebuf[4] = 0xff;
+    framebuf[11] = 0xff;
+    assert_eq!(frame_hash(&framebuf, 6, (2, 2), 2), hash);
+    framebuf[7] = 0xff;
+    assert_ne!(frame_hash(&framebuf, 6, (2, 2), 2), hash);
+  }
+
+  #[test]
+  fn chain_depends_on_order() {
+    let (a, b) = (fnv1a(b"first"), fnv1a(b"second"));
+    let forward = chain_hash(Some(chain_hash(None, a)), b);
+    let backward = chain_hash(Some(chain_hash(None, b)), a);
+    assert_ne!(forward, backward);
+    assert_eq!(forward, chain_hash(Some(chain_hash(None, a)), b));
+  }
+}
@@ -1,78 +1,939 @@
-use std::collections::HashMap;
+use std::{collections::{HashMap, HashSet}, time::{Duration, Instant}};
 
-use sdl2::{audio::AudioStatus, controller::{self, Axis, Button}, event::Event, keyboard::{self, Keycode}};
+use serde::{Deserialize, Serialize};
+use log::{info, warn};
+use sdl2::{audio::AudioStatus, controller::{self, Axis, Button}, event::Event, keyboard::{Keycode, Mod}};
 
-use crate::EmuContext;
+use crate::{combo::{ComboEvent, MenuCombo}, config::{CoreBindingsConfig, InputConfig, JoyConfig, KeyboardPlayer2, LayoutConfig, PadConfig}, emu_thread::Command, menu::{ListMenu, MenuAction, MenuKey}, rebind, rom::openable_extensions, savestate::CoreId, scope::Scope, slots::SLOTS, EmuContext};
 
 pub enum InputKind {
   Press, Release
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum InputEvent {
   Game(GameInput),
   Pause, Reset, Save, Load, Mute,
+  // boots the ROM again, keeping the battery save
+  HardReset,
+  OpenRom, Rebind, ReloadRom,
+  // switches between the binding layouts set up in the config
+  CycleLayout,
+  TurboA, TurboB,
+  VolumeUp, VolumeDown,
+  // target audio latency
+  LatencyUp, LatencyDown,
+  ToggleStats, CyclePalette, CycleBackground, CycleFilter, ToggleNtsc, Rotate, ToggleCheats,
+  FrameStep, ToggleVram, ToggleScope, ToggleFullscreen, ToggleInputDisplay,
+  // crops the edges most TVs cut
+  ToggleOverscan,
+  // window size, as a multiple of the core's resolution
+  Scale(u8),
+  // mutes or unmutes a sound channel of the core, counted from 1
+  MuteChannel(u8),
+  // makes the slot the one Save and Load use
+  SelectSlot(u8),
+  // saves to the slot, leaving the selected one as it is
+  SaveToSlot(u8),
+  // held down
+  FastForward, Rewind,
+  // of the frame as the core made it, without filters or overlays
+  Screenshot,
+  // the slot Save and Load use, stepping through them
+  NextSlot, PrevSlot,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub enum GameInput {
   Up, Down, Left, Right, A, B, Start, Select,
+  // of the PlayStation pad, only bound in its core's map
+  Cross, Circle, Square, Triangle, L1, R1, L2, R2,
 }
 
-const AXIS_DEAD_ZONE: i16 = 10_000;
+// for the axes without one of their own
+const DEFAULT_DEAD_ZONE: i16 = 10_000;
+const MIN_DEAD_ZONE: i16 = 1_000;
+const MAX_DEAD_ZONE: i16 = 30_000;
+// out of the dead zone, a stick holds the directions within 67.5° of where it points, so diagonals are as wide as the rest
+const STICK_SECTOR: f32 = 0.3827;
 
-pub struct Keymaps {
-  keymap: HashMap<keyboard::Keycode, InputEvent>,
-  padmap: HashMap<controller::Button, InputEvent>,
+// range of the target audio latency hotkeys
+const MIN_LATENCY_MS: i32 = 10;
+const MAX_LATENCY_MS: i32 = 500;
+
+// by the number on them
+const NUMBER_KEYS: [Keycode; 10] = [
+  Keycode::NUM_0, Keycode::NUM_1, Keycode::NUM_2, Keycode::NUM_3, Keycode::NUM_4,
+  Keycode::NUM_5, Keycode::NUM_6, Keycode::NUM_7, Keycode::NUM_8, Keycode::NUM_9,
+];
+
+// Quit follows
+const PAUSE_MENU: [&str; 5] = ["Resume", "Save state", "Load state", "Reset", "Open ROM"];
+
+// The profile used by controllers that have no bindings of their own
+const DEFAULT_PAD_PROFILE: &str = "default";
+// The layout made of the top level bindings, which is the hard-coded one unless they're changed
+const DEFAULT_LAYOUT: &str = "default";
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AxisDir {
+  pub axis: Axis,
+  pub positive: bool,
 }
-impl Default for Keymaps {
+impl AxisDir {
+  fn name(&self) -> String {
+    format!("{}{}", self.axis.string(), if self.positive { '+' } else { '-' })
+  }
+
+  fn from_name(name: &str) -> Option<Self> {
+    let (axis, sign) = name.split_at_checked(name.len().checked_sub(1)?)?;
+    let positive = match sign {
+      "+" => true,
+      "-" => false,
+      _ => return None,
+    };
+    Axis::from_string(axis).map(|axis| AxisDir { axis, positive })
+  }
+}
+
+/// A key with the modifiers held with it. The left and right modifier keys count the same.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Chord {
+  key: Keycode,
+  ctrl: bool,
+  alt: bool,
+  shift: bool,
+}
+impl Chord {
+  const fn plain(key: Keycode) -> Self { Chord { key, ctrl: false, alt: false, shift: false } }
+  const fn ctrl(key: Keycode) -> Self { Chord { ctrl: true, ..Chord::plain(key) } }
+  const fn alt(key: Keycode) -> Self { Chord { alt: true, ..Chord::plain(key) } }
+  const fn shift(key: Keycode) -> Self { Chord { shift: true, ..Chord::plain(key) } }
+
+  /// The chord of a key event. A modifier key pressed alone is a plain key, so it can be bound like any other.
+  pub fn from_event(key: Keycode, keymod: Mod) -> Self {
+    if is_modifier(key) { return Chord::plain(key); }
+    Chord {
+      key,
+      ctrl: keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD),
+      alt: keymod.intersects(Mod::LALTMOD | Mod::RALTMOD),
+      shift: keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD),
+    }
+  }
+
+  fn unmodified(&self) -> Self { Chord::plain(self.key) }
+
+  pub fn key(&self) -> Keycode { self.key }
+
+  // as in the config, e.g. "Ctrl+Shift+R"
+  fn name(&self) -> String {
+    let modifiers = [(self.ctrl, "Ctrl+"), (self.alt, "Alt+"), (self.shift, "Shift+")];
+    let prefix: String = modifiers.iter().filter(|(held, _)| *held).map(|(_, name)| *name).collect();
+    prefix + &self.key.name()
+  }
+
+  fn from_name(name: &str) -> Option<Self> {
+    let mut chord = Chord::plain(Keycode::A);
+    let mut rest = name;
+    // the key itself can be named "+", only whole prefixes are modifiers
+    loop {
+      if let Some(after) = rest.strip_prefix("Ctrl+") { chord.ctrl = true; rest = after; }
+      else if let Some(after) = rest.strip_prefix("Alt+") { chord.alt = true; rest = after; }
+      else if let Some(after) = rest.strip_prefix("Shift+") { chord.shift = true; rest = after; }
+      else { break; }
+    }
+    chord.key = Keycode::from_name(rest)?;
+    Some(chord)
+  }
+}
+
+pub fn is_modifier(key: Keycode) -> bool {
+  matches!(key, Keycode::LCtrl | Keycode::RCtrl | Keycode::LAlt | Keycode::RAlt | Keycode::LShift | Keycode::RShift | Keycode::LGui | Keycode::RGui)
+}
+
+// what on a controller holds a game input down
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum PadSource {
+  Button(Button),
+  Axis(Axis),
+  // of a plain joystick
+  JoyButton(u8),
+  JoyAxis(u8),
+}
+
+#[derive(Clone)]
+pub struct Padmap {
+  buttons: HashMap<controller::Button, InputEvent>,
+  axes: HashMap<AxisDir, GameInput>,
+  dead_zones: HashMap<Axis, i16>,
+  // analog triggers used as buttons, those without a binding here can be bound as axes
+  triggers: HashMap<Axis, InputEvent>,
+}
+impl Padmap {
+  fn dead_zone(&self, axis: Axis) -> i16 {
+    self.dead_zones.get(&axis).copied().unwrap_or(DEFAULT_DEAD_ZONE)
+  }
+}
+
+// Bindings of the joysticks without a controller mapping, which only number their buttons and axes
+#[derive(Clone)]
+pub struct Joymap {
+  buttons: HashMap<u8, InputEvent>,
+  // axis index with its direction, true for positive
+  axes: HashMap<(u8, bool), GameInput>,
+}
+
+fn joy_axis_from_name(name: &str) -> Option<(u8, bool)> {
+  let (index, sign) = name.split_at_checked(name.len().checked_sub(1)?)?;
+  let positive = match sign {
+    "+" => true,
+    "-" => false,
+    _ => return None,
+  };
+  Some((index.parse().ok()?, positive))
+}
+
+// the other axis of the same stick, the triggers have none
+fn stick_partner(axis: Axis) -> Option<Axis> {
+  match axis {
+    Axis::LeftX => Some(Axis::LeftY),
+    Axis::LeftY => Some(Axis::LeftX),
+    Axis::RightX => Some(Axis::RightY),
+    Axis::RightY => Some(Axis::RightX),
+    _ => None,
+  }
+}
+
+// whether an axis holds its negative and positive directions. A stick is out of its dead zone by the distance
+// from the center, scaled by each axis' own dead zone, so pushing diagonally doesn't need more or less travel
+fn axis_held((value, dead_zone): (i16, i16), partner: Option<(i16, i16)>) -> (bool, bool) {
+  let Some((partner_value, partner_dead_zone)) = partner else {
+    return (value < -dead_zone, value > dead_zone);
+  };
+  let scaled = |value: i16, dead_zone: i16| value as f32 / dead_zone.max(1) as f32;
+  let (x, y) = (scaled(value, dead_zone), scaled(partner_value, partner_dead_zone));
+  if x * x + y * y < 1.0 { return (false, false); }
+
+  let lean = value as f32 / (value as f32).hypot(partner_value as f32);
+  (lean < -STICK_SECTOR, lean > STICK_SECTOR)
+}
+
+// A whole set of keyboard and controller bindings, there can be several to switch between
+#[derive(Clone)]
+struct Layout {
+  keymap: HashMap<Chord, InputEvent>,
+  // player 2's game inputs, by key alone so they don't depend on what player 1 holds with them
+  keymap_p2: HashMap<Keycode, GameInput>,
+  // controller bindings, keyed by mapping profile (the controller name, or the default one)
+  padmaps: HashMap<String, Padmap>,
+  joymap: Joymap,
+  // game inputs of the cores that have their own, the hotkeys are always the shared ones
+  core_maps: HashMap<CoreId, CoreMap>,
+}
+
+// The game inputs of a single core, for the keyboard and every controller.
+// The shared game input bindings don't apply to a core that has one
+#[derive(Clone, Default)]
+struct CoreMap {
+  keymap: HashMap<Chord, GameInput>,
+  buttons: HashMap<Button, GameInput>,
+}
+impl Default for Layout {
   fn default() -> Self {
     use GameInput::*;
 
-    let default_keymap = HashMap::from([
-      (Keycode::K,   InputEvent::Game(A)),
-      (Keycode::L,   InputEvent::Game(B)),
-      (Keycode::W,   InputEvent::Game(Up)),
-      (Keycode::S,   InputEvent::Game(Down)),
-      (Keycode::A,   InputEvent::Game(Left)),
-      (Keycode::D,   InputEvent::Game(Right)),
-      (Keycode::I,      InputEvent::Game(Select)),
-      (Keycode::O,      InputEvent::Game(Start)),
-      (Keycode::Space,  InputEvent::Pause),
-      (Keycode::R,      InputEvent::Reset),
-      (Keycode::M,      InputEvent::Mute),
-      (Keycode::NUM_9,   InputEvent::Save),
-      (Keycode::NUM_0,   InputEvent::Load),
+    // hotkeys that throw away the progress made, resets and loads, need Shift so they can't be hit by accident
+    // next to the game keys. Saving is left plain, the state it replaces is kept as a .bak
+    let mut default_keymap = HashMap::from([
+      (Chord::plain(Keycode::K),   InputEvent::Game(A)),
+      (Chord::plain(Keycode::L),   InputEvent::Game(B)),
+      (Chord::plain(Keycode::W),   InputEvent::Game(Up)),
+      (Chord::plain(Keycode::S),   InputEvent::Game(Down)),
+      (Chord::plain(Keycode::A),   InputEvent::Game(Left)),
+      (Chord::plain(Keycode::D),   InputEvent::Game(Right)),
+      (Chord::plain(Keycode::I),      InputEvent::Game(Select)),
+      (Chord::plain(Keycode::O),      InputEvent::Game(Start)),
+      (Chord::plain(Keycode::J),         InputEvent::TurboA),
+      (Chord::plain(Keycode::SEMICOLON), InputEvent::TurboB),
+      (Chord::plain(Keycode::Space),  InputEvent::Pause),
+      (Chord::shift(Keycode::R),      InputEvent::Reset),
+      (Chord::plain(Keycode::M),      InputEvent::Mute),
+      (Chord::plain(Keycode::NUM_9),   InputEvent::Save),
+      (Chord::shift(Keycode::NUM_0),   InputEvent::Load),
+      (Chord::plain(Keycode::MINUS),   InputEvent::VolumeDown),
+      (Chord::plain(Keycode::EQUALS),  InputEvent::VolumeUp),
+      (Chord::plain(Keycode::F2),      InputEvent::ReloadRom),
+      (Chord::plain(Keycode::F3),      InputEvent::ToggleStats),
+      (Chord::plain(Keycode::P),       InputEvent::CyclePalette),
+      (Chord::plain(Keycode::F4),      InputEvent::CycleFilter),
+      (Chord::plain(Keycode::F5),      InputEvent::ToggleNtsc),
+      (Chord::plain(Keycode::F6),      InputEvent::Rotate),
+      (Chord::plain(Keycode::F7),      InputEvent::ToggleCheats),
+      (Chord::plain(Keycode::F9),      InputEvent::ToggleVram),
+      (Chord::plain(Keycode::F10),     InputEvent::ToggleScope),
+      (Chord::plain(Keycode::F11),     InputEvent::ToggleFullscreen),
+      (Chord::plain(Keycode::F1),      InputEvent::ToggleInputDisplay),
+      (Chord::plain(Keycode::BACKSLASH), InputEvent::FrameStep),
+      (Chord::plain(Keycode::Tab),       InputEvent::FastForward),
+      (Chord::plain(Keycode::Backspace), InputEvent::Rewind),
+      (Chord::plain(Keycode::F12),       InputEvent::Screenshot),
+      (Chord::plain(Keycode::RIGHTBRACKET), InputEvent::NextSlot),
+      (Chord::plain(Keycode::LEFTBRACKET),  InputEvent::PrevSlot),
+
+      (Chord::ctrl(Keycode::O), InputEvent::OpenRom),
+      (Chord::ctrl(Keycode::B), InputEvent::Rebind),
+      (Chord::ctrl(Keycode::L), InputEvent::CycleLayout),
+      (Chord { ctrl: true, ..Chord::shift(Keycode::R) }, InputEvent::HardReset),
+
+      (Chord::alt(Keycode::NUM_1), InputEvent::Scale(1)),
+      (Chord::alt(Keycode::NUM_2), InputEvent::Scale(2)),
+      (Chord::alt(Keycode::NUM_3), InputEvent::Scale(3)),
+      (Chord::alt(Keycode::NUM_4), InputEvent::Scale(4)),
+      (Chord::alt(Keycode::MINUS),  InputEvent::LatencyDown),
+      (Chord::alt(Keycode::EQUALS), InputEvent::LatencyUp),
+      (Chord::alt(Keycode::RETURN), InputEvent::ToggleFullscreen),
+      (Chord::alt(Keycode::B),      InputEvent::CycleBackground),
+      (Chord::alt(Keycode::O),      InputEvent::ToggleOverscan),
+      // no MuteChannel, neither core can mute its channels yet
+    ]);
+    // Shift+number picks the slot, Ctrl+number saves straight to it.
+    // Shift+0 is Load, slot 0 is picked with [ and ] or from the picker
+    for (slot, key) in (0..SLOTS).zip(NUMBER_KEYS) {
+      if slot != 0 {
+        default_keymap.insert(Chord::shift(key), InputEvent::SelectSlot(slot));
+      }
+      default_keymap.insert(Chord::ctrl(key), InputEvent::SaveToSlot(slot));
+    }
+
+    // on the other side of the keyboard, none of these are bound above. No modifiers, holding one would turn
+    // player 1's keys into hotkeys
+    let default_keymap_p2 = HashMap::from([
+      (Keycode::Up,     Up),
+      (Keycode::Down,   Down),
+      (Keycode::Left,   Left),
+      (Keycode::Right,  Right),
+      (Keycode::COMMA,  A),
+      (Keycode::PERIOD, B),
+      (Keycode::SLASH,  Select),
+      (Keycode::RETURN, Start),
     ]);
 
-    let default_padmap = HashMap::from([
+    let default_buttons = HashMap::from([
       (Button::X,         InputEvent::Game(A)),
       (Button::A,         InputEvent::Game(B)),
       (Button::B,         InputEvent::Game(Start)),
       (Button::Y,         InputEvent::Game(Select)),
       (Button::Back,      InputEvent::Game(Select)),
       (Button::Start,     InputEvent::Game(Start)),
+      (Button::LeftShoulder,  InputEvent::TurboA),
+      (Button::RightShoulder, InputEvent::TurboB),
       (Button::DPadLeft,  InputEvent::Game(Left)),
       (Button::DPadRight, InputEvent::Game(Right)),
       (Button::DPadUp,    InputEvent::Game(Up)),
       (Button::DPadDown,  InputEvent::Game(Down)),
     ]);
 
-    Keymaps { keymap: default_keymap, padmap: default_padmap }
+    let default_axes = HashMap::from([
+      (AxisDir { axis: Axis::LeftX, positive: false }, Left),
+      (AxisDir { axis: Axis::LeftX, positive: true  }, Right),
+      (AxisDir { axis: Axis::LeftY, positive: false }, Up),
+      (AxisDir { axis: Axis::LeftY, positive: true  }, Down),
+      (AxisDir { axis: Axis::RightX, positive: false }, Left),
+      (AxisDir { axis: Axis::RightX, positive: true  }, Right),
+      (AxisDir { axis: Axis::RightY, positive: false }, Up),
+      (AxisDir { axis: Axis::RightY, positive: true  }, Down),
+    ]);
+
+    let default_padmap = Padmap { buttons: default_buttons, axes: default_axes, dead_zones: HashMap::new(), triggers: HashMap::new() };
+
+    // most generic pads number their face buttons first, and Select and Start after the shoulders
+    let default_joymap = Joymap {
+      buttons: HashMap::from([
+        (0, InputEvent::Game(A)),
+        (1, InputEvent::Game(B)),
+        (2, InputEvent::TurboA),
+        (3, InputEvent::TurboB),
+        (8, InputEvent::Game(Select)),
+        (9, InputEvent::Game(Start)),
+      ]),
+      axes: HashMap::from([
+        ((0, false), Left),
+        ((0, true), Right),
+        ((1, false), Up),
+        ((1, true), Down),
+      ]),
+    };
+
+    Layout {
+      keymap: default_keymap,
+      keymap_p2: default_keymap_p2,
+      padmaps: HashMap::from([(DEFAULT_PAD_PROFILE.to_string(), default_padmap)]),
+      joymap: default_joymap,
+      // the NES and the Game Boy share the bindings above
+      core_maps: HashMap::new(),
+    }
+  }
+}
+
+fn parse_bindings<K: Eq + std::hash::Hash, V: Copy>(
+  bindings: &HashMap<String, V>,
+  parse_key: impl Fn(&str) -> Option<K>
+) -> HashMap<K, V> {
+  bindings.iter()
+    .filter_map(|(name, input)| match parse_key(name) {
+      Some(key) => Some((key, *input)),
+      None => { warn!("Unknown binding '{name}' in config, skipping"); None }
+    })
+    .collect()
+}
+
+impl Layout {
+  /// Builds a layout from the config bindings, taking what has no valid binding from `fallback`.
+  fn from_config(cfg: &LayoutConfig, fallback: &Layout) -> Self {
+    let mut keymap = parse_bindings(&cfg.keyboard, Chord::from_name);
+    // the bindings of the configs from before chords, one table per modifier
+    let legacy = [(&cfg.keyboard_ctrl, Chord::ctrl as fn(Keycode) -> Chord), (&cfg.keyboard_alt, Chord::alt), (&cfg.keyboard_shift, Chord::shift)];
+    for (bindings, chord) in legacy {
+      keymap.extend(parse_bindings(bindings, |name| Keycode::from_name(name).map(chord)));
+    }
+
+    // configs from before a hotkey was added still get its binding, unless they bind it elsewhere or its chord is taken
+    let missing: Vec<_> = fallback.keymap.iter()
+      .filter(|(chord, input)| !keymap.contains_key(chord) && !keymap.values().any(|bound| bound == *input))
+      .map(|(chord, input)| (*chord, *input))
+      .collect();
+    keymap.extend(missing);
+    let keymap_p2 = parse_bindings(&cfg.keyboard_player2, Keycode::from_name);
+    let keymap_p2 = if keymap_p2.is_empty() { fallback.keymap_p2.clone() } else { keymap_p2 };
+    for key in keymap_p2.keys().filter(|key| keymap.contains_key(&Chord::plain(**key))) {
+      warn!("{} is bound for both players, player 2 gets it while playing on the keyboard", key.name());
+    }
+    for key in keymap_p2.keys().filter(|key| is_modifier(**key)) {
+      warn!("{} is bound for player 2, holding it changes player 1's keys too", key.name());
+    }
+
+    let mut padmaps = fallback.padmaps.clone();
+    for (profile, pad) in &cfg.controller {
+      let buttons = parse_bindings(&pad.buttons, Button::from_string);
+      let axes = parse_bindings(&pad.axes, AxisDir::from_name);
+      let dead_zones = parse_bindings(&pad.dead_zones, Axis::from_string).into_iter()
+        .map(|(axis, dead_zone)| (axis, dead_zone.clamp(MIN_DEAD_ZONE, MAX_DEAD_ZONE)))
+        .collect();
+      let triggers: HashMap<_, _> = parse_bindings(&pad.triggers, Axis::from_string).into_iter()
+        .filter(|(axis, _)| {
+          let is_trigger = matches!(axis, Axis::TriggerLeft | Axis::TriggerRight);
+          if !is_trigger { warn!("{} isn't a trigger, skipping its binding", axis.string()); }
+          is_trigger
+        })
+        .collect();
+      if !buttons.is_empty() || !axes.is_empty() || !triggers.is_empty() {
+        padmaps.insert(profile.clone(), Padmap { buttons, axes, dead_zones, triggers });
+      } else if !dead_zones.is_empty() {
+        // only the dead zones are tuned, the bindings stay what they were
+        let base = padmaps.get(profile).unwrap_or(&padmaps[DEFAULT_PAD_PROFILE]).clone();
+        padmaps.insert(profile.clone(), Padmap { dead_zones, ..base });
+      }
+    }
+
+    let joy_buttons = parse_bindings(&cfg.joystick.buttons, |name| name.parse().ok());
+    let joy_axes = parse_bindings(&cfg.joystick.axes, joy_axis_from_name);
+    let joymap = match joy_buttons.is_empty() && joy_axes.is_empty() {
+      true => fallback.joymap.clone(),
+      false => Joymap { buttons: joy_buttons, axes: joy_axes },
+    };
+
+    let mut core_maps = fallback.core_maps.clone();
+    for (name, bindings) in &cfg.cores {
+      let Some(core) = CoreId::from_dir_name(name) else {
+        warn!("Unknown core '{name}' in config, skipping its bindings");
+        continue;
+      };
+      let keymap = parse_bindings(&bindings.keyboard, Chord::from_name);
+      let buttons = parse_bindings(&bindings.buttons, Button::from_string);
+      core_maps.insert(core, CoreMap { keymap, buttons });
+    }
+
+    Layout {
+      keymap,
+      keymap_p2,
+      padmaps,
+      joymap,
+      core_maps,
+    }
+  }
+
+  fn to_config(&self) -> LayoutConfig {
+    LayoutConfig {
+      keyboard: self.keymap.iter().map(|(k, v)| (k.name(), *v)).collect(),
+      keyboard_ctrl: HashMap::new(),
+      keyboard_alt: HashMap::new(),
+      keyboard_shift: HashMap::new(),
+      keyboard_player2: self.keymap_p2.iter().map(|(k, v)| (k.name(), *v)).collect(),
+      controller: self.padmaps.iter().map(|(profile, pad)| {
+        let pad = PadConfig {
+          buttons: pad.buttons.iter().map(|(b, v)| (b.string(), *v)).collect(),
+          axes: pad.axes.iter().map(|(a, v)| (a.name(), *v)).collect(),
+          dead_zones: pad.dead_zones.iter().map(|(a, v)| (a.string(), *v)).collect(),
+          triggers: pad.triggers.iter().map(|(a, v)| (a.string(), *v)).collect(),
+        };
+        (profile.clone(), pad)
+      }).collect(),
+      joystick: JoyConfig {
+        buttons: self.joymap.buttons.iter().map(|(b, v)| (b.to_string(), *v)).collect(),
+        axes: self.joymap.axes.iter().map(|((axis, positive), v)| (format!("{axis}{}", if *positive { '+' } else { '-' }), *v)).collect(),
+      },
+      cores: self.core_maps.iter().map(|(core, map)| {
+        let bindings = CoreBindingsConfig {
+          keyboard: map.keymap.iter().map(|(k, v)| (k.name(), *v)).collect(),
+          buttons: map.buttons.iter().map(|(b, v)| (b.string(), *v)).collect(),
+        };
+        (core.dir_name().to_string(), bindings)
+      }).collect(),
+    }
+  }
+}
+
+pub struct Keymaps {
+  // by name, the default one first
+  layouts: Vec<(String, Layout)>,
+  active: usize,
+  // the one cycled to last, for the games that don't pin a layout
+  chosen: usize,
+  // ROM CRC32 -> name of the layout that game starts with
+  game_layouts: HashMap<String, String>,
+  // of the loaded game, picks the game inputs when it has its own
+  core: Option<CoreId>,
+  // connected controllers instance ids, with their names
+  pad_names: HashMap<u32, String>,
+  // instance ids of the connected joysticks that aren't controllers, the only ones whose joystick events count
+  joysticks: HashSet<u32>,
+  // game inputs currently held by each controller, with the sources holding them, released if it gets unplugged
+  pad_held: HashMap<u32, HashMap<GameInput, HashSet<PadSource>>>,
+  // what each key pressed, so its release lets go of that even if it was rebound or the modifiers changed meanwhile
+  key_held: HashMap<Keycode, InputEvent>,
+  // same for the keys player 2 holds
+  p2_key_held: HashMap<Keycode, GameInput>,
+  keyboard_player2: KeyboardPlayer2,
+  // only kept for the config, the emulation thread applies it
+  block_opposing: bool,
+  // last position of every axis of each controller, a stick's directions depend on both of its axes
+  axis_values: HashMap<u32, HashMap<Axis, i16>>,
+  // triggers pulled past the press threshold, with what they pressed
+  trigger_held: HashMap<(u32, Axis), InputEvent>,
+  // thresholds of the triggers, the gap between them keeps noise from pressing and releasing over and over
+  trigger_press: f32,
+  trigger_release: f32,
+  // the controller buttons that open the pause menu when held together
+  combo: MenuCombo,
+}
+impl Default for Keymaps {
+  fn default() -> Self {
+    Keymaps {
+      layouts: vec![(DEFAULT_LAYOUT.to_string(), Layout::default())],
+      active: 0,
+      chosen: 0,
+      game_layouts: HashMap::new(),
+      core: None,
+      pad_names: HashMap::new(),
+      joysticks: HashSet::new(),
+      pad_held: HashMap::new(),
+      key_held: HashMap::new(),
+      p2_key_held: HashMap::new(),
+      keyboard_player2: KeyboardPlayer2::Auto,
+      block_opposing: true,
+      axis_values: HashMap::new(),
+      trigger_held: HashMap::new(),
+      trigger_press: 0.6,
+      trigger_release: 0.4,
+      combo: MenuCombo::new(vec![GameInput::Start, GameInput::Select], Duration::from_secs(1)),
+    }
+  }
+}
+
+impl Keymaps {
+  /// Builds the keymaps from the config bindings, with the defaults for what they leave unbound.
+  pub fn from_config(cfg: &InputConfig) -> Self {
+    let default = Layout::from_config(&cfg.bindings, &Layout::default());
+    let mut layouts = vec![(DEFAULT_LAYOUT.to_string(), default)];
+    for (name, bindings) in &cfg.layouts {
+      if name == DEFAULT_LAYOUT {
+        warn!("The {DEFAULT_LAYOUT} layout is set by the [input] bindings, skipping [input.layouts.{name}]");
+        continue;
+      }
+      let layout = Layout::from_config(bindings, &layouts[0].1);
+      layouts.push((name.clone(), layout));
+    }
+
+    let chosen = layouts.iter().position(|(name, _)| *name == cfg.layout).unwrap_or_else(|| {
+      if !cfg.layout.is_empty() {
+        warn!("Unknown layout '{}' in config, using the default one", cfg.layout);
+      }
+      0
+    });
+
+    Keymaps {
+      layouts,
+      active: chosen,
+      chosen,
+      game_layouts: cfg.game_layouts.clone(),
+      core: None,
+      pad_names: HashMap::new(),
+      joysticks: HashSet::new(),
+      pad_held: HashMap::new(),
+      key_held: HashMap::new(),
+      p2_key_held: HashMap::new(),
+      keyboard_player2: cfg.keyboard_player2,
+      block_opposing: cfg.block_opposing,
+      axis_values: HashMap::new(),
+      trigger_held: HashMap::new(),
+      trigger_press: cfg.trigger_press.clamp(0.0, 1.0),
+      trigger_release: cfg.trigger_release.clamp(0.0, cfg.trigger_press.clamp(0.0, 1.0)),
+      combo: MenuCombo::new(cfg.menu_combo.clone(), Duration::from_millis(cfg.menu_combo_ms)),
+    }
+  }
+
+  pub fn to_config(&self) -> InputConfig {
+    InputConfig {
+      layout: self.layouts[self.chosen].0.clone(),
+      game_layouts: self.game_layouts.clone(),
+      bindings: self.layouts[0].1.to_config(),
+      layouts: self.layouts[1..].iter().map(|(name, layout)| (name.clone(), layout.to_config())).collect(),
+      trigger_press: self.trigger_press,
+      trigger_release: self.trigger_release,
+      menu_combo: self.combo.buttons().to_vec(),
+      menu_combo_ms: self.combo.hold().as_millis() as u64,
+      keyboard_player2: self.keyboard_player2,
+      block_opposing: self.block_opposing,
+    }
+  }
+
+  fn layout(&self) -> &Layout {
+    &self.layouts[self.active].1
+  }
+
+  pub fn layout_name(&self) -> &str {
+    &self.layouts[self.active].0
+  }
+
+  /// Picks the layout of a game that was just loaded: the one it pins, or else the last one cycled to.
+  /// The game inputs are its core's own, when it has some.
+  pub fn game_loaded(&mut self, crc32: u32, core: CoreId) {
+    self.core = Some(core);
+    let pinned = self.game_layouts.get(&format!("{crc32:08x}"));
+    let found = pinned.and_then(|pinned| self.layouts.iter().position(|(name, _)| name == pinned));
+    if let (Some(pinned), None) = (pinned, found) {
+      warn!("Unknown layout '{pinned}' pinned to this game, ignoring it");
+    }
+    self.active = found.unwrap_or(self.chosen);
+  }
+
+  /// Switches to the next layout, which is kept for the games that don't pin one.
+  pub fn cycle_layout(&mut self) {
+    self.active = (self.active + 1) % self.layouts.len();
+    self.chosen = self.active;
+  }
+
+  pub fn controller_added(&mut self, which: u32, name: String) {
+    self.pad_names.insert(which, name);
+  }
+
+  pub fn joystick_added(&mut self, which: u32) {
+    self.joysticks.insert(which);
+  }
+
+  // controllers send joystick events too, those are already handled as controller ones
+  pub fn is_joystick(&self, which: u32) -> bool {
+    self.joysticks.contains(&which)
+  }
+
+  /// Forgets a disconnected controller, returning the game inputs it was still holding.
+  pub fn controller_removed(&mut self, which: u32) -> HashSet<GameInput> {
+    self.pad_names.remove(&which);
+    self.joysticks.remove(&which);
+    self.axis_values.remove(&which);
+    self.trigger_held.retain(|(held_by, _), _| *held_by != which);
+    self.combo.controller_removed(which);
+    self.pad_held.remove(&which).unwrap_or_default().into_keys().collect()
+  }
+
+
+  /// Tracks which sources of a controller hold each game input, returning whether the game should hear about it.
+  /// Only the first press and the last release count, so a drifting stick can't let go of a held d-pad.
+  fn pad_input(&mut self, which: u32, source: PadSource, input: GameInput, kind: &InputKind) -> bool {
+    let held = self.pad_held.entry(which).or_default();
+    match kind {
+      InputKind::Press => {
+        let sources = held.entry(input).or_default();
+        sources.insert(source) && sources.len() == 1
+      }
+      InputKind::Release => {
+        let Some(sources) = held.get_mut(&input) else { return false };
+        if !sources.remove(&source) || !sources.is_empty() { return false; }
+        held.remove(&input);
+        true
+      }
+    }
+  }
+
+  // restates the directions, returning the changes the game should hear of as pad_input filters out the rest.
+  // Releases go first, so moving from one direction to another never has the game see both held
+  fn restate_directions(&mut self, which: u32, mut states: Vec<(PadSource, GameInput, bool)>) -> Vec<(GameInput, InputKind)> {
+    states.sort_by_key(|(_, _, held)| *held);
+    states.into_iter().filter_map(|(source, input, held)| {
+      let kind = if held { InputKind::Press } else { InputKind::Release };
+      self.pad_input(which, source, input, &kind).then_some((input, kind))
+    }).collect()
+  }
+
+  // the game inputs `source` is among the holders of, whatever it's bound to now
+  fn pad_held_by(&self, which: u32, source: PadSource) -> Vec<GameInput> {
+    self.pad_held.get(&which).map_or(Vec::new(), |held| {
+      held.iter().filter(|(_, sources)| sources.contains(&source)).map(|(input, _)| *input).collect()
+    })
+  }
+
+  fn core_map(&self) -> Option<&CoreMap> {
+    self.core.and_then(|core| self.layout().core_maps.get(&core))
+  }
+
+  /// What a key chord is bound to. A chord falls back to its key alone only for game inputs,
+  /// so holding a modifier doesn't stop the game, but never fires the hotkey of the bare key.
+  pub fn key_binding(&self, chord: Chord) -> Option<InputEvent> {
+    let keymap = &self.layout().keymap;
+    let hotkey = keymap.get(&chord).copied();
+    let game_input = match self.core_map() {
+      Some(core_map) => core_map.keymap.get(&chord).or_else(|| core_map.keymap.get(&chord.unmodified())).copied().map(InputEvent::Game),
+      None => keymap.get(&chord).or_else(|| keymap.get(&chord.unmodified())).copied().filter(|input| matches!(input, InputEvent::Game(_))),
+    };
+    hotkey.filter(|input| !matches!(input, InputEvent::Game(_))).or(game_input)
+  }
+
+  // what a controller button is bound to, with the loaded core's game inputs
+  fn button_binding(&self, which: u32, button: Button) -> Option<InputEvent> {
+    let binding = self.padmap(which).buttons.get(&button).copied();
+    match self.core_map() {
+      Some(core_map) => binding.filter(|input| !matches!(input, InputEvent::Game(_)))
+        .or(core_map.buttons.get(&button).copied().map(InputEvent::Game)),
+      None => binding,
+    }
+  }
+
+  /// Whether the keyboard has a second player, who then gets the keys of the second bindings.
+  pub fn keyboard_player2(&self) -> bool {
+    match self.keyboard_player2 {
+      KeyboardPlayer2::Off => false,
+      KeyboardPlayer2::On => true,
+      KeyboardPlayer2::Auto => !self.pad_names.is_empty() || !self.joysticks.is_empty(),
+    }
+  }
+
+  // player 2's game input of a chord. Player 1's hotkeys come first, so e.g. Alt+Enter stays fullscreen
+  fn player2_binding(&self, chord: Chord) -> Option<GameInput> {
+    if !self.keyboard_player2() { return None; }
+    if self.key_binding(chord).is_some_and(|input| !matches!(input, InputEvent::Game(_))) { return None; }
+    self.layout().keymap_p2.get(&chord.key).copied()
+  }
+
+  // same as key_input, for player 2's keys. A release lets go of what the key pressed even if the player left since
+  fn player2_key_input(&mut self, keycode: Keycode, input: Option<GameInput>, kind: &InputKind) -> Option<GameInput> {
+    match kind {
+      InputKind::Press => {
+        if let Some(input) = input {
+          self.p2_key_held.insert(keycode, input);
+        }
+        input
+      }
+      InputKind::Release => self.p2_key_held.remove(&keycode),
+    }
+  }
+
+  /// Tracks what a key pressed. On release, that's what is let go of,
+  /// whatever the key is bound to now and whatever modifiers are held then.
+  fn key_input(&mut self, keycode: Keycode, input: Option<InputEvent>, kind: &InputKind) -> Option<InputEvent> {
+    match kind {
+      InputKind::Press => {
+        if let Some(input) = input {
+          self.key_held.insert(keycode, input);
+        }
+        input
+      }
+      InputKind::Release => self.key_held.remove(&keycode),
+    }
+  }
+
+  /// Forgets everything held, returning it so the game can let go of it too.
+  pub fn release_all(&mut self) -> HashSet<GameInput> {
+    let held = self.held_inputs();
+    self.key_held.clear();
+    self.p2_key_held.clear();
+    self.pad_held.clear();
+    self.trigger_held.clear();
+    self.combo.clear();
+    held
+  }
+
+  /// Every game input held down right now, by a key or a controller.
+  pub fn held_inputs(&self) -> HashSet<GameInput> {
+    let pads = self.pad_held.values().flat_map(|held| held.keys());
+    let keys = self.key_held.values().filter_map(|input| match input {
+      InputEvent::Game(game_input) => Some(game_input),
+      _ => None,
+    });
+    keys.chain(pads).copied().collect()
+  }
+
+  /// The game inputs player 2 holds down on the keyboard.
+  pub fn player2_held(&self) -> HashSet<GameInput> {
+    self.p2_key_held.values().copied().collect()
+  }
+
+  pub fn pad_name(&self, which: u32) -> Option<&String> {
+    self.pad_names.get(&which)
+  }
+
+  fn padmap(&self, which: u32) -> &Padmap {
+    let padmaps = &self.layout().padmaps;
+    self.pad_names.get(&which)
+      .and_then(|name| padmaps.get(name))
+      .unwrap_or(&padmaps[DEFAULT_PAD_PROFILE])
+  }
+
+  // Rebinding a controller gives it its own profile, starting from the default bindings
+  fn padmap_mut(&mut self, profile: &str) -> &mut Padmap {
+    let padmaps = &mut self.layouts[self.active].1.padmaps;
+    if !padmaps.contains_key(profile) {
+      let default = padmaps[DEFAULT_PAD_PROFILE].clone();
+      padmaps.insert(profile.to_string(), default);
+    }
+    padmaps.get_mut(profile).unwrap()
+  }
+
+  pub fn bind_key(&mut self, chord: Chord, input: GameInput) {
+    if let Some(core_map) = self.core.and_then(|core| self.layouts[self.active].1.core_maps.get_mut(&core)) {
+      core_map.keymap.retain(|_, v| *v != input);
+      core_map.keymap.insert(chord, input);
+      return;
+    }
+    let keymap = &mut self.layouts[self.active].1.keymap;
+    keymap.retain(|_, v| !matches!(v, InputEvent::Game(i) if *i == input));
+    keymap.insert(chord, InputEvent::Game(input));
+  }
+
+  pub fn bind_key_player2(&mut self, key: Keycode, input: GameInput) {
+    let keymap = &mut self.layouts[self.active].1.keymap_p2;
+    keymap.retain(|_, v| *v != input);
+    keymap.insert(key, input);
+  }
+
+  pub fn bind_button(&mut self, profile: &str, button: Button, input: GameInput) {
+    if let Some(core_map) = self.core.and_then(|core| self.layouts[self.active].1.core_maps.get_mut(&core)) {
+      core_map.buttons.retain(|_, v| *v != input);
+      core_map.buttons.insert(button, input);
+      return;
+    }
+    let pad = self.padmap_mut(profile);
+    pad.buttons.retain(|_, v| !matches!(v, InputEvent::Game(i) if *i == input));
+    pad.buttons.insert(button, InputEvent::Game(input));
+  }
+
+  pub fn bind_axis(&mut self, profile: &str, axis: AxisDir, input: GameInput) {
+    let pad = self.padmap_mut(profile);
+    pad.axes.retain(|_, v| *v != input);
+    pad.axes.insert(axis, input);
+  }
+
+  pub fn bind_joy_button(&mut self, button: u8, input: GameInput) {
+    let joymap = &mut self.layouts[self.active].1.joymap;
+    joymap.buttons.retain(|_, v| !matches!(v, InputEvent::Game(i) if *i == input));
+    joymap.buttons.insert(button, InputEvent::Game(input));
+  }
+
+  /// How far the axis of the controller must be pushed to count, out of 32767.
+  pub fn dead_zone(&self, which: u32, axis: Axis) -> i16 {
+    self.padmap(which).dead_zone(axis)
+  }
+
+  /// Changes the dead zone of an axis of a mapping profile, within the range that keeps the axis usable.
+  /// Returns the dead zone set.
+  pub fn set_dead_zone(&mut self, profile: &str, axis: Axis, dead_zone: i16) -> i16 {
+    let dead_zone = dead_zone.clamp(MIN_DEAD_ZONE, MAX_DEAD_ZONE);
+    self.padmap_mut(profile).dead_zones.insert(axis, dead_zone);
+    dead_zone
+  }
+
+  // remembers where an axis is, returning the axes whose directions may have changed with it
+  fn axis_moved(&mut self, which: u32, axis: Axis, value: i16) -> Vec<Axis> {
+    self.axis_values.entry(which).or_default().insert(axis, value);
+    std::iter::once(axis).chain(stick_partner(axis)).collect()
+  }
+
+  // whether the trigger is used as a button, by its binding or by what it still holds from before a rebind
+  fn is_trigger_bound(&self, which: u32, axis: Axis) -> bool {
+    self.padmap(which).triggers.contains_key(&axis) || self.trigger_held.contains_key(&(which, axis))
+  }
+
+  // what a trigger presses or releases as it moves. It presses past one threshold and only releases below
+  // the lower one, so resting near either doesn't send a stream of events
+  fn trigger_moved(&mut self, which: u32, axis: Axis, value: i16) -> Option<(InputEvent, InputKind)> {
+    let pulled = value.max(0) as f32 / i16::MAX as f32;
+    match self.trigger_held.get(&(which, axis)) {
+      Some(&input) if pulled < self.trigger_release => {
+        self.trigger_held.remove(&(which, axis));
+        Some((input, InputKind::Release))
+      }
+      None if pulled > self.trigger_press => {
+        let input = *self.padmap(which).triggers.get(&axis)?;
+        self.trigger_held.insert((which, axis), input);
+        Some((input, InputKind::Press))
+      }
+      _ => None,
+    }
+  }
+
+  // whether the axis holds its negative and positive directions now, with its dead zone and its stick's other axis
+  fn axis_held(&self, which: u32, axis: Axis) -> (bool, bool) {
+    let padmap = self.padmap(which);
+    let value = |axis| self.axis_values.get(&which).and_then(|values| values.get(&axis)).copied().unwrap_or(0);
+    let partner = stick_partner(axis).map(|partner| (value(partner), padmap.dead_zone(partner)));
+    axis_held((value(axis), padmap.dead_zone(axis)), partner)
   }
 }
 
 fn match_input(ctx: &mut EmuContext, input: Option<InputEvent>, kind: InputKind) {
   if input.is_none() { return; }
   let input = input.unwrap();
-  
-  let emu = &mut ctx.emu;
+
+  // with no game loaded the thread only holds a placeholder core, which must stay untouched
+  if !ctx.has_rom() && matches!(input,
+    InputEvent::Game(_) | InputEvent::TurboA | InputEvent::TurboB | InputEvent::Pause | InputEvent::Reset
+    | InputEvent::HardReset | InputEvent::Save | InputEvent::SaveToSlot(_) | InputEvent::Load | InputEvent::FrameStep | InputEvent::ToggleCheats
+    | InputEvent::ToggleVram | InputEvent::FastForward | InputEvent::Rewind | InputEvent::Screenshot
+  ) {
+    return;
+  }
+
   let audio_dev = &ctx.audio_dev;
 
   match (&input, &kind) {
-    (InputEvent::Game(input), _) => emu.input_event(&input, kind),
+    (InputEvent::Game(dir @ (GameInput::Left | GameInput::Right)), InputKind::Press) if ctx.picker.is_some() => {
+      let step = if *dir == GameInput::Right { 1 } else { SLOTS - 1 };
+      ctx.slot = (ctx.slot + step) % SLOTS;
+      ctx.picker = Some(ctx.state_picker());
+    }
+    (InputEvent::Game(input), _) => ctx.emu.send(Command::Input(*input, kind)),
+    // anything that changes the core state on one side only would desync netplay
+    (InputEvent::TurboA | InputEvent::TurboB | InputEvent::Reset | InputEvent::HardReset | InputEvent::Load | InputEvent::ToggleCheats | InputEvent::FrameStep
+      | InputEvent::FastForward | InputEvent::Rewind, InputKind::Press) if ctx.netplay => {
+      ctx.osd.message("Not available during netplay");
+    }
+    (InputEvent::FastForward, _) => ctx.emu.send(Command::FastForward(matches!(kind, InputKind::Press))),
+    (InputEvent::Rewind, _) => ctx.emu.send(Command::Rewind(matches!(kind, InputKind::Press))),
+    (InputEvent::TurboA, _) => ctx.emu.send(Command::Turbo(GameInput::A, kind)),
+    (InputEvent::TurboB, _) => ctx.emu.send(Command::Turbo(GameInput::B, kind)),
     (InputEvent::Pause, InputKind::Press) => {
       ctx.is_paused = !ctx.is_paused;
+      ctx.emu.send(Command::Pause(ctx.is_paused));
+      ctx.pause_menu = ctx.is_paused.then(pause_menu);
     
       match audio_dev.status() {
         AudioStatus::Playing => audio_dev.pause(),
@@ -80,73 +941,761 @@ fn match_input(ctx: &mut EmuContext, input: Option<InputEvent>, kind: InputKind)
       }
     }
 
-    (InputEvent::Reset, InputKind::Press)  => {
-      emu.reset();
-      audio_dev.pause();
-      audio_dev.clear();
-      audio_dev.resume();
-      ctx.is_paused = false;
-    }
+    (InputEvent::HardReset, InputKind::Press) => ctx.hard_reset(),
+    (InputEvent::Reset, InputKind::Press) => ctx.reset(),
     (InputEvent::Mute, InputKind::Press) => {
       ctx.is_muted = !ctx.is_muted;
+      ctx.config.audio.muted = ctx.is_muted;
+      ctx.send_mute();
       match audio_dev.status() {
         AudioStatus::Playing => {
           audio_dev.pause();
           audio_dev.clear();
         },
-        _ => audio_dev.resume(),
+        _ if ctx.audio_available => {
+          ctx.prime_audio();
+          audio_dev.resume();
+        }
+        _ => {}
       }
     },
+    (InputEvent::VolumeUp | InputEvent::VolumeDown, InputKind::Press) => {
+      let step = if matches!(input, InputEvent::VolumeUp) { 1.0 } else { -1.0 };
+      // steps of 10%, rounded so repeated steps don't accumulate float error
+      ctx.volume = ((ctx.volume * 10.0).round() + step).clamp(0.0, 10.0) / 10.0;
+      ctx.config.audio.volume = ctx.volume;
+      ctx.osd.message(format!("Volume: {}%", (ctx.volume * 100.0).round()));
+    }
+    (InputEvent::LatencyUp | InputEvent::LatencyDown, InputKind::Press) => {
+      let step = if matches!(input, InputEvent::LatencyUp) { 10 } else { -10 };
+      let latency = (ctx.config.audio.latency_ms as i32 + step).clamp(MIN_LATENCY_MS, MAX_LATENCY_MS);
+      ctx.config.audio.latency_ms = latency as u32;
+      // rate control converges on the new target over the next few seconds
+      ctx.resampler.set_target_latency(Duration::from_millis(latency as u64));
+      ctx.osd.message(format!("Audio latency: {latency} ms"));
+    }
+    (InputEvent::ToggleStats, InputKind::Press) => ctx.stats.visible = !ctx.stats.visible,
+    (InputEvent::ToggleInputDisplay, InputKind::Press) => ctx.config.video.input_display = !ctx.config.video.input_display,
+    (InputEvent::ToggleOverscan, InputKind::Press) => {
+      ctx.config.video.hide_overscan = !ctx.config.video.hide_overscan;
+      ctx.emu.send(Command::HideOverscan(ctx.config.video.hide_overscan));
+      ctx.osd.message(if ctx.config.video.hide_overscan { "Overscan hidden" } else { "Overscan shown" });
+    }
+    (InputEvent::CyclePalette, InputKind::Press) => ctx.cycle_palette(),
+    (InputEvent::CycleBackground, InputKind::Press) => ctx.cycle_background(),
+    (InputEvent::CycleFilter, InputKind::Press) => {
+      ctx.filter.kind = ctx.filter.kind.next();
+      ctx.config.video.filter = ctx.filter.kind;
+      ctx.osd.message(format!("Filter: {}", ctx.filter.kind.name()));
+    }
+    (InputEvent::Rotate, InputKind::Press) => ctx.cycle_rotation(),
+    (InputEvent::ToggleVram, InputKind::Press) => ctx.set_vram(ctx.vram.is_none()),
+    (InputEvent::ToggleScope, InputKind::Press) => {
+      ctx.scope = match ctx.scope {
+        Some(_) => None,
+        None => Some(Scope::default()),
+      };
+      ctx.send_mute();
+    }
+    // pauses, the next presses step a frame each
+    (InputEvent::FrameStep, InputKind::Press) => {
+      ctx.is_paused = true;
+      audio_dev.pause();
+      ctx.emu.send(Command::Pause(true));
+      ctx.emu.send(Command::Step);
+    }
+    (InputEvent::ToggleCheats, InputKind::Press) => {
+      ctx.cheats_enabled = !ctx.cheats_enabled;
+      ctx.emu.send(Command::CheatsEnabled(ctx.cheats_enabled));
+      ctx.osd.message(if ctx.cheats_enabled { "Cheats on" } else { "Cheats off" });
+    }
+    (InputEvent::Scale(scale), InputKind::Press) => ctx.pending_scale = Some(*scale as f32),
+    (InputEvent::MuteChannel(channel), InputKind::Press) => ctx.toggle_channel((*channel as usize).saturating_sub(1)),
+    (InputEvent::ToggleFullscreen, InputKind::Press) => ctx.pending_fullscreen = !ctx.pending_fullscreen,
+    (InputEvent::ToggleNtsc, InputKind::Press) => {
+      ctx.filter.ntsc_enabled = !ctx.filter.ntsc_enabled;
+      ctx.config.video.ntsc = ctx.filter.ntsc_enabled;
+      ctx.osd.message(if ctx.filter.ntsc_enabled { "NTSC filter on (NES only)" } else { "NTSC filter off" });
+    }
+    (InputEvent::Save | InputEvent::SaveToSlot(_) | InputEvent::Load, InputKind::Press) if !ctx.caps.save_states => {
+      ctx.osd.message("This core can't save states");
+    }
     (InputEvent::Save, InputKind::Press) => {
       ctx.audio_dev.pause();
-      ctx.emu.save(&ctx.rom_path);
-      if !ctx.is_muted { ctx.audio_dev.resume(); }
+      ctx.save_slot();
+      if ctx.plays_audio() { ctx.audio_dev.resume(); }
+    }
+    (InputEvent::SaveToSlot(slot), InputKind::Press) if *slot < SLOTS => {
+      ctx.audio_dev.pause();
+      ctx.save_to_slot(*slot);
+      if ctx.plays_audio() { ctx.audio_dev.resume(); }
+    }
+    (InputEvent::NextSlot | InputEvent::PrevSlot, InputKind::Press) => {
+      let step = if matches!(input, InputEvent::NextSlot) { 1 } else { SLOTS - 1 };
+      ctx.slot = (ctx.slot + step) % SLOTS;
+      if ctx.picker.is_some() {
+        ctx.picker = Some(ctx.state_picker());
+      }
+      ctx.osd.message(format!("Slot {} selected", ctx.slot));
+    }
+    (InputEvent::Screenshot, InputKind::Press) => ctx.take_screenshot(),
+    (InputEvent::SelectSlot(slot), InputKind::Press) if *slot < SLOTS => {
+      ctx.slot = *slot;
+      if ctx.picker.is_some() {
+        ctx.picker = Some(ctx.state_picker());
+      }
+      ctx.osd.message(format!("Slot {slot} selected"));
+    }
+    // holding load shows the slot picker, releasing it loads the picked slot
+    (InputEvent::Load, InputKind::Press) if ctx.picker.is_none() => {
+      ctx.picker = Some(ctx.state_picker());
+    }
+    (InputEvent::Load, InputKind::Release) if ctx.picker.is_some() => {
+      ctx.picker = None;
+      ctx.audio_dev.pause();
+      ctx.load_slot();
+      if ctx.plays_audio() { ctx.audio_dev.resume(); }
     }
-    (InputEvent::Load, InputKind::Press) => {
+    // the page's picker doesn't block, the game keeps running until a ROM comes back
+    #[cfg(feature = "web")]
+    (InputEvent::OpenRom, InputKind::Press) => crate::web::pick_rom(&openable_extensions()),
+    #[cfg(not(feature = "web"))]
+    (InputEvent::OpenRom, InputKind::Press) => {
+      // the game is paused while the dialog is open
+      ctx.emu.send(Command::Pause(true));
       ctx.audio_dev.pause();
-      ctx.emu.load(&ctx.rom_path);
-      if !ctx.is_muted { ctx.audio_dev.resume(); }
+      let mut dialog = rfd::FileDialog::new().set_title("Open ROM");
+      if let Some(dir) = &ctx.config.last_dir {
+        dialog = dialog.set_directory(dir);
+      }
+      let picked = dialog
+        .add_filter("ROM", &openable_extensions())
+        .pick_file();
+      ctx.emu.send(Command::Pause(ctx.is_paused));
+      if ctx.plays_audio() && !ctx.is_paused { ctx.audio_dev.resume(); }
+
+      // cancelling leaves the current game untouched
+      if let Some(dir) = picked.as_ref().and_then(|p| p.parent()) {
+        ctx.config.last_dir = Some(dir.to_path_buf());
+      }
+      ctx.pending_rom = picked;
+    }
+    (InputEvent::Rebind, InputKind::Press) => rebind::start(ctx),
+    (InputEvent::ReloadRom, InputKind::Press) => ctx.reload_rom(),
+    (InputEvent::CycleLayout, InputKind::Press) => {
+      ctx.keys.cycle_layout();
+      ctx.osd.message(format!("Layout: {}", ctx.keys.layout_name()));
     }
     _ => {}
   }
 }
 
+pub fn controller_removed(ctx: &mut EmuContext, which: u32) {
+  for input in ctx.keys.controller_removed(which) {
+    ctx.emu.send(Command::Input(input, InputKind::Release));
+  }
+
+  if !ctx.is_paused {
+    ctx.is_paused = true;
+    ctx.emu.send(Command::Pause(true));
+    ctx.audio_dev.pause();
+    info!("Controller disconnected, game paused");
+  }
+}
+
+/// Translates presses into menu navigation: arrows/enter/escape, or the bound game inputs.
+pub fn menu_key(keys: &Keymaps, event: &Event) -> Option<MenuKey> {
+  let input = match event {
+    Event::KeyDown { keycode: Some(keycode), .. } => match *keycode {
+      Keycode::Up => return Some(MenuKey::Up),
+      Keycode::Down => return Some(MenuKey::Down),
+      Keycode::Return | Keycode::KpEnter => return Some(MenuKey::Confirm),
+      Keycode::Escape | Keycode::Backspace => return Some(MenuKey::Back),
+      _ => keys.layout().keymap.get(&Chord::plain(*keycode)).copied(),
+    },
+    Event::ControllerButtonDown { which, button, .. } => keys.button_binding(*which, *button),
+    Event::JoyButtonDown { which, button_idx, .. } if keys.is_joystick(*which) => keys.layout().joymap.buttons.get(button_idx).copied(),
+    _ => None,
+  };
+
+  match input? {
+    InputEvent::Game(GameInput::Up) => Some(MenuKey::Up),
+    InputEvent::Game(GameInput::Down) => Some(MenuKey::Down),
+    InputEvent::Game(GameInput::A | GameInput::Start) => Some(MenuKey::Confirm),
+    InputEvent::Game(GameInput::B) => Some(MenuKey::Back),
+    _ => None,
+  }
+}
+
+fn pause_menu() -> ListMenu {
+  let items = PAUSE_MENU.map(String::from).into_iter().chain(["Quit".to_string()]).collect();
+  ListMenu::new("Paused", items)
+}
+
+fn open_pause_menu(ctx: &mut EmuContext) {
+  if !ctx.has_rom() { return; }
+  match (&ctx.pause_menu, ctx.is_paused) {
+    (Some(_), _) => {}
+    (None, true) => ctx.pause_menu = Some(pause_menu()),
+    (None, false) => match_input(ctx, Some(InputEvent::Pause), InputKind::Press),
+  }
+}
+
+fn pause_menu_input(ctx: &mut EmuContext, event: &Event) {
+  let Some(menu) = &mut ctx.pause_menu else { return };
+  // the pause binding and the Guide button close it like they opened it
+  let closes = match event {
+    Event::KeyDown { keycode: Some(keycode), keymod, .. } => matches!(ctx.keys.key_binding(Chord::from_event(*keycode, *keymod)), Some(InputEvent::Pause)),
+    Event::ControllerButtonDown { button: Button::Guide, .. } => true,
+    _ => false,
+  };
+  let action = match closes {
+    true => Some(MenuAction::Cancel),
+    false => menu_key(&ctx.keys, event).and_then(|key| menu.input(key)),
+  };
+
+  let resume = |ctx: &mut EmuContext| if ctx.is_paused { match_input(ctx, Some(InputEvent::Pause), InputKind::Press) };
+  match action {
+    Some(MenuAction::Confirm(0)) | Some(MenuAction::Cancel) => resume(ctx),
+    Some(MenuAction::Confirm(1)) => {
+      match_input(ctx, Some(InputEvent::Save), InputKind::Press);
+      resume(ctx);
+    }
+    // the current slot, without going through the picker
+    Some(MenuAction::Confirm(2)) => {
+      match_input(ctx, Some(InputEvent::Load), InputKind::Press);
+      match_input(ctx, Some(InputEvent::Load), InputKind::Release);
+      resume(ctx);
+    }
+    Some(MenuAction::Confirm(3)) => {
+      ctx.pause_menu = None;
+      match_input(ctx, Some(InputEvent::Reset), InputKind::Press);
+    }
+    // the game stays paused behind the dialog, a new one starts running
+    Some(MenuAction::Confirm(4)) => {
+      ctx.pause_menu = None;
+      match_input(ctx, Some(InputEvent::OpenRom), InputKind::Press);
+    }
+    Some(MenuAction::Confirm(_)) => ctx.pending_quit = true,
+    None => {}
+  }
+}
+
+// returns whether the event was taken, anything else is handled as usual
+fn rom_browser_input(ctx: &mut EmuContext, event: &Event) -> bool {
+  let Some(browser) = &mut ctx.rom_browser else { return false };
+  if let Event::KeyDown { keycode: Some(Keycode::F5), .. } = event {
+    browser.rescan();
+    return true;
+  }
+  let Some(key) = menu_key(&ctx.keys, event) else { return false };
+
+  // there's nothing behind it to go back to
+  if let Some(MenuAction::Confirm(i)) = browser.menu.input(key) {
+    ctx.pending_rom = browser.path(i).cloned();
+  }
+  true
+}
+
+fn rom_chooser_input(ctx: &mut EmuContext, event: &Event) {
+  let Some(chooser) = &mut ctx.rom_chooser else { return };
+  let Some(key) = menu_key(&ctx.keys, event) else { return };
+
+  match chooser.menu.input(key) {
+    Some(MenuAction::Confirm(i)) => {
+      let chooser = ctx.rom_chooser.take().unwrap();
+      let entry = chooser.menu.items[i].clone();
+      // remembered, so the next load of this archive picks it straight away
+      ctx.config.archive_choices.insert(chooser.path.display().to_string(), entry);
+      ctx.pending_rom = Some(chooser.path);
+    }
+    Some(MenuAction::Cancel) => {
+      ctx.rom_chooser = None;
+      ctx.osd.message("Load cancelled");
+    }
+    None => {}
+  }
+}
+
 pub fn handle_input(ctx: &mut EmuContext, event: &Event) {
+  if ctx.rebind.is_some() {
+    rebind::handle_input(ctx, event);
+    return;
+  }
+
+  // presses drive the chooser, releases still reach the game so nothing stays held
+  if ctx.rom_chooser.is_some() && matches!(event,
+    Event::KeyDown { .. } | Event::ControllerButtonDown { .. } | Event::ControllerAxisMotion { .. } | Event::JoyButtonDown { .. }
+  ) {
+    rom_chooser_input(ctx, event);
+    return;
+  }
+
+  if rom_browser_input(ctx, event) { return; }
+
+  // same for the pause menu
+  if ctx.pause_menu.is_some() && matches!(event,
+    Event::KeyDown { .. } | Event::ControllerButtonDown { .. } | Event::ControllerAxisMotion { .. } | Event::JoyButtonDown { .. }
+  ) {
+    pause_menu_input(ctx, event);
+    return;
+  }
+
   match event {
-    Event::KeyDown { keycode, .. } => if let Some(keycode) = keycode {
-      let input = ctx.keys.keymap.get(keycode).map(|x| x.to_owned());
+    Event::KeyDown { keycode, keymod, .. } => if let Some(keycode) = keycode {
+      let chord = Chord::from_event(*keycode, *keymod);
+      if let Some(input) = ctx.keys.player2_binding(chord) {
+        let input = ctx.keys.player2_key_input(*keycode, Some(input), &InputKind::Press);
+        player2_input(ctx, input, InputKind::Press);
+        return;
+      }
+      let input = ctx.keys.key_binding(chord);
+      let input = ctx.keys.key_input(*keycode, input, &InputKind::Press);
       match_input(ctx, input, InputKind::Press);
     },
     Event::KeyUp { keycode, .. } => if let Some(keycode) = keycode {
-      let input = ctx.keys.keymap.get(keycode).map(|x| x.to_owned());
+      if let Some(input) = ctx.keys.player2_key_input(*keycode, None, &InputKind::Release) {
+        player2_input(ctx, Some(input), InputKind::Release);
+        return;
+      }
+      let input = ctx.keys.key_input(*keycode, None, &InputKind::Release);
       match_input(ctx, input, InputKind::Release);
     },
 
-    Event::ControllerButtonDown { button, .. } => {
-      let input = ctx.keys.padmap.get(button).map(|x| x.to_owned());
+    Event::ControllerButtonDown { which, button, .. } => {
+      let input = ctx.keys.button_binding(*which, *button);
+      // the menu must be reachable with only a controller, Guide is often taken by the OS so there's the combo too
+      if input.is_none() && *button == Button::Guide {
+        open_pause_menu(ctx);
+        return;
+      }
+      if let Some(InputEvent::Game(game_input)) = input {
+        if !ctx.keys.pad_input(*which, PadSource::Button(*button), game_input, &InputKind::Press) { return; }
+        if combo_input(ctx, *which, game_input, InputKind::Press) { return; }
+      }
       match_input(ctx, input, InputKind::Press);
     },
-    Event::ControllerButtonUp { button, .. } => {
-      let input = ctx.keys.padmap.get(button).map(|x| x.to_owned());
+    Event::ControllerButtonUp { which, button, .. } => {
+      let source = PadSource::Button(*button);
+      // what the press held, whatever the button is bound to now
+      let input = match ctx.keys.pad_held_by(*which, source).first() {
+        Some(&game_input) => {
+          if !ctx.keys.pad_input(*which, source, game_input, &InputKind::Release) { return; }
+          if combo_input(ctx, *which, game_input, InputKind::Release) { return; }
+          Some(InputEvent::Game(game_input))
+        }
+        None => ctx.keys.button_binding(*which, *button).filter(|input| !matches!(input, InputEvent::Game(_))),
+      };
       match_input(ctx, input, InputKind::Release);
     },
 
-    Event::ControllerAxisMotion { axis: Axis::LeftX, value, .. } => {
-        if *value > AXIS_DEAD_ZONE { ctx.emu.input_event(&GameInput::Right, InputKind::Press); }
-        else if *value < -AXIS_DEAD_ZONE { ctx.emu.input_event(&GameInput::Left, InputKind::Press); }
-        else {
-          ctx.emu.input_event(&GameInput::Left, InputKind::Release);
-          ctx.emu.input_event(&GameInput::Right, InputKind::Release);
-        }
+    Event::ControllerAxisMotion { which, axis, value, .. } if ctx.keys.is_trigger_bound(*which, *axis) => {
+      let Some((input, kind)) = ctx.keys.trigger_moved(*which, *axis, *value) else { return };
+      if let InputEvent::Game(game_input) = input {
+        if !ctx.keys.pad_input(*which, PadSource::Axis(*axis), game_input, &kind) { return; }
       }
-      Event::ControllerAxisMotion { axis: Axis::LeftY, value, .. } => {
-        if *value > AXIS_DEAD_ZONE { ctx.emu.input_event(&GameInput::Down, InputKind::Press); }
-        else if *value < -AXIS_DEAD_ZONE { ctx.emu.input_event(&GameInput::Up, InputKind::Press); }
-        else {
-          ctx.emu.input_event(&GameInput::Up, InputKind::Release);
-          ctx.emu.input_event(&GameInput::Down, InputKind::Release);
-        }
+      match_input(ctx, Some(input), kind);
+    }
+    Event::ControllerAxisMotion { which, axis, value, .. } => {
+      // moving one axis of a stick can change what the other one holds
+      let axes = ctx.keys.axis_moved(*which, *axis, *value);
+      let states = axes.into_iter().flat_map(|axis| axis_states(&ctx.keys, *which, axis)).collect();
+      restate_directions(ctx, *which, states);
+    }
+
+    Event::JoyButtonDown { which, button_idx, .. } if ctx.keys.is_joystick(*which) => {
+      let input = ctx.keys.layout().joymap.buttons.get(button_idx).copied();
+      if let Some(InputEvent::Game(game_input)) = input {
+        if !ctx.keys.pad_input(*which, PadSource::JoyButton(*button_idx), game_input, &InputKind::Press) { return; }
+        if combo_input(ctx, *which, game_input, InputKind::Press) { return; }
       }
+      match_input(ctx, input, InputKind::Press);
+    }
+    Event::JoyButtonUp { which, button_idx, .. } if ctx.keys.is_joystick(*which) => {
+      let source = PadSource::JoyButton(*button_idx);
+      let input = match ctx.keys.pad_held_by(*which, source).first() {
+        Some(&game_input) => {
+          if !ctx.keys.pad_input(*which, source, game_input, &InputKind::Release) { return; }
+          if combo_input(ctx, *which, game_input, InputKind::Release) { return; }
+          Some(InputEvent::Game(game_input))
+        }
+        None => ctx.keys.layout().joymap.buttons.get(button_idx).copied().filter(|input| !matches!(input, InputEvent::Game(_))),
+      };
+      match_input(ctx, input, InputKind::Release);
+    }
+    // joysticks don't say which axes make a stick, each one is checked on its own
+    Event::JoyAxisMotion { which, axis_idx, value, .. } if ctx.keys.is_joystick(*which) => {
+      let joymap = &ctx.keys.layout().joymap;
+      let negative = joymap.axes.get(&(*axis_idx, false)).copied();
+      let positive = joymap.axes.get(&(*axis_idx, true)).copied();
+      let held = [(negative, *value < -DEFAULT_DEAD_ZONE), (positive, *value > DEFAULT_DEAD_ZONE)];
+      let states = direction_states(&ctx.keys, *which, PadSource::JoyAxis(*axis_idx), held);
+      restate_directions(ctx, *which, states);
+    }
     _ => {}
   }
-}
\ No newline at end of file
+}
+
+fn player2_input(ctx: &mut EmuContext, input: Option<GameInput>, kind: InputKind) {
+  let Some(input) = input else { return };
+  // the placeholder core must stay untouched
+  if !ctx.has_rom() { return; }
+  ctx.emu.send(Command::PlayerInput(1, input, kind));
+}
+
+// both directions of an axis as it is now
+fn axis_states(keys: &Keymaps, which: u32, axis: Axis) -> Vec<(PadSource, GameInput, bool)> {
+  let padmap = keys.padmap(which);
+  let negative = padmap.axes.get(&AxisDir { axis, positive: false }).copied();
+  let positive = padmap.axes.get(&AxisDir { axis, positive: true }).copied();
+  let (negative_held, positive_held) = keys.axis_held(which, axis);
+  direction_states(keys, which, PadSource::Axis(axis), [(negative, negative_held), (positive, positive_held)])
+}
+
+// what the axis held before a rebind is let go of too
+fn direction_states(keys: &Keymaps, which: u32, source: PadSource, directions: [(Option<GameInput>, bool); 2]) -> Vec<(PadSource, GameInput, bool)> {
+  let bound = directions.iter().filter_map(|(input, held)| input.map(|input| (input, *held)));
+  let unbound = keys.pad_held_by(which, source).into_iter()
+    .filter(|input| directions.iter().all(|(bound, _)| *bound != Some(*input)))
+    .map(|input| (input, false));
+  bound.chain(unbound).map(|(input, held)| (source, input, held)).collect()
+}
+
+fn restate_directions(ctx: &mut EmuContext, which: u32, states: Vec<(PadSource, GameInput, bool)>) {
+  for (input, kind) in ctx.keys.restate_directions(which, states) {
+    ctx.emu.send(Command::Input(input, kind));
+  }
+}
+
+// hands a controller's game input to the pause menu combo, returning whether the combo took it
+fn combo_input(ctx: &mut EmuContext, which: u32, input: GameInput, kind: InputKind) -> bool {
+  let Some(events) = ctx.keys.combo.input(which, input, &kind, Instant::now()) else { return false };
+  combo_events(ctx, events);
+  true
+}
+
+fn combo_events(ctx: &mut EmuContext, events: Vec<ComboEvent>) {
+  for event in events {
+    match event {
+      ComboEvent::Input(input, kind) => match_input(ctx, Some(InputEvent::Game(input)), kind),
+      ComboEvent::OpenMenu => open_pause_menu(ctx),
+    }
+  }
+}
+
+/// Delivers what the pause menu combo held back for long enough, and opens the menu once it's held through.
+pub fn poll_menu_combo(ctx: &mut EmuContext) {
+  let events = ctx.keys.combo.poll(Instant::now());
+  combo_events(ctx, events);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::{panic::{self, AssertUnwindSafe}, sync::{mpsc, OnceLock}, thread};
+
+  use crate::{config::Config, emu::EmuInterface, storage, test_core::{TestCore, WIDTH}};
+
+  const PAD: u32 = 0;
+
+  // what the game hears from moving an axis, as (input, pressed)
+  fn move_axis(keys: &mut Keymaps, axis: Axis, value: i16) -> Vec<(GameInput, bool)> {
+    let axes = keys.axis_moved(PAD, axis, value);
+    let states = axes.into_iter().flat_map(|axis| axis_states(keys, PAD, axis)).collect();
+    keys.restate_directions(PAD, states).into_iter()
+      .map(|(input, kind)| (input, matches!(kind, InputKind::Press)))
+      .collect()
+  }
+
+  fn dpad(keys: &mut Keymaps, button: Button, input: GameInput, kind: InputKind) -> bool {
+    keys.pad_input(PAD, PadSource::Button(button), input, &kind)
+  }
+
+  #[test]
+  fn stick_drift_doesnt_release_the_dpad() {
+    let mut keys = Keymaps::default();
+    assert!(dpad(&mut keys, Button::DPadLeft, GameInput::Left, InputKind::Press));
+
+    // drifting inside the dead zone, then pushed the same way
+    assert_eq!(move_axis(&mut keys, Axis::LeftX, -2000), []);
+    assert_eq!(move_axis(&mut keys, Axis::LeftX, 0), []);
+    assert_eq!(move_axis(&mut keys, Axis::LeftX, i16::MIN), []);
+
+    // the game only hears the release once neither holds it
+    assert!(!dpad(&mut keys, Button::DPadLeft, GameInput::Left, InputKind::Release));
+    assert_eq!(move_axis(&mut keys, Axis::LeftX, 0), [(GameInput::Left, false)]);
+  }
+
+  #[test]
+  fn right_stick_is_a_source_of_its_own() {
+    let mut keys = Keymaps::default();
+    assert_eq!(move_axis(&mut keys, Axis::LeftX, i16::MAX), [(GameInput::Right, true)]);
+    assert_eq!(move_axis(&mut keys, Axis::RightX, i16::MAX), []);
+    assert_eq!(move_axis(&mut keys, Axis::LeftX, 0), []);
+    assert_eq!(move_axis(&mut keys, Axis::RightX, 0), [(GameInput::Right, false)]);
+  }
+
+  #[test]
+  fn flipping_the_stick_releases_before_pressing() {
+    let mut keys = Keymaps::default();
+    assert_eq!(move_axis(&mut keys, Axis::LeftX, i16::MIN), [(GameInput::Left, true)]);
+    // a noisy stick held out doesn't press again
+    assert_eq!(move_axis(&mut keys, Axis::LeftX, i16::MIN + 100), []);
+    assert_eq!(move_axis(&mut keys, Axis::LeftX, i16::MIN), []);
+    // straight to the other side, in one motion
+    assert_eq!(move_axis(&mut keys, Axis::LeftX, i16::MAX), [(GameInput::Left, false), (GameInput::Right, true)]);
+    assert_eq!(move_axis(&mut keys, Axis::LeftX, i16::MIN), [(GameInput::Right, false), (GameInput::Left, true)]);
+    assert_eq!(move_axis(&mut keys, Axis::LeftX, 0), [(GameInput::Left, false)]);
+    assert_eq!(move_axis(&mut keys, Axis::LeftX, 0), []);
+  }
+
+  #[test]
+  fn axis_held_by_distance_and_direction() {
+    // alone, by its own dead zone
+    assert_eq!(axis_held((-9000, 8000), None), (true, false));
+    assert_eq!(axis_held((9000, 8000), None), (false, true));
+    assert_eq!(axis_held((7000, 8000), None), (false, false));
+    // out of the dead zone together with its partner, though neither is alone
+    assert_eq!(axis_held((6000, 8000), Some((6000, 8000))), (false, true));
+    // mostly pushed along the partner, this axis stays centered
+    assert_eq!(axis_held((1000, 8000), Some((i16::MAX, 8000))), (false, false));
+  }
+
+  #[test]
+  fn load_needs_shift() {
+    let keys = Keymaps::default();
+    assert!(matches!(keys.key_binding(Chord::shift(Keycode::NUM_0)), Some(InputEvent::Load)));
+    assert!(keys.key_binding(Chord::plain(Keycode::NUM_0)).is_none());
+    assert!(matches!(keys.key_binding(Chord::shift(Keycode::NUM_1)), Some(InputEvent::SelectSlot(1))));
+    assert!(matches!(keys.key_binding(Chord::ctrl(Keycode::NUM_0)), Some(InputEvent::SaveToSlot(0))));
+  }
+
+  #[test]
+  fn help_lists_every_default_hotkey() {
+    // what --help calls each action, a new one doesn't build until it has a line
+    let described = |input: &InputEvent| match input {
+      InputEvent::Game(_) => "d-pad, A, B, Select, Start",
+      InputEvent::TurboA | InputEvent::TurboB => "turbo A, turbo B",
+      InputEvent::Pause => "pause",
+      InputEvent::FastForward => "fast-forward",
+      InputEvent::Rewind => "rewind",
+      InputEvent::FrameStep => "step a frame",
+      InputEvent::Save | InputEvent::Load => "save, load (the slot picker while held)",
+      InputEvent::PrevSlot | InputEvent::NextSlot => "previous, next slot",
+      InputEvent::SelectSlot(_) | InputEvent::SaveToSlot(_) => "select a slot, save to a slot",
+      InputEvent::Reset | InputEvent::HardReset => "reset, hard reset",
+      InputEvent::VolumeDown | InputEvent::VolumeUp => "volume down, up",
+      InputEvent::Mute => "mute",
+      InputEvent::LatencyDown | InputEvent::LatencyUp => "audio latency down, up",
+      InputEvent::ToggleInputDisplay => "input display",
+      InputEvent::ReloadRom => "reload the ROM",
+      InputEvent::ToggleStats => "stats",
+      InputEvent::CyclePalette | InputEvent::CycleBackground => "next palette, next background",
+      InputEvent::CycleFilter | InputEvent::ToggleNtsc => "next filter, NTSC",
+      InputEvent::Rotate => "rotate",
+      InputEvent::ToggleCheats => "cheats",
+      InputEvent::ToggleVram | InputEvent::ToggleScope => "VRAM viewer, audio scope",
+      InputEvent::ToggleFullscreen => "fullscreen",
+      InputEvent::Scale(_) => "window scale",
+      InputEvent::ToggleOverscan => "hide the overscan",
+      InputEvent::Screenshot => "screenshot",
+      InputEvent::OpenRom | InputEvent::Rebind | InputEvent::CycleLayout => "open a ROM, rebind, next layout",
+      InputEvent::MuteChannel(_) => "sound channel",
+    };
+    for input in Layout::default().keymap.values() {
+      assert!(crate::cli::HELP.lines().any(|line| line.ends_with(described(input))), "{input:?} isn't in the help");
+    }
+  }
+
+  #[test]
+  fn old_configs_get_the_new_hotkeys() {
+    // from before fast forward and screenshots, with Tab taken
+    let cfg: LayoutConfig = toml::from_str(r#"
+      [keyboard]
+      K = "A"
+      Tab = "Pause"
+      [keyboard_ctrl]
+      O = "OpenRom"
+    "#).unwrap();
+    let layout = Layout::from_config(&cfg, &Layout::default());
+    let binding = |name| layout.keymap.get(&Chord::from_name(name).unwrap()).copied();
+
+    assert_eq!(binding("K"), Some(InputEvent::Game(GameInput::A)));
+    assert_eq!(binding("Tab"), Some(InputEvent::Pause));
+    assert_eq!(binding("Ctrl+O"), Some(InputEvent::OpenRom));
+    assert_eq!(binding("F12"), Some(InputEvent::Screenshot));
+    assert_eq!(binding("L"), Some(InputEvent::Game(GameInput::B)));
+    // bound elsewhere by the config, and kept off its default chord
+    assert_eq!(binding("Space"), None);
+    assert!(!layout.keymap.values().any(|input| *input == InputEvent::FastForward));
+  }
+
+  #[test]
+  fn player2_keys_leave_player1_chords_alone() {
+    let keys = Keymaps::default();
+    let layout = keys.layout();
+    for &key in layout.keymap_p2.keys() {
+      // SDL reports a held modifier in the keymod of every key pressed meanwhile
+      assert!(!is_modifier(key), "{key:?} would change player 1's chords while held");
+      assert!(!layout.keymap.contains_key(&Chord::plain(key)), "{key:?} is bound for both players");
+    }
+
+    // player 1's R and 0 while player 2 holds Select stay plain keys
+    assert!(keys.key_binding(Chord::from_event(Keycode::R, Mod::NOMOD)).is_none());
+    assert!(keys.key_binding(Chord::from_event(Keycode::NUM_0, Mod::NOMOD)).is_none());
+  }
+
+  #[test]
+  fn held_inputs_are_replayed_after_a_state_load() {
+    let mut keys = Keymaps::default();
+    let mut emu = TestCore::new();
+    let state = emu.save_state().unwrap();
+
+    // held down by a key and a controller after the state was saved
+    keys.key_input(Keycode::K, Some(InputEvent::Game(GameInput::A)), &InputKind::Press);
+    assert!(dpad(&mut keys, Button::DPadLeft, GameInput::Left, InputKind::Press));
+    emu.input_event(&GameInput::A, InputKind::Press);
+    emu.input_event(&GameInput::Left, InputKind::Press);
+
+    emu.load_state(&state).unwrap();
+    assert_eq!(emu.held[0], 0);
+    for input in keys.held_inputs() {
+      emu.input_event(&input, InputKind::Press);
+    }
+    assert_eq!(emu.held[0], 1 << GameInput::A as u16 | 1 << GameInput::Left as u16);
+
+    // the key lets go of what it pressed, even when rebound meanwhile
+    let released = keys.key_input(Keycode::K, Some(InputEvent::Game(GameInput::B)), &InputKind::Release);
+    assert!(matches!(released, Some(InputEvent::Game(GameInput::A))));
+    assert_eq!(keys.held_inputs(), HashSet::from([GameInput::Left]));
+  }
+
+  type ContextTest = Box<dyn FnOnce(&mut EmuContext) + Send>;
+
+  // SDL can only ever be started from one thread, so every test context is made on that one
+  fn with_context(test: impl FnOnce(&mut EmuContext) + Send + 'static) {
+    static SDL_THREAD: OnceLock<mpsc::Sender<(ContextTest, mpsc::Sender<thread::Result<()>>)>> = OnceLock::new();
+    let sdl_thread = SDL_THREAD.get_or_init(|| {
+      let (tx, rx) = mpsc::channel::<(ContextTest, mpsc::Sender<thread::Result<()>>)>();
+      thread::spawn(move || {
+        let sdl = sdl2::init().unwrap();
+        let audio = sdl.audio().unwrap();
+        for (test, done) in rx {
+          let mut ctx = EmuContext::with_audio(&audio, 1.0, Config::default(), None);
+          let _ = done.send(panic::catch_unwind(AssertUnwindSafe(|| test(&mut ctx))));
+        }
+      });
+      tx
+    });
+    let (done, result) = mpsc::channel();
+    sdl_thread.send((Box::new(test), done)).unwrap();
+    if let Err(panic) = result.recv().unwrap() {
+      panic::resume_unwind(panic);
+    }
+  }
+
+  // a paused game, stepped by the test alone
+  fn load_test_core(ctx: &mut EmuContext, name: &str) {
+    ctx.emu.send(Command::Load(Box::new(TestCore::new()), 0));
+    ctx.emu.send(Command::Pause(true));
+    ctx.is_paused = true;
+    ctx.rom_path = std::env::temp_dir().join(format!("cmbemu-test-{}-{name}.nes", std::process::id()));
+    // the frames made before the pause got there
+    let _ = ctx.emu.sram();
+    ctx.emu.update_frame(Duration::ZERO);
+  }
+
+  // the frame count of the test core after one more shown frame, it writes it in the row padding
+  fn step(ctx: &mut EmuContext) -> u8 {
+    ctx.emu.send(Command::Step);
+    // answered once the thread is done with the step
+    let _ = ctx.emu.sram();
+    ctx.emu.update_frame(Duration::ZERO);
+    ctx.emu.frame().unwrap().buf[WIDTH * 4]
+  }
+
+  fn key(ctx: &mut EmuContext, keycode: Keycode, down: bool) {
+    let event = match down {
+      true => Event::KeyDown { timestamp: 0, window_id: 0, keycode: Some(keycode), scancode: None, keymod: Mod::NOMOD, repeat: false },
+      false => Event::KeyUp { timestamp: 0, window_id: 0, keycode: Some(keycode), scancode: None, keymod: Mod::NOMOD, repeat: false },
+    };
+    handle_input(ctx, &event);
+  }
+
+  fn tap(ctx: &mut EmuContext, keycode: Keycode) {
+    key(ctx, keycode, true);
+    key(ctx, keycode, false);
+  }
+
+  #[test]
+  fn fast_forward_steps_several_frames_while_held() {
+    with_context(|ctx| {
+      load_test_core(ctx, "fast-forward");
+      let start = step(ctx);
+      key(ctx, Keycode::Tab, true);
+      assert_eq!(step(ctx), start.wrapping_add(4));
+      key(ctx, Keycode::Tab, false);
+      assert_eq!(step(ctx), start.wrapping_add(5));
+    });
+  }
+
+  #[test]
+  fn rewind_goes_back_a_snapshot_per_frame_while_held() {
+    with_context(|ctx| {
+      load_test_core(ctx, "rewind");
+      for _ in 0..16 {
+        step(ctx);
+      }
+      key(ctx, Keycode::Backspace, true);
+      let rewound = step(ctx);
+      assert_eq!(step(ctx), rewound.wrapping_sub(4));
+      key(ctx, Keycode::Backspace, false);
+      assert_eq!(step(ctx), rewound.wrapping_sub(3));
+    });
+  }
+
+  #[test]
+  fn screenshot_writes_the_frame() {
+    with_context(|ctx| {
+      load_test_core(ctx, "screenshot");
+      step(ctx);
+      tap(ctx, Keycode::F12);
+
+      let dir = storage::data_dir().map_or(std::env::temp_dir(), |dir| dir.join("screenshots"));
+      let stem = ctx.rom_path.file_stem().unwrap().to_string_lossy().into_owned();
+      let shots: Vec<_> = std::fs::read_dir(&dir).unwrap()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.file_name().is_some_and(|name| name.to_string_lossy().starts_with(&stem)))
+        .collect();
+      let data = shots.first().map(std::fs::read);
+      for shot in &shots {
+        let _ = std::fs::remove_file(shot);
+      }
+      assert_eq!(shots.len(), 1);
+      assert!(data.unwrap().unwrap().starts_with(b"BM"));
+    });
+  }
+
+  #[test]
+  fn next_slot_steps_the_slot_up() {
+    with_context(|ctx| {
+      load_test_core(ctx, "next-slot");
+      tap(ctx, Keycode::RIGHTBRACKET);
+      assert_eq!(ctx.slot, 1);
+      ctx.slot = SLOTS - 1;
+      tap(ctx, Keycode::RIGHTBRACKET);
+      assert_eq!(ctx.slot, 0);
+    });
+  }
+
+  #[test]
+  fn prev_slot_steps_the_slot_down() {
+    with_context(|ctx| {
+      load_test_core(ctx, "prev-slot");
+      tap(ctx, Keycode::LEFTBRACKET);
+      assert_eq!(ctx.slot, SLOTS - 1);
+      tap(ctx, Keycode::LEFTBRACKET);
+      assert_eq!(ctx.slot, SLOTS - 2);
+    });
+  }
+}
@@ -0,0 +1,184 @@
+use sdl2::{pixels::Color, rect::Rect, render::{BlendMode, Canvas}, video::Window};
+
+use crate::{emu::Emulator, input::{GameInput, InputKind}, osd::{draw_text, in_window_coords, GLYPH_SIZE}};
+
+// side of a button of the diagram, in window pixels
+const CELL: i32 = 12;
+const MARGIN: i32 = 8;
+
+// where each button sits in the diagram, in cells, with its size
+const LAYOUT: [(GameInput, f32, f32, f32, f32); 8] = [
+  (GameInput::Up,     1.0, 0.0, 1.0, 1.0),
+  (GameInput::Left,   0.0, 1.0, 1.0, 1.0),
+  (GameInput::Right,  2.0, 1.0, 1.0, 1.0),
+  (GameInput::Down,   1.0, 2.0, 1.0, 1.0),
+  (GameInput::Select, 3.5, 1.25, 1.5, 0.5),
+  (GameInput::Start,  5.5, 1.25, 1.5, 0.5),
+  (GameInput::B,      7.5, 1.0, 1.0, 1.0),
+  (GameInput::A,      9.0, 1.0, 1.0, 1.0),
+];
+const DIAGRAM_SIZE: (i32, i32) = (10 * CELL, 3 * CELL);
+
+fn opposite(input: GameInput) -> Option<GameInput> {
+  match input {
+    GameInput::Up => Some(GameInput::Down),
+    GameInput::Down => Some(GameInput::Up),
+    GameInput::Left => Some(GameInput::Right),
+    GameInput::Right => Some(GameInput::Left),
+    _ => None,
+  }
+}
+
+/// The game inputs the core was given, per player, as it got them. Turbo pulses and netplay inputs included,
+/// unlike what the keys and controllers hold.
+#[derive(Clone, Copy, Default)]
+pub struct DeliveredInputs {
+  held: [u16; 2],
+  // what the frontend holds, which the core may not have been given all of
+  requested: [u16; 2],
+  // opposite directions are never held together, the last one pressed wins
+  block_opposing: bool,
+}
+
+impl DeliveredInputs {
+  pub fn new(block_opposing: bool) -> Self {
+    Self { block_opposing, ..Default::default() }
+  }
+
+  /// Gives the input to the core, keeping track of it. Every input the core gets goes through here.
+  /// A direction pressed while its opposite is held lets go of the opposite first, which is pressed
+  /// again if it's still held when the newer one is released.
+  pub fn send(&mut self, emu: &mut Emulator, player: usize, input: &GameInput, kind: InputKind) {
+    let (Some(requested), Some(opposite), true) = (self.requested.get_mut(player), opposite(*input), self.block_opposing) else {
+      self.send_exact(emu, player, input, kind);
+      return;
+    };
+
+    match kind {
+      InputKind::Press => {
+        *requested |= 1 << *input as u16;
+        if self.is_held(player, opposite) {
+          self.send_exact(emu, player, &opposite, InputKind::Release);
+        }
+        self.send_exact(emu, player, input, InputKind::Press);
+      }
+      InputKind::Release => {
+        *requested &= !(1 << *input as u16);
+        let still_held = *requested & (1 << opposite as u16) != 0;
+        // it was already let go of when its opposite was pressed
+        if self.is_held(player, *input) {
+          self.send_exact(emu, player, input, InputKind::Release);
+        }
+        if still_held && !self.is_held(player, opposite) {
+          self.send_exact(emu, player, &opposite, InputKind::Press);
+        }
+      }
+    }
+  }
+
+  /// Gives the input to the core as it is, for netplay: both sides must give theirs the same inputs, whatever their settings.
+  pub fn send_exact(&mut self, emu: &mut Emulator, player: usize, input: &GameInput, kind: InputKind) {
+    if let Some(held) = self.held.get_mut(player) {
+      match kind {
+        InputKind::Press => *held |= 1 << *input as u16,
+        InputKind::Release => *held &= !(1 << *input as u16),
+      }
+    }
+    emu.player_input_event(player, input, kind);
+  }
+
+  pub fn block_opposing(&self) -> bool { self.block_opposing }
+
+  pub fn is_held(&self, player: usize, input: GameInput) -> bool {
+    self.held.get(player).is_some_and(|held| held & (1 << input as u16) != 0)
+  }
+}
+
+/// Draws a controller per player in the bottom right corner, with the held buttons filled.
+/// It's only drawn over the window, the frames stay as the core made them.
+pub fn draw_input_display(canvas: &mut Canvas<Window>, inputs: &DeliveredInputs, players: usize) {
+  const SCALE: i32 = 1;
+  let label_width = 3 * GLYPH_SIZE * SCALE;
+
+  in_window_coords(canvas, |canvas| {
+    let (width, height) = canvas.output_size().unwrap_or_default();
+    let prev_color = canvas.draw_color();
+    let prev_blend = canvas.blend_mode();
+
+    for player in 0..players {
+      // player 1 at the bottom, the others stacked above
+      let x = width as i32 - MARGIN - DIAGRAM_SIZE.0;
+      let y = height as i32 - (MARGIN + DIAGRAM_SIZE.1) * (player as i32 + 1);
+
+      canvas.set_blend_mode(BlendMode::Blend);
+      canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+      let _ = canvas.fill_rect(Rect::new(x - label_width - 4, y - 4, (DIAGRAM_SIZE.0 + label_width + 8) as u32, (DIAGRAM_SIZE.1 + 8) as u32));
+      canvas.set_blend_mode(prev_blend);
+
+      if players > 1 {
+        draw_text(canvas, x - label_width, y + (DIAGRAM_SIZE.1 - GLYPH_SIZE) / 2, SCALE, &format!("P{}", player + 1), Color::WHITE);
+      }
+
+      canvas.set_draw_color(Color::WHITE);
+      for (input, left, top, w, h) in LAYOUT {
+        let cell = |v: f32| (v * CELL as f32).round() as i32;
+        // a gap between neighbours, so the d-pad reads as separate buttons
+        let rect = Rect::new(x + cell(left) + 1, y + cell(top) + 1, (cell(w) - 2) as u32, (cell(h) - 2) as u32);
+        let _ = match inputs.is_held(player, input) {
+          true => canvas.fill_rect(rect),
+          false => canvas.draw_rect(rect),
+        };
+      }
+    }
+    canvas.set_draw_color(prev_color);
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_core::TestCore;
+  use GameInput::*;
+
+  // sends the inputs as (input, pressed), returning what the core got the same way
+  fn deliver(inputs: &mut DeliveredInputs, sent: &[(GameInput, bool)]) -> Vec<(GameInput, bool)> {
+    let core = TestCore::new();
+    let events = core.events.clone();
+    let mut emu: Emulator = Box::new(core);
+    for (input, pressed) in sent {
+      let kind = if *pressed { InputKind::Press } else { InputKind::Release };
+      inputs.send(&mut emu, 0, input, kind);
+    }
+    let events = events.lock().unwrap();
+    events.iter().map(|(_, input, pressed)| (*input, *pressed)).collect()
+  }
+
+  #[test]
+  fn last_direction_pressed_wins() {
+    let mut inputs = DeliveredInputs::new(true);
+    let got = deliver(&mut inputs, &[(Left, true), (Right, true), (Up, true), (Down, true)]);
+    assert_eq!(got, [(Left, true), (Left, false), (Right, true), (Up, true), (Up, false), (Down, true)]);
+    assert!(!inputs.is_held(0, Left) && inputs.is_held(0, Right));
+    assert!(!inputs.is_held(0, Up) && inputs.is_held(0, Down));
+  }
+
+  #[test]
+  fn older_direction_comes_back_on_release() {
+    let mut inputs = DeliveredInputs::new(true);
+    deliver(&mut inputs, &[(Left, true), (Right, true), (Down, true), (Up, true)]);
+
+    let got = deliver(&mut inputs, &[(Right, false), (Up, false)]);
+    assert_eq!(got, [(Right, false), (Left, true), (Up, false), (Down, true)]);
+    // the older one, let go of while blocked, doesn't bring anything back
+    let got = deliver(&mut inputs, &[(Right, true), (Left, false), (Right, false)]);
+    assert_eq!(got, [(Left, false), (Right, true), (Right, false)]);
+    assert!(!inputs.is_held(0, Left) && !inputs.is_held(0, Right));
+  }
+
+  #[test]
+  fn both_directions_go_through_when_not_blocking() {
+    let mut inputs = DeliveredInputs::new(false);
+    let got = deliver(&mut inputs, &[(Left, true), (Right, true), (Right, false)]);
+    assert_eq!(got, [(Left, true), (Right, true), (Right, false)]);
+  }
+}
@@ -0,0 +1,35 @@
+use std::{fs, path::PathBuf};
+
+use env_logger::{Builder, Target};
+use log::LevelFilter;
+
+use crate::storage;
+
+fn log_path() -> Option<PathBuf> {
+  storage::data_dir().map(|dir| dir.join("cmbemu.log"))
+}
+
+/// Sets up the logger: info and above by default, RUST_LOG refines it, and `level` overrides both.
+pub fn init(level: Option<LevelFilter>, to_file: bool) {
+  let mut builder = Builder::new();
+  builder.filter_level(LevelFilter::Info).parse_default_env();
+  if let Some(level) = level {
+    builder.filter_level(level);
+  }
+
+  if to_file {
+    let file = log_path().ok_or("no data directory".to_string()).and_then(|path| {
+      if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+      }
+      fs::File::create(&path).map_err(|e| format!("{}: {e}", path.display()))
+    });
+
+    match file {
+      Ok(file) => { builder.target(Target::Pipe(Box::new(file))); }
+      Err(msg) => eprintln!("Couldn't open the log file, logging to stderr: {msg}\n"),
+    }
+  }
+
+  builder.init();
+}
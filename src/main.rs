@@ -1,173 +1,1329 @@
-use std::{error::Error, fs, io::Read, path::{Path, PathBuf}};
-use sdl2::{audio::AudioQueue, event::Event, pixels::PixelFormatEnum, render::{Canvas, Texture, TextureCreator}, video::{Window, WindowContext}, AudioSubsystem};
-use std::time::{Duration, Instant};
+use std::{error::Error, fs, path::{Path, PathBuf}};
+use sdl2::{audio::{AudioQueue, AudioSpecDesired, AudioStatus}, event::{Event, WindowEvent}, messagebox::{show_message_box, show_simple_message_box, ButtonData, ClickedButton, MessageBoxButtonFlag, MessageBoxFlag}, pixels::Color, rect::Rect, video::{FullscreenType, Window, WindowPos}, hint, AudioSubsystem, VideoSubsystem};
+use std::time::{Duration, Instant, SystemTime};
+use log::{error, info, warn};
 
 mod emu;
-use emu::Emulator;
+use emu::{shown_region, AudioSource, EmuCaps, Emulator};
+#[cfg(test)]
+mod test_core;
+
+mod cores;
+
+mod cli;
+use cli::Args;
+
+mod logging;
+
+mod verify;
+
+mod runahead;
+
+mod rewind;
+
+mod emu_thread;
+use emu_thread::{Command, EmuThread, Notice};
+
+mod netplay;
+use netplay::Netplay;
 
 mod sdl2ctx;
 use sdl2ctx::Sdl2Context;
 
 mod input;
-use input::{handle_input, Keymaps};
+use input::{controller_removed, handle_input, poll_menu_combo, InputKind, Keymaps};
+
+mod combo;
+
+mod config;
+use config::{Config, FullscreenMode, PacingMode};
+
+mod rebind;
+use rebind::Rebind;
+
+mod turbo;
+
+mod resampler;
+use resampler::Resampler;
+
+mod mixer;
+use mixer::ChannelMixer;
+
+mod pacing;
+
+mod osd;
+use osd::{draw_paused, Osd, Stats};
+
+mod input_display;
+use input_display::draw_input_display;
+
+mod hash;
+
+mod battery;
+use battery::Battery;
+
+mod ntsc;
+
+mod filter;
+use filter::Filter;
+
+mod palette;
+use palette::{gb_palette_index, BACKGROUNDS, GB_PALETTES};
+
+mod romdb;
+use romdb::{RomInfo, TitleDb};
+
+mod savestate;
+use savestate::{CoreId, StateHeader};
+
+mod slots;
+use slots::{StatePicker, Thumbnail};
+
+mod screenshot;
+
+
+mod vram;
+use vram::{DebugVram, VramViewer};
+
+mod cheats;
+use cheats::Cheats;
+
+mod scope;
+use scope::Scope;
+
+mod rom;
+use rom::{read_rom, MultipleRoms, RomChooser};
+
+mod menu;
+use menu::ListMenu;
+
+mod browser;
+use browser::RomBrowser;
+
+mod renderer;
+use renderer::ScalingMode;
+
+mod storage;
+
+#[cfg(feature = "web")]
+mod web;
 
 extern crate nen_emulator;
-use nen_emulator::{cart::is_nes_rom, Nes};
-
-extern crate tomboy_emulator;
-use tomboy_emulator::{cart::is_gb_rom, gb::Gameboy};
-
-fn open_rom(path: &Path) -> Result<Emulator, Box<dyn Error>> {
-	let mut bytes = Vec::new();
-	let file = fs::File::open(path)?;
-			
-	let _ = zip::read::ZipArchive::new(file)
-		.and_then(|mut archive|
-			// we only take the first file in the archive, might be done in a smarter way
-			archive.by_index(0)
-			.map(|mut f| f.read_to_end(&mut bytes))
-		).or_else(|_| 
-			fs::File::open(path).map(|mut f| f.read_to_end(&mut bytes))
-		)?;
+use nen_emulator::Nes;
 
-	
-	if is_nes_rom(&bytes) {
-		Nes::boot_from_bytes(&bytes)
-		.map(|x| Box::new(x) as Emulator)
-		.map_err(|msg| msg.into())
-	} else if is_gb_rom(&bytes) {
-		Gameboy::boot_from_bytes(&bytes)
-		.map(|x| Box::new(x) as Emulator)
-		.map_err(|msg| msg.into())
-	} else {
-		Err("No valid ROM".into())
-	}
+/// Boots the ROM at `path`, returning the emulator, what identifies the ROM and its bytes.
+fn open_rom(path: &Path, entry: Option<&str>, titles: &TitleDb) -> Result<(Emulator, RomInfo, Vec<u8>), Box<dyn Error>> {
+	let bytes = read_rom(path, entry)?;
+	let emu = cores::boot(&bytes, path)?;
+	let info = RomInfo::new(&bytes, path, titles, emu.game_title());
+	Ok((emu, info, bytes))
 }
 
+// the device runs at this whatever the core, the resampler converts to it
+const DEVICE_RATE: i32 = 48000;
+
+// what the device is asked for, the core's channels at the device rate
+fn device_spec(source: AudioSource) -> AudioSpecDesired {
+	AudioSpecDesired { freq: Some(DEVICE_RATE), channels: Some(source.channels), samples: None }
+}
+
+// from the spec the device was actually opened with, SDL formats keep the sample size in bits in the low byte
+fn queued_frames(audio_dev: &AudioQueue<f32>) -> usize {
+	let spec = audio_dev.spec();
+	let bytes_per_sample = (spec.format as u16 & 0xff) as usize / 8;
+	audio_dev.size() as usize / bytes_per_sample.max(1) / (spec.channels as usize).max(1)
+}
+
+// how long the main loop waits for a frame when no game is running
+const IDLE_FRAME: Duration = Duration::from_millis(16);
+// queued audio past this many times the target latency is a backlog from a stall, and is dropped
+const MAX_AUDIO_BACKLOG: usize = 4;
+// how often --watch looks at the ROM file
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+// paused for longer than this, the screensaver is allowed again
+const SCREENSAVER_PAUSE: Duration = Duration::from_secs(60);
+
 struct EmuContext {
-	emu: Emulator,
+	emu: EmuThread,
+	// of the loaded game, kept here so the main thread doesn't have to ask
+	core: CoreId,
+	resolution: (usize, usize),
+	visible: (usize, usize, usize, usize),
+	fps: f32,
 	is_paused: bool,
 	is_muted: bool,
+	// the core sent samples with its last frame, the audio is kept off while it doesn't
+	audio_available: bool,
+	// of the loaded core
+	caps: EmuCaps,
+	// of the loaded core's sound channels, with whether each is heard
+	channels: Vec<(&'static str, bool)>,
+	volume: f32,
 	ms_frame: Duration,
+	// present() paces the loop instead of waiting for the emulation thread's frames
+	vsync: bool,
+	// presents per emulated frame under vsync, 2 for a 60 fps game on a 120 Hz display
+	vsync_interval: u32,
+	// presents since the last emulated frame
+	presents: u32,
+	// the audio queue paces the emulation thread, see PacingMode::Audio
+	audio_clocked: bool,
+	// asked of the emulation thread and not received yet, in samples of the core
+	samples_requested: usize,
+	// the display the pacing was picked for, with its refresh rate
+	display: Option<i32>,
+	refresh_rate: Option<i32>,
+	// pixels per window unit on that display, see Sdl2Context::dpi_scale
+	dpi_scale: f32,
+	stats: Stats,
+	filter: Filter,
+	// degrees clockwise, a multiple of 90
+	rotation: u16,
+
+	osd: Osd,
 
 	audio_dev: AudioQueue<f32>,
+	// of the loaded core, what the queue was opened with so it's opened the same way when the device comes back
+	audio_source: AudioSource,
+	// from the command line, the default one when unset
+	audio_device: Option<String>,
+	// the chosen device couldn't be opened, the default one is used until it comes back
+	audio_fallback: bool,
+	// times the queue ran dry while playing
+	underruns: u32,
+	// from the core's channels to the device's, before resampling
+	mixer: ChannelMixer,
+	// converts from the core's rate to the device's, and keeps the queue latency steady
+	resampler: Resampler,
 	rom_path: PathBuf,
+	rom: RomInfo,
+	// as loaded, resets boot from them instead of the file, which may have changed since
+	rom_bytes: Vec<u8>,
+	titles: TitleDb,
+	slot: u8,
+	// open while the load key is held
+	picker: Option<StatePicker>,
+	// set by the drop handler or the open dialog, loaded at the end of the frame's event polling
+	pending_rom: Option<PathBuf>,
+	// window scale asked for by a hotkey, applied along with the pending ROM
+	pending_scale: Option<f32>,
+	// fullscreen is toggled by the main loop, which has the window
+	pending_fullscreen: bool,
+	// reloads the game when its ROM file changes, checked every WATCH_INTERVAL
+	watch: bool,
+	watch_check: Instant,
+	rom_modified: Option<SystemTime>,
+	// last frame a game was running, the screensaver stays off until SCREENSAVER_PAUSE after it
+	last_played: Instant,
+	// dropped state whose ROM is being loaded first
+	pending_state: Option<PathBuf>,
+	// open when an archive holds several ROMs, takes over the input until one is picked
+	rom_chooser: Option<RomChooser>,
+	// shown while no game is loaded, takes over the input the same way
+	rom_browser: Option<RomBrowser>,
+	// open while paused, takes over the input the same way
+	pause_menu: Option<ListMenu>,
+	// asked for from the pause menu, handled with the window events
+	pending_quit: bool,
 
 	keys: Keymaps,
+	rebind: Option<Rebind>,
+	// the session itself lives on the emulation thread
+	netplay: bool,
+	// toggles every cheat of the game at once
+	cheats_enabled: bool,
+	// the VRAM viewer while open, with the core's last video memory, the main loop opens its window to match
+	vram: Option<Option<DebugVram>>,
+	// the audio waveform overlay while shown
+	scope: Option<Scope>,
+	// the battery save of the game, flushed as it changes
+	battery: Battery,
+	config: Config,
 }
 impl EmuContext {
-	pub fn new(sdl: &Sdl2Context) -> Self {
+	pub fn new(sdl: &Sdl2Context, config: Config, netplay: Option<Netplay>) -> Self {
+		Self::with_audio(&sdl.audio_subsystem, sdl.dpi_scale(), config, netplay)
+	}
+
+	// all the context needs of SDL but the window, so the tests can make one
+	fn with_audio(audio: &AudioSubsystem, dpi_scale: f32, config: Config, netplay: Option<Netplay>) -> Self {
 		let emu = Box::new(Nes::boot_empty()) as Emulator;
+		let (core, resolution, fps, caps) = (emu.core_id(), emu.resolution(), emu.fps(), emu.capabilities());
+		let visible = shown_region(&*emu, config.video.hide_overscan);
 
-		let audio_dev = sdl.audio_subsystem
-			.open_queue(None, &emu.audio_spec().1).unwrap();
+		// the placeholder core is never heard, the device only has to exist
+		let audio_source = emu.native_audio().unwrap_or(AudioSource { rate: DEVICE_RATE as u32, channels: 2 });
+		let audio_dev: AudioQueue<f32> = audio.open_queue(None, &device_spec(audio_source)).unwrap();
+		let latency = Duration::from_millis(config.audio.latency_ms as u64);
+		let mixer = ChannelMixer::new(audio_source.channels as usize, audio_dev.spec().channels as usize);
+		let resampler = Resampler::new(audio_source.rate, audio_dev.spec().freq as u32, audio_dev.spec().channels as usize, latency);
 
 		let ms_frame = Duration::ZERO;
-		let keys = Keymaps::default();
+		let keys = Keymaps::from_config(&config.input);
+		let volume = config.audio.volume.clamp(0.0, 1.0);
 
-		Self { emu, ms_frame, audio_dev, rom_path: PathBuf::new(), keys, is_muted: true, is_paused: true, }
-	}
+		let is_netplay = netplay.is_some();
+		let emu = EmuThread::spawn(emu, config.emulation.run_ahead, config.turbo.rate, config.input.block_opposing, config.video.hide_overscan, netplay);
 
-	pub fn try_init(&mut self, rom_path: &Path, canvas: &mut Canvas<Window>, audio: &AudioSubsystem) -> Result<(), Box<dyn Error>> {
-		let emu = open_rom(rom_path)?;
+		Self { emu, core, resolution, visible, fps, ms_frame, vsync: false, vsync_interval: 1, presents: 0, audio_clocked: false, samples_requested: 0, display: None, refresh_rate: None, dpi_scale, stats: Stats::default(), filter: Filter::new(config.video.filter, config.video.ntsc), rotation: 0, osd: Osd::default(), volume, audio_dev, audio_source, audio_device: None, audio_fallback: false, underruns: 0, mixer, resampler, rom_path: PathBuf::new(), rom: RomInfo::default(), rom_bytes: Vec::new(), titles: TitleDb::load(), slot: 0, picker: None, pending_rom: None, pending_scale: None, pending_fullscreen: false, pending_state: None, watch: false, watch_check: Instant::now(), rom_modified: None, last_played: Instant::now(), rom_chooser: None, rom_browser: None, pause_menu: None, pending_quit: false, keys, rebind: None, netplay: is_netplay, cheats_enabled: true, vram: None, scope: None, battery: Battery::default(), config, is_muted: true, audio_available: true, caps, channels: Vec::new(), is_paused: true, }
+	}
 
-		let (width, height) = emu.resolution();
-		canvas.set_logical_size(width as u32, height as u32)?;
+	pub fn try_init(&mut self, rom_path: &Path, audio: &AudioSubsystem) -> Result<(), Box<dyn Error>> {
+		let entry = self.config.archive_choices.get(&rom_path.display().to_string());
+		let (mut emu, rom, rom_bytes) = open_rom(rom_path, entry.map(String::as_str), &self.titles)?;
+		let caps = emu.capabilities();
+		// the game being replaced keeps its battery save
+		let _ = self.save_sram().inspect_err(|msg| error!("{msg}"));
 
-		let (audio_enabled, spec) = emu.audio_spec();
-		let audio_dev = audio
-			.open_queue(None, &spec)?;
+		self.audio_dev.pause();
+		if let Some(source) = emu.native_audio().filter(|_| caps.audio) {
+			self.open_audio(audio, source)?;
+		}
 
-		audio_dev.clear();
-		if audio_enabled { audio_dev.resume(); }
+		let is_muted = self.config.audio.muted;
+		// until the first frame tells otherwise
+		self.audio_available = caps.audio;
+		if self.netplay && !caps.second_player {
+			self.osd.message("This core has a single player, the other side can't play");
+		}
+		self.caps = caps;
+		self.channels = match caps.channel_mute {
+			true => emu.audio_channels().into_iter().map(|name| (name, true)).collect(),
+			false => Vec::new(),
+		};
 
 		self.is_paused = false;
-		self.is_muted = !audio_enabled;
+		self.pause_menu = None;
+		self.rom_browser = None;
+		self.is_muted = is_muted;
 		self.ms_frame = Duration::from_secs_f32(1.0 / emu.fps());		
+		self.core = emu.core_id();
+		self.resolution = emu.resolution();
+		self.visible = shown_region(&*emu, self.config.video.hide_overscan);
+		self.fps = emu.fps();
+		info!("Running at {:.2} fps", self.fps);
 		self.rom_path = rom_path.into();
-		self.audio_dev = audio_dev;
-		self.emu = emu;
+		self.rom_modified = modified_time(rom_path);
+		self.config.add_recent(rom_path, rom.crc32);
+		self.rotation = self.config.video.rotation.get(&format!("{:08x}", rom.crc32)).copied().unwrap_or(0);
+		self.osd.message(format!("Loaded {}", rom.title));
+		let layout = self.keys.layout_name().to_string();
+		self.keys.game_loaded(rom.crc32, self.core);
+		if self.keys.layout_name() != layout {
+			self.osd.message(format!("Layout: {}", self.keys.layout_name()));
+		}
+		self.rom = rom;
+		self.rom_bytes = rom_bytes;
+
+		// set up before handing the core over
+		if caps.battery_ram {
+			self.load_sram(&mut emu);
+		}
+		self.battery.reset(emu.sram());
+		// both players must start from power on
+		if !self.netplay {
+			self.resume_autosave(&mut emu);
+		}
+		self.emu.send(Command::Load(emu, self.rom.crc32));
+		self.replay_held();
+		self.send_mute();
+		self.apply_palette();
+		self.load_cheats();
+
+		self.audio_dev.clear();
+		if self.plays_audio() {
+			self.prime_audio();
+			self.audio_dev.resume();
+		}
+
+		Ok(())
+	}
+
+	/// Opens the audio queue for the core's `source` on the chosen device, or the default one when that fails,
+	/// and sets the resampler up to convert it to what the device gives.
+	fn open_audio(&mut self, audio: &AudioSubsystem, source: AudioSource) -> Result<(), String> {
+		let spec = device_spec(source);
+		let chosen = self.audio_device.as_deref().map(|name| audio.open_queue(name, &spec)
+			.inspect_err(|msg| warn!("Couldn't open audio device {name}, using the default one: {msg}"))
+		);
+		self.audio_fallback = matches!(chosen, Some(Err(_)));
+		let audio_dev: AudioQueue<f32> = match chosen {
+			Some(Ok(audio_dev)) => audio_dev,
+			_ => audio.open_queue(None, &spec)?,
+		};
 
+		let obtained = audio_dev.spec();
+		if obtained.freq != source.rate as i32 {
+			info!("Resampling the core's {} Hz audio to the device's {} Hz", source.rate, obtained.freq);
+		}
+		if obtained.channels != source.channels {
+			info!("Mixing the core's {} audio channels to the device's {}", source.channels, obtained.channels);
+		}
+		let latency = self.resampler.target_latency();
+		self.mixer = ChannelMixer::new(source.channels as usize, obtained.channels as usize);
+		self.resampler = Resampler::new(source.rate, obtained.freq as u32, obtained.channels as usize, latency);
+		self.audio_dev = audio_dev;
+		self.audio_source = source;
 		Ok(())
 	}
+
+	/// Opens the audio again after its device went away or the chosen one came back, keeping it paused or muted as it was.
+	pub fn reopen_audio(&mut self, audio: &AudioSubsystem) {
+		if let Err(msg) = self.open_audio(audio, self.audio_source) {
+			error!("Couldn't reopen the audio device: {msg}");
+			return;
+		}
+		if self.plays_audio() && !self.is_paused && self.has_rom() {
+			self.prime_audio();
+			self.audio_dev.resume();
+		}
+	}
+
+	/// Whether anything should be heard when the game runs.
+	pub fn plays_audio(&self) -> bool {
+		!self.is_muted && self.audio_available
+	}
+
+	fn apply_palette(&mut self) {
+		let (_, palette) = GB_PALETTES[gb_palette_index(&self.config.video.gb_palette)];
+		self.emu.send(Command::SetPalette(palette));
+	}
+
+	// cheats would desync netplay, they're left out of it
+	fn load_cheats(&mut self) {
+		let mut cheats = match self.netplay {
+			true => Cheats::default(),
+			false => Cheats::load(&self.load_path("cheats.toml", None), self.core),
+		};
+		// codes the core has no way to apply are left out, rather than doing nothing
+		if !self.caps.memory && !cheats.is_empty() {
+			warn!("This core's RAM can't be written, skipping {} cheat(s)", cheats.len());
+			cheats = Cheats::default();
+		}
+		if !cheats.is_empty() {
+			self.osd.message(format!("{} cheat(s) loaded", cheats.len()));
+		}
+		self.emu.send(Command::Cheats(cheats));
+	}
+
+	/// Presses what's held down again, for a core whose joypad was just replaced by a state or a reboot.
+	fn replay_held(&self) {
+		for input in self.keys.held_inputs() {
+			self.emu.send(Command::Input(input, InputKind::Press));
+		}
+		for input in self.keys.player2_held() {
+			self.emu.send(Command::PlayerInput(1, input, InputKind::Press));
+		}
+	}
+
+	/// The thread keeps producing audio while muted if the waveform overlay wants to show it.
+	pub fn send_mute(&self) {
+		self.emu.send(Command::Mute(self.is_muted && self.scope.is_none()));
+	}
+
+	pub fn set_vram(&mut self, open: bool) {
+		if open == self.vram.is_some() { return; }
+		if open && !self.caps.vram {
+			self.osd.message("This core has no VRAM viewer");
+			return;
+		}
+		self.vram = open.then_some(None);
+		self.emu.send(Command::Vram(open));
+	}
+
+	pub fn cycle_background(&mut self) {
+		let current = BACKGROUNDS.iter().position(|(_, color)| *color == self.config.video.background);
+		let (name, color) = BACKGROUNDS[current.map_or(0, |i| (i + 1) % BACKGROUNDS.len())];
+		self.config.video.background = color;
+		self.osd.message(format!("Background: {name}"));
+	}
+
+	pub fn cycle_palette(&mut self) {
+		let next = (gb_palette_index(&self.config.video.gb_palette) + 1) % GB_PALETTES.len();
+		let (name, _) = GB_PALETTES[next];
+		self.config.video.gb_palette = name.to_string();
+		self.apply_palette();
+		self.osd.message(format!("Palette: {name}"));
+	}
+
+	/// Logical size of the canvas: the size the filtered image is shown at, turned by the rotation.
+	pub fn logical_size(&self) -> (u32, u32) {
+		let (width, height) = self.filter.display_size(self.resolution, self.visible, self.core);
+		if self.rotation % 180 == 90 { (height as u32, width as u32) } else { (width as u32, height as u32) }
+	}
+
+	/// Resizes the window to `scale` times the core's resolution, as shown after the filters and rotation.
+	/// When that doesn't fit the desktop, the largest whole scale that does is used. Returns the scale applied.
+	/// The size is in window units, so a scale looks as large on every display whatever its pixel density.
+	pub fn resize_window(&self, sdl: &mut Sdl2Context, scale: f32) -> f32 {
+		let (width, height) = self.logical_size();
+		// the NTSC filter shows the image larger than the core's resolution, the scale is of the latter
+		let (_, shown_height) = self.filter.display_size(self.resolution, self.visible, self.core);
+		let unscale = self.visible.3 as f32 / shown_height as f32;
+		let (width, height) = (width as f32 * unscale, height as f32 * unscale);
+
+		let mut scale = scale.max(1.0);
+		if let Some((desktop_width, desktop_height)) = sdl.desktop_size() {
+			let fit = (desktop_width as f32 / width).min(desktop_height as f32 / height).floor().max(1.0);
+			scale = scale.min(fit);
+		}
+
+		let _ = sdl.renderer.window_mut().set_size((width * scale).round() as u32, (height * scale).round() as u32)
+			.inspect_err(|msg| warn!("Couldn't resize the window: {msg}"));
+		scale
+	}
+
+	/// Leaves fullscreen, or enters it the way the config says. Exclusive fullscreen switches the display
+	/// to the mode closest to the game's fps, and falls back to borderless when that doesn't work.
+	pub fn toggle_fullscreen(&mut self, sdl: &mut Sdl2Context) {
+		let mode = sdl.closest_display_mode(self.fps);
+		let window = sdl.renderer.window_mut();
+		let result = match (window.fullscreen_state(), self.config.video.fullscreen) {
+			(FullscreenType::Off, FullscreenMode::Exclusive) => {
+				// alt-tabbing away gives the desktop its mode back
+				hint::set("SDL_VIDEO_MINIMIZE_ON_FOCUS_LOSS", "1");
+				let exclusive = match mode {
+					Some(mode) => window.set_display_mode(mode).and_then(|_| window.set_fullscreen(FullscreenType::True)),
+					None => Err("no display mode found".to_string()),
+				};
+				exclusive.or_else(|msg| {
+					warn!("Couldn't switch to exclusive fullscreen, using borderless: {msg}");
+					window.set_fullscreen(FullscreenType::Desktop)
+				})
+			}
+			(FullscreenType::Off, FullscreenMode::Desktop) => window.set_fullscreen(FullscreenType::Desktop),
+			_ => window.set_fullscreen(FullscreenType::Off),
+		};
+		if let Err(msg) = result {
+			warn!("Couldn't toggle fullscreen: {msg}");
+		}
+		// the refresh rate may have changed with the mode
+		self.update_pacing(sdl);
+	}
+
+	/// Mutes or unmutes the `idx`th sound channel of the core, showing which ones are heard.
+	pub fn toggle_channel(&mut self, idx: usize) {
+		if !self.caps.channel_mute {
+			self.osd.message("This core can't mute its sound channels");
+			return;
+		}
+		let Some((_, on)) = self.channels.get_mut(idx) else { return };
+		*on = !*on;
+		self.emu.send(Command::ChannelEnabled(idx, *on));
+
+		let readout = self.channels.iter()
+			.map(|(name, on)| format!("{name} {}", if *on { "on" } else { "off" }))
+			.collect::<Vec<_>>();
+		self.osd.message(readout.join(", "));
+	}
+
+	pub fn cycle_rotation(&mut self) {
+		self.rotation = (self.rotation + 90) % 360;
+		if self.has_rom() {
+			let key = format!("{:08x}", self.rom.crc32);
+			match self.rotation {
+				0 => self.config.video.rotation.remove(&key),
+				rotation => self.config.video.rotation.insert(key, rotation),
+			};
+		}
+		self.osd.message(format!("Rotation: {} degrees", self.rotation));
+	}
+
+	/// False in the empty state, before any game loaded: the emulation thread then holds a placeholder core
+	/// that's never stepped, and a splash is shown instead of its frames.
+	pub fn has_rom(&self) -> bool {
+		!self.rom_path.as_os_str().is_empty()
+	}
+
+	fn data_dir(&self, rom_dir: String) -> Option<PathBuf> {
+		storage::data_dir().map(|dir| dir
+			.join(self.core.dir_name())
+			.join(rom_dir)
+		)
+	}
+
+	/// Where `file` is saved for the current ROM: a directory per core and ROM CRC32 under the user data dir,
+	/// created on demand. When there is no usable data dir, it goes next to the ROM.
+	pub fn save_path(&self, file: &str) -> PathBuf {
+		match self.data_dir(format!("{:08x}", self.rom.crc32)) {
+			Some(dir) if fs::create_dir_all(&dir).is_ok() => dir.join(file),
+			_ => self.rom_path.with_extension(file),
+		}
+	}
+
+	/// Same as `save_path`, but falls back to the older locations when only those exist:
+	/// the directory named by the old ROM hash, then next to the ROM.
+	pub fn load_path(&self, file: &str, legacy_ext: Option<&str>) -> PathBuf {
+		let path = self.save_path(file);
+		let hashed = self.data_dir(format!("{:016x}", self.rom.legacy_hash)).map(|dir| dir.join(file));
+		let next_to_rom = legacy_ext.map(|ext| self.rom_path.with_extension(ext));
+
+		if path.exists() { return path; }
+		[hashed, next_to_rom].into_iter().flatten()
+			.find(|legacy| legacy.exists())
+			.unwrap_or(path)
+	}
+
+	// slot 0 keeps the name states had before slots existed
+	fn slot_file(slot: u8, ext: &str) -> String {
+		match slot {
+			0 => format!("state.{ext}"),
+			n => format!("state{n}.{ext}"),
+		}
+	}
+
+	pub fn save_slot(&mut self) {
+		self.save_to_slot(self.slot);
+	}
+
+	/// Saves a state to `slot`, whichever slot is selected. The state it replaces is kept as a .bak file,
+	/// so an overwrite by mistake can be undone.
+	pub fn save_to_slot(&mut self, slot: u8) {
+		let path = self.save_path(&Self::slot_file(slot, "sav"));
+		// the emulation thread writes it after this, commands are handled in order
+		if path.exists() {
+			let mut backup = path.clone().into_os_string();
+			backup.push(".bak");
+			let _ = fs::read(&path).and_then(|state| storage::write(Path::new(&backup), &state))
+				.inspect_err(|msg| warn!("Couldn't back up the previous state: {msg}"));
+		}
+		self.emu.send(Command::SaveState(path, self.state_header()));
+
+		// the thumbnail is only a preview, failing to write it doesn't affect the state
+		if let Some(frame) = self.emu.frame() {
+			let thumbnail = Thumbnail::from_framebuf(&frame.buf, frame.pitch, frame.visible, frame.format);
+			let _ = thumbnail.save(&self.save_path(&Self::slot_file(slot, "thumb")))
+				.inspect_err(|msg| warn!("Couldn't write state thumbnail: {msg}"));
+		}
+
+		self.osd.message(format!("Saved slot {slot}"));
+	}
+
+	/// Saves the shown frame to the screenshots folder, named after the ROM and when it was taken.
+	pub fn take_screenshot(&mut self) {
+		let Some(frame) = self.emu.frame() else { return };
+		let stem = self.rom_path.file_stem().map_or("screenshot".into(), |stem| stem.to_string_lossy());
+		let millis = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis();
+		let file = format!("{stem}-{millis}.bmp");
+		let path = match storage::data_dir() {
+			Some(dir) => dir.join("screenshots").join(file),
+			None => self.rom_path.with_file_name(file),
+		};
+
+		match screenshot::save(&path, &frame.buf, frame.pitch, frame.visible, frame.format) {
+			Ok(()) => self.osd.message(format!("Screenshot saved to {}", path.display())),
+			Err(msg) => self.osd.message(format!("Couldn't save the screenshot: {msg}")),
+		}
+	}
+
+	pub fn load_slot(&mut self) {
+		let legacy_ext = (self.slot == 0).then_some("sav");
+		let path = self.load_path(&Self::slot_file(self.slot, "sav"), legacy_ext);
+
+		match self.emu.load_state(path, self.state_header()) {
+			Ok(_) => {
+				self.replay_held();
+				self.osd.message(format!("Loaded slot {}", self.slot));
+			}
+			Err(msg) => self.osd.message(format!("Couldn't load state: {msg}")),
+		}
+	}
+
+	/// Loads a state file dropped on the window into the running game.
+	/// With no game running, the state's ROM is looked up among the recent ones and loaded first.
+	pub fn load_dropped_state(&mut self, path: &Path) {
+		let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+
+		if self.has_rom() {
+			match self.emu.load_state(path.into(), self.state_header()) {
+				Ok(_) => {
+					self.replay_held();
+					self.osd.message(format!("Loaded {name}"));
+				}
+				Err(msg) => self.osd.message(format!("Couldn't load {name}: {msg}")),
+			}
+			return;
+		}
+
+		let recent = savestate::peek_header(path).and_then(|header| self.config.recent_roms.iter()
+			.find(|rom| rom.crc32 == header.rom_crc && rom.path.exists())
+		);
+		match recent {
+			Some(rom) => {
+				self.pending_rom = Some(rom.path.clone());
+				self.pending_state = Some(path.into());
+			}
+			None => self.osd.message(format!("Couldn't load {name}: load its ROM first")),
+		}
+	}
+
+	pub fn state_picker(&self) -> StatePicker {
+		let legacy_ext = (self.slot == 0).then_some("sav");
+		StatePicker::new(&self.load_path(&Self::slot_file(self.slot, "sav"), legacy_ext), &self.save_path(&Self::slot_file(self.slot, "thumb")))
+	}
+
+	/// Writes the battery save now, if it changed since it was last written.
+	pub fn save_sram(&mut self) -> Result<(), String> {
+		if !self.has_rom() { return Ok(()); }
+		let path = self.save_path("battery.srm");
+		self.battery.flush(self.emu.sram(), &path)
+	}
+
+	/// Writes the battery save every few seconds in the background, so a crash loses little progress.
+	pub fn flush_sram(&mut self) {
+		if !self.has_rom() { return; }
+		let path = self.save_path("battery.srm");
+		self.battery.poll(&self.emu, path);
+	}
+
+	fn load_sram(&self, emu: &mut Emulator) {
+		let Some(expected_len) = emu.sram().map(|sram| sram.len()) else { return };
+		let Ok(data) = fs::read(self.load_path("battery.srm", Some("srm"))) else { return };
+
+		if data.len() != expected_len {
+			warn!("Battery save has size {} instead of {expected_len}, ignoring it", data.len());
+			return;
+		}
+		emu.load_sram(&data);
+	}
+
+	pub fn state_header(&self) -> StateHeader {
+		StateHeader { core: self.core, rom_crc: self.rom.crc32 }
+	}
+
+	pub fn autosave(&self) {
+		if !self.has_rom() || !self.caps.save_states { return; }
+		self.emu.send(Command::SaveState(self.save_path("autosave.sav"), self.state_header()));
+	}
+
+	fn resume_autosave(&mut self, emu: &mut Emulator) {
+		if !self.caps.save_states { return; }
+		let path = self.load_path("autosave.sav", Some(&format!("{:016x}.autosave", self.rom.legacy_hash)));
+		// a ROM rebuilt after the autosave was made would likely not match it anymore
+		match (modified_time(&path), modified_time(&self.rom_path)) {
+			(Some(save_time), Some(rom_time)) if save_time >= rom_time => {
+				match emu.load(&path, &self.state_header()) {
+					Ok(_) => self.osd.message("Resumed from autosave"),
+					Err(msg) => self.osd.message(format!("Couldn't resume autosave: {msg}")),
+				}
+			}
+			_ => {}
+		}
+	}
+
+	/// Resets the game like its console's reset button would.
+	pub fn reset(&mut self) {
+		match self.core {
+			// the Game Boy has no reset button, it can only be power cycled
+			CoreId::Gameboy => {
+				self.emu.send(Command::HardReset(self.rom_bytes.clone()));
+				// the rebooted core has its default colors, and nothing held
+				self.apply_palette();
+				self.replay_held();
+				for (idx, _) in self.channels.iter().enumerate().filter(|(_, (_, on))| !on) {
+					self.emu.send(Command::ChannelEnabled(idx, false));
+				}
+			}
+			_ => self.emu.send(Command::Reset),
+		}
+
+		self.audio_dev.pause();
+		self.audio_dev.clear();
+		self.prime_audio();
+		self.audio_dev.resume();
+		self.is_paused = false;
+		self.pause_menu = None;
+	}
+
+	/// Power cycles the game by booting its ROM again, with the cartridge RAM as it is now.
+	pub fn hard_reset(&mut self) {
+		let mut emu = match cores::boot(&self.rom_bytes, &self.rom_path) {
+			Ok(emu) => emu,
+			Err(msg) => {
+				self.osd.message(format!("Couldn't reset: {msg}"));
+				return;
+			}
+		};
+		let sram = self.emu.sram().filter(|sram| emu.sram().is_some_and(|new| new.len() == sram.len()));
+		if let Some(sram) = sram {
+			emu.load_sram(&sram);
+		}
+		self.emu.send(Command::Load(emu, self.rom.crc32));
+		self.replay_held();
+		self.send_mute();
+		self.apply_palette();
+		self.load_cheats();
+
+		self.audio_dev.pause();
+		self.audio_dev.clear();
+		self.prime_audio();
+		self.audio_dev.resume();
+		self.is_paused = false;
+		self.pause_menu = None;
+	}
+
+	/// Loads the running game again from disk, for when its ROM was rebuilt.
+	pub fn reload_rom(&mut self) {
+		if self.has_rom() {
+			self.pending_rom = Some(self.rom_path.clone());
+		}
+	}
+
+	/// With --watch, reloads the game when its ROM file changed since it was loaded.
+	pub fn watch_rom(&mut self) {
+		if !self.watch || !self.has_rom() || self.watch_check.elapsed() < WATCH_INTERVAL { return; }
+		self.watch_check = Instant::now();
+
+		let modified = modified_time(&self.rom_path);
+		if modified.is_some() && modified != self.rom_modified {
+			info!("{} changed, reloading it", self.rom_path.display());
+			// not retried until it changes again, when it fails to load
+			self.rom_modified = modified;
+			self.reload_rom();
+		}
+	}
+
+	/// Keeps the screensaver from starting while a game runs, as playing with a pad doesn't count as activity.
+	pub fn update_screensaver(&mut self, video: &VideoSubsystem) {
+		if self.has_rom() && !self.is_paused {
+			self.last_played = Instant::now();
+		}
+		let inhibit = !self.config.video.screensaver && self.has_rom() && self.last_played.elapsed() < SCREENSAVER_PAUSE;
+		if inhibit == video.is_screen_saver_enabled() {
+			match inhibit {
+				true => video.disable_screen_saver(),
+				false => video.enable_screen_saver(),
+			}
+		}
+	}
+
+	/// Picks between vsync and timer pacing for the current core, and applies it to the renderer.
+	/// Vsync is used when the display refresh is the core's fps, or a multiple of it, within a tolerance
+	/// the audio rate control can absorb.
+	pub fn update_pacing(&mut self, sdl: &mut Sdl2Context) {
+		const VSYNC_TOLERANCE: f32 = 0.002;
+		const MAX_VSYNC_INTERVAL: u32 = 4;
+
+		let fps = self.fps;
+		self.display = sdl.renderer.window().display_index().ok();
+		self.refresh_rate = sdl.refresh_rate();
+		let interval = self.refresh_rate.and_then(|hz| (1..=MAX_VSYNC_INTERVAL)
+			.find(|n| ((hz as f32 / *n as f32 - fps) / fps).abs() < VSYNC_TOLERANCE)
+		);
+		let vsync = match self.config.video.pacing {
+			PacingMode::Timer | PacingMode::Audio => false,
+			PacingMode::Auto => interval.is_some(),
+			// a 50 fps PAL game synced to a 60 Hz display would run a fifth too fast
+			PacingMode::Vsync if self.refresh_rate.is_some() && interval.is_none() => {
+				warn!("The display refresh doesn't match the game's {fps:.2} fps, using timer pacing");
+				false
+			}
+			PacingMode::Vsync => true,
+		};
+
+		self.vsync = sdl.renderer.set_vsync(vsync) && vsync;
+		if vsync && !self.vsync {
+			warn!("Couldn't enable vsync, falling back to timer pacing");
+		}
+		self.vsync_interval = interval.unwrap_or(1);
+		self.presents = 0;
+		self.emu.send(Command::Vsync(self.vsync));
+		let mode = match self.config.video.pacing {
+			PacingMode::Audio => "audio clock",
+			_ if self.vsync => "vsync",
+			_ => "timer",
+		};
+		info!("Pacing: {mode} at {} Hz for {fps:.2} fps",
+			self.refresh_rate.map_or("unknown".into(), |hz| hz.to_string()));
+	}
+
+	/// Counts a present under vsync, asking the emulation thread for a frame once enough went by.
+	/// Returns whether it did, so the loop can wait for that frame.
+	fn vsync_tick(&mut self) -> bool {
+		self.presents = (self.presents + 1) % self.vsync_interval;
+		if self.presents != 0 { return false; }
+		self.emu.send(Command::Tick);
+		true
+	}
+
+	/// Fills an empty queue with silence up to the target latency, so rate control starts from a steady state.
+	pub fn prime_audio(&self) {
+		let silence = vec![0.0; self.resampler.target_frames() * self.resampler.channels()];
+		let _ = self.audio_dev.queue_audio(&silence);
+	}
+
+	fn queued_frames(&self) -> usize {
+		queued_frames(&self.audio_dev)
+	}
+
+	/// How long the queued audio takes to play, the delay between the game and the speakers.
+	pub fn audio_latency(&self) -> Duration {
+		Duration::from_secs_f64(self.queued_frames() as f64 / self.audio_dev.spec().freq.max(1) as f64)
+	}
+
+	/// Queues the audio the emulation thread produced since the last call.
+	pub fn queue_audio(&mut self) {
+		// a hiccup starved the queue: refill it to the target latency at once, instead of crackling until rate control catches up
+		if self.plays_audio() && !self.is_paused && self.has_rom() && self.audio_dev.size() == 0 {
+			self.underruns += 1;
+			self.prime_audio();
+		}
+
+		let was_available = self.audio_available;
+		self.emu.audio(|samples| {
+			self.audio_available = !samples.is_empty();
+			self.samples_requested = self.samples_requested.saturating_sub(samples.len());
+			samples.iter_mut().for_each(|sample| *sample *= self.volume);
+			if let Some(scope) = &mut self.scope {
+				scope.push(samples, self.mixer.channels());
+			}
+			// only there for the overlay, or sent before the thread heard about the mute
+			if self.is_muted { return; }
+
+			// with audio clocking, the queue is kept at its target by asking for what it misses instead
+			if !self.audio_clocked {
+				// not through self, the closure already borrows the thread's handle
+				self.resampler.update_rate(queued_frames(&self.audio_dev));
+			}
+			let samples = self.resampler.process(self.mixer.process(samples));
+			self.audio_dev.queue_audio(samples).unwrap();
+		});
+
+		// a core can start or stop making sound, the Game Boy's until its APU is there
+		if self.audio_available != was_available {
+			info!("The core {} sound", if self.audio_available { "has" } else { "has no" });
+			self.audio_dev.pause();
+			self.audio_dev.clear();
+			if self.plays_audio() && !self.is_paused {
+				self.prime_audio();
+				self.audio_dev.resume();
+			}
+		}
+
+		// a long stall leaves a backlog that would play late for a long while, start over instead
+		if self.queued_frames() > self.resampler.target_frames() * MAX_AUDIO_BACKLOG {
+			warn!("Audio fell behind, dropping the backlog");
+			self.audio_dev.clear();
+			self.prime_audio();
+		}
+	}
+
+	/// With audio-clocked pacing, asks the emulation thread for the samples the queue misses to reach its target.
+	/// Without sound there's nothing to clock it by, it's paced by frames then.
+	pub fn request_samples(&mut self) {
+		let clocked = matches!(self.config.video.pacing, PacingMode::Audio) && self.plays_audio() && !self.netplay;
+		if clocked != self.audio_clocked {
+			self.audio_clocked = clocked;
+			self.samples_requested = 0;
+			self.emu.send(Command::AudioClock(clocked));
+		}
+		if !clocked || self.is_paused { return; }
+
+		let missing = self.resampler.target_frames().saturating_sub(self.queued_frames());
+		let wanted = self.mixer.input_samples(self.resampler.input_samples(missing));
+		if wanted > self.samples_requested {
+			self.emu.send(Command::Samples(wanted - self.samples_requested));
+			self.samples_requested = wanted;
+		}
+	}
+
+	pub fn poll_notices(&mut self) {
+		for notice in self.emu.notices() {
+			match notice {
+				Notice::Message(msg) => self.osd.message(msg),
+				// may come in after the viewer was closed
+				Notice::Vram(vram) => if self.vram.is_some() {
+					self.vram = Some(vram);
+				},
+				Notice::NetplayEnded(msg) => {
+					self.netplay = false;
+					self.is_paused = true;
+					self.audio_dev.pause();
+					self.osd.message(format!("Netplay ended, game paused: {msg}"));
+				}
+			}
+		}
+	}
 }
 
-fn new_texture<'a>(ctx: &EmuContext, creator: &'a TextureCreator<WindowContext>) -> Texture<'a> {
-	let (width, height) = ctx.emu.resolution();
-	creator
-		.create_texture_target(PixelFormatEnum::RGBA32, width as u32, height as u32)
-		.unwrap()
+/// Prints the names --audio-device accepts. Returns the process exit code.
+fn list_audio_devices() -> i32 {
+	let audio = match sdl2::init().and_then(|sdl| sdl.audio()) {
+		Ok(audio) => audio,
+		Err(msg) => {
+			error!("Couldn't start SDL audio: {msg}");
+			return 2;
+		}
+	};
+	for i in 0..audio.num_audio_playback_devices().unwrap_or(0) {
+		if let Ok(name) = audio.audio_playback_device_name(i) {
+			println!("{name}");
+		}
+	}
+	0
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+	fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Writes the battery save one last time. When that fails, the user picks between trying again and losing the progress.
+fn save_sram_on_quit(ctx: &mut EmuContext, window: &Window) {
+	let buttons = [
+		ButtonData { flags: MessageBoxButtonFlag::RETURNKEY_DEFAULT, button_id: 0, text: "Retry" },
+		ButtonData { flags: MessageBoxButtonFlag::ESCAPEKEY_DEFAULT, button_id: 1, text: "Quit anyway" },
+	];
+
+	while let Err(msg) = ctx.save_sram() {
+		error!("{msg}");
+		let message = format!("{msg}\nProgress since the last save will be lost.");
+		let clicked = show_message_box(MessageBoxFlag::WARNING, &buttons, "CMB Emu", &message, window, None);
+		if !matches!(clicked, Ok(ClickedButton::CustomButton(button)) if button.button_id == 0) { break; }
+	}
+}
+
+/// Reports a ROM that failed to load in a message box over the window.
+/// The running game stays paused behind the box until it's dismissed.
+fn show_load_error(ctx: &EmuContext, window: &Window, path: &Path, err: &dyn Error) {
+	let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy();
+	let message = format!("Couldn't load {name}:\n{err}");
+	error!("{message}");
+
+	ctx.emu.send(Command::Pause(true));
+	ctx.audio_dev.pause();
+	let _ = show_simple_message_box(MessageBoxFlag::ERROR, "CMB Emu", &message, window)
+		.inspect_err(|msg| warn!("Couldn't show the error message box: {msg}"));
+	ctx.emu.send(Command::Pause(ctx.is_paused));
+	if !ctx.is_paused && ctx.plays_audio() {
+		ctx.audio_dev.resume();
+	}
+}
+
+/// Handles the pending window and input events, returns false once the app should quit.
+fn poll_events(ctx: &mut EmuContext, sdl: &mut Sdl2Context) -> bool {
+	let main_window = sdl.renderer.window().id();
+	let mut moved = false;
+
+	for event in sdl.events.poll_iter() {
+		handle_input(ctx, &event);
+
+		match event {
+			// the VRAM viewer closes on its own, but with it open closing the main window isn't a Quit event
+			Event::Window { window_id, win_event: WindowEvent::Close, .. } if window_id != main_window => ctx.set_vram(false),
+			Event::Window { win_event: WindowEvent::Moved(..) | WindowEvent::DisplayChanged(..), .. } => moved = true,
+			Event::Quit { .. } | Event::Window { win_event: WindowEvent::Close, .. } => ctx.pending_quit = true,
+			Event::DropFile { filename, .. } => {
+				let path = PathBuf::from(filename);
+				// legacy states have no header, so they're told by extension
+				if savestate::is_state_at(&path) || path.extension().is_some_and(|ext| ext == "sav") {
+					ctx.load_dropped_state(&path);
+				} else {
+					ctx.pending_rom = Some(path);
+				}
+			}
+			Event::ControllerDeviceAdded { which , .. } => {
+				match sdl.controller_subsystem.open(which) {
+					Ok(controller) => {
+						info!("Found controller: {}", controller.name());
+						ctx.keys.controller_added(controller.instance_id(), controller.name());
+						sdl.controllers.push(controller);
+					}
+					Err(_) => warn!("A controller was connected, but I couldn't initialize it")
+				}
+			}
+			// a vanished device leaves its queue stopped, instead of paused
+			Event::AudioDeviceRemoved { iscapture: false, .. } if ctx.audio_dev.status() == AudioStatus::Stopped => {
+				warn!("The audio device was disconnected, switching to the default one");
+				ctx.reopen_audio(&sdl.audio_subsystem);
+			}
+			Event::AudioDeviceAdded { which, iscapture: false, .. } if ctx.audio_fallback => {
+				let name = sdl.audio_subsystem.audio_playback_device_name(which).ok();
+				if name.is_some() && name == ctx.audio_device {
+					info!("The audio device {} is back, switching to it", name.unwrap_or_default());
+					ctx.reopen_audio(&sdl.audio_subsystem);
+				}
+			}
+			Event::ControllerDeviceRemoved { which, .. } => {
+				sdl.controllers.retain(|controller| controller.instance_id() != which);
+				controller_removed(ctx, which);
+			}
+			// controllers show up as joysticks too, those are opened as controllers instead
+			Event::JoyDeviceAdded { which, .. } if !sdl.controller_subsystem.is_game_controller(which) => {
+				match sdl.joystick_subsystem.open(which) {
+					Ok(joystick) => {
+						info!("Found joystick without a controller mapping: {}", joystick.name());
+						ctx.keys.joystick_added(joystick.instance_id());
+						sdl.joysticks.push(joystick);
+					}
+					Err(_) => warn!("A joystick was connected, but I couldn't initialize it")
+				}
+			}
+			Event::JoyDeviceRemoved { which, .. } if ctx.keys.is_joystick(which) => {
+				sdl.joysticks.retain(|joystick| joystick.instance_id() != which);
+				controller_removed(ctx, which);
+			}
+			_ => {}
+		}
+	}
+
+	// another monitor can have another refresh rate
+	if moved && sdl.renderer.window().display_index().ok() != ctx.display {
+		ctx.update_pacing(sdl);
+	}
+	// and another pixel density, the scale is kept in game pixels per window unit
+	if moved && sdl.dpi_scale() != ctx.dpi_scale {
+		ctx.dpi_scale = sdl.dpi_scale();
+		info!("The window is now at {}x pixel density", ctx.dpi_scale);
+		if sdl.renderer.window().fullscreen_state() == FullscreenType::Off {
+			ctx.resize_window(sdl, ctx.config.video.scale);
+		}
+	}
+
+	if ctx.pending_quit {
+		ctx.audio_dev.pause();
+		ctx.config.input = ctx.keys.to_config();
+		let _ = ctx.config.save()
+			.inspect_err(|msg| error!("Couldn't save config: {msg}"));
+		save_sram_on_quit(ctx, sdl.renderer.window());
+		ctx.autosave();
+		// the thread saves before it stops
+		ctx.emu.join();
+		return false;
+	}
+	true
 }
 
 fn main() {
-	const SCALE: f32 = 3.0;
-	const WINDOW_WIDTH:  u32  = (SCALE * 30 as f32 * 8.0) as u32;
-	const WINDOW_HEIGHT: u32  = (SCALE * 30 as f32 * 8.0) as u32;
-			
+	let args = Args::parse();
+	logging::init(args.log_level, args.log_file);
+
+	if let Some(verify) = &args.verify {
+		std::process::exit(verify::run(verify));
+	}
+	if args.list_audio_devices {
+		std::process::exit(list_audio_devices());
+	}
+
+	let config = Config::load();
+
+	// sized once the context knows the core's resolution
 	let mut sdl = Sdl2Context
-		::new("CMB Emu", WINDOW_WIDTH, WINDOW_HEIGHT)
+		::new("CMB Emu", 1, 1, args.renderer, args.software_renderer)
 		.unwrap();
 	
-	// Just default it to NES
-	let mut ctx = EmuContext::new(&sdl);
+	let delay = args.input_delay.unwrap_or(config.netplay.input_delay);
+	let netplay = match (args.host, &args.connect) {
+		(Some(port), _) => Some(Netplay::host(port, delay)),
+		(None, Some(addr)) => Some(Netplay::connect(addr, delay)),
+		_ => None,
+	};
+	let netplay = netplay.and_then(|netplay| netplay
+		.inspect_err(|msg| error!("Couldn't start netplay: {msg}"))
+		.ok()
+	);
 
-	let texture_creator = sdl.canvas.texture_creator();
-	let mut texture = new_texture(&ctx, &texture_creator);
+	// starts empty, the browser or a dropped file loads the first game
+	let mut ctx = EmuContext::new(&sdl, config, netplay);
+	ctx.audio_device = args.audio_device;
+	ctx.watch = args.watch;
 
-	'running: loop {
-		let ms_since_start = Instant::now();
+	let scaling = if ctx.config.video.smooth { ScalingMode::Linear } else { ScalingMode::Nearest };
+	sdl.renderer.set_scaling_mode(scaling);
+	ctx.resize_window(&mut sdl, args.scale.unwrap_or(ctx.config.video.scale));
+	sdl.renderer.window_mut().set_position(WindowPos::Centered, WindowPos::Centered);
 
-		if !ctx.is_paused {
-			ctx.emu.step_one_frame();
-			
-			if !ctx.is_muted && ctx.audio_dev.size() < 95*6 {
-				ctx.emu.step_one_frame();
-			}
-			
-			if ctx.is_muted {
-				ctx.emu.samples();
-			} else {
-				ctx.audio_dev.queue_audio(&ctx.emu.samples()).unwrap();
+	ctx.update_pacing(&mut sdl);
+	let rom_dir = ctx.config.rom_dir.clone().or(ctx.config.last_dir.clone());
+	let recent = ctx.config.recent_roms.iter().map(|rom| rom.path.clone()).collect();
+	ctx.rom_browser = Some(RomBrowser::new(rom_dir, recent));
+
+	// a game given on the command line, with the state to start from
+	if let Some(rom) = &args.rom {
+		if let Err(msg) = ctx.try_init(rom, &sdl.audio_subsystem) {
+			error!("Couldn't load {}: {msg}", rom.display());
+			std::process::exit(2);
+		}
+		let _ = sdl.renderer.window_mut().set_title(&format!("CMB Emu - {}", ctx.rom.title));
+		ctx.update_pacing(&mut sdl);
+
+		if let Some(state) = &args.state {
+			if let Err(msg) = ctx.emu.load_state(state.clone(), ctx.state_header()) {
+				error!("Couldn't load state {}: {msg}", state.display());
+				std::process::exit(1);
 			}
 		}
+	} else if args.state.is_some() {
+		error!("--state needs the ROM it was saved from, like: cmbemu game.nes --state game.slot3.sav");
+		std::process::exit(1);
+	}
 
-		for event in sdl.events.poll_iter() {
-			handle_input(&mut ctx, &event);
+	// filtering and uploading the last frame, shown with the stats
+	let mut upload_time = Duration::ZERO;
+	let mut vram_viewer: Option<VramViewer> = None;
 
-			match event {
-				Event::Quit { .. } => {
-					ctx.audio_dev.pause();
-					break 'running;
-				}
-				Event::DropFile { filename, .. } => {
-					let _  = ctx
-					.try_init(&PathBuf::from(filename), &mut sdl.canvas, &sdl.audio_subsystem)
-					.inspect_err(|msg| eprintln!("{msg}\n"));
+	// one iteration per shown frame, false once the app quits
+	let frame = move || {
+		// inputs first, so they reach the emulation thread before it steps the next frame
+		if !poll_events(&mut ctx, &mut sdl) {
+			return false;
+		}
+		#[cfg(feature = "web")]
+		if let Some(rom) = web::take_uploaded_rom() {
+			ctx.pending_rom = Some(rom);
+		}
+		poll_menu_combo(&mut ctx);
+		ctx.poll_notices();
+		ctx.queue_audio();
+		ctx.request_samples();
+		ctx.flush_sram();
+		ctx.watch_rom();
+		ctx.update_screensaver(&sdl.video_subsystem);
+		if let Some(browser) = &mut ctx.rom_browser {
+			browser.poll();
+		}
 
-					texture = new_texture(&ctx, &texture_creator);
-				}
-				Event::ControllerDeviceAdded { which , .. } => {
-					match sdl.controller_subsystem.open(which) {
-						Ok(controller) => {
-							eprintln!("Found controller: {}\n", controller.name());
-							sdl.controllers.push(controller);
+		if std::mem::take(&mut ctx.pending_fullscreen) {
+			ctx.toggle_fullscreen(&mut sdl);
+		}
+		if let Some(scale) = ctx.pending_scale.take() {
+			let scale = ctx.resize_window(&mut sdl, scale);
+			ctx.config.video.scale = scale;
+			ctx.osd.message(format!("Window scale: {scale}x"));
+		}
+
+		if let Some(rom_path) = ctx.pending_rom.take() {
+			// reloading the running game from disk keeps it paused if it was
+			let reload = ctx.has_rom() && rom_path == ctx.rom_path;
+			let was_paused = ctx.is_paused;
+			let result = ctx.try_init(&rom_path, &sdl.audio_subsystem);
+			if result.is_err() {
+				ctx.pending_state = None;
+			}
+
+			match result {
+				Ok(()) => {
+					let _ = sdl.renderer.window_mut().set_title(&format!("CMB Emu - {}", ctx.rom.title));
+					ctx.update_pacing(&mut sdl);
+					if let Some(state) = ctx.pending_state.take() {
+						ctx.load_dropped_state(&state);
+					}
+					if reload {
+						ctx.osd.message("ROM reloaded");
+						if was_paused {
+							ctx.is_paused = true;
+							ctx.emu.send(Command::Pause(true));
+							ctx.audio_dev.pause();
 						}
-						Err(_) => eprintln!("A controller was connected, but I couldn't initialize it\n")
 					}
 				}
-				_ => {}
+				// the current game keeps running until one is picked
+				Err(err) => match err.downcast::<MultipleRoms>() {
+					Ok(roms) => ctx.rom_chooser = Some(RomChooser::new(rom_path, roms.0)),
+					Err(msg) if reload => ctx.osd.message(format!("Couldn't reload the ROM: {msg}")),
+					Err(msg) => show_load_error(&ctx, sdl.renderer.window(), &rom_path, &*msg),
+				}
 			}
 		}
 
-		sdl.canvas.clear();
-		let (framebuf, pitch) = ctx.emu.framebuf();
-		texture.update(None, &framebuf, pitch).unwrap();
-		sdl.canvas.copy(&texture, None, None).unwrap();
-		sdl.canvas.present();
+		// without vsync, the emulation thread's frames pace the loop. with it, the loop paces the thread
+		let ticked = ctx.vsync && !ctx.is_paused && ctx.vsync_tick();
+		let wait = match (ctx.vsync, ctx.is_paused || ctx.ms_frame.is_zero()) {
+			(true, _) if ticked => ctx.ms_frame,
+			(true, _) => Duration::ZERO,
+			(false, true) => IDLE_FRAME,
+			(false, false) => ctx.ms_frame,
+		};
+		ctx.emu.update_frame(wait);
+
+		let (resolution, core) = ctx.emu.frame()
+			.map_or((ctx.resolution, ctx.core), |frame| (frame.resolution, frame.core));
+		// hiding the overscan changes it while playing, the window keeps its size and the image is scaled to it
+		if let Some(frame) = ctx.emu.frame() {
+			ctx.visible = frame.visible;
+		}
+		let logical = ctx.logical_size();
+		sdl.renderer.resize_output(logical.0, logical.1);
+
+		if let Some(frame) = ctx.emu.frame() {
+			let start = Instant::now();
+			let size = ctx.filter.output_size(resolution, core);
+			let (framebuf, pitch, format) = ctx.filter.apply(&frame.buf, frame.pitch, resolution, frame.format, core);
+			sdl.renderer.upload_frame(framebuf, pitch, size, format, ctx.scope.as_ref()).unwrap();
+			upload_time = start.elapsed();
+		}
+
+		// the unrotated image, centered so that turning it around its center fills the logical area
+		let (width, height) = ctx.filter.display_size(resolution, ctx.visible, core);
+		let (x, y, src_width, src_height) = ctx.filter.visible_rect(resolution, ctx.visible, core);
+		let src = Rect::new(x as i32, y as i32, src_width as u32, src_height as u32);
+		let dst = Rect::new(
+			(logical.0 as i32 - width as i32) / 2, (logical.1 as i32 - height as i32) / 2,
+			width as u32, height as u32,
+		);
+		let [r, g, b] = ctx.config.video.background;
+		sdl.renderer.draw_frame(src, ctx.has_rom().then_some(dst), ctx.rotation, Color::RGB(r, g, b));
+		let renderer = format!("renderer {}", sdl.renderer.name());
+		let canvas = sdl.renderer.overlay();
+		if ctx.is_paused && ctx.has_rom() && ctx.pause_menu.is_none() {
+			draw_paused(canvas);
+		}
+
+		let jitter = ctx.emu.frame().map_or(Duration::ZERO, |frame| frame.jitter);
+		let mode = match (ctx.audio_clocked, ctx.vsync) {
+			(true, _) => "audio clock".to_string(),
+			(false, true) => format!("vsync x{}", ctx.vsync_interval),
+			(false, false) => "timer".to_string(),
+		};
+		let refresh = ctx.refresh_rate.map_or("? Hz".to_string(), |hz| format!("{hz} Hz"));
+		let pacing = format!("{mode} @ {refresh}, jitter {:.2} ms", jitter.as_secs_f32() * 1000.0);
+		let upload = format!("upload {:.2} ms", upload_time.as_secs_f32() * 1000.0);
+		let underruns = format!("underruns {}", ctx.underruns);
+		let latency = format!("audio {} ms, target {} ms", ctx.audio_latency().as_millis(), ctx.resampler.target_latency().as_millis());
+		ctx.stats.draw(canvas, &[renderer, pacing, upload, underruns, latency]);
+		if let Some(frame) = ctx.emu.frame().filter(|_| ctx.config.video.input_display && ctx.has_rom()) {
+			draw_input_display(canvas, &frame.inputs, if ctx.caps.second_player { 2 } else { 1 });
+		}
+		if let Some(picker) = &ctx.picker {
+			picker.draw(canvas, ctx.slot);
+		}
+		if let Some(browser) = &ctx.rom_browser {
+			browser.menu.draw(canvas);
+		}
+		if let Some(chooser) = &ctx.rom_chooser {
+			chooser.menu.draw(canvas);
+		}
+		if let Some(menu) = &ctx.pause_menu {
+			menu.draw(canvas);
+		}
+		ctx.osd.draw(canvas);
+		// once more, so inputs and quits aren't held back by rendering
+		if !poll_events(&mut ctx, &mut sdl) {
+			return false;
+		}
+		sdl.renderer.present();
+		ctx.stats.frame();
 
-		let ms_elapsed = Instant::now() - ms_since_start;
-		if ctx.ms_frame > ms_elapsed {
-			std::thread::sleep(ctx.ms_frame - ms_elapsed);
+		match (&ctx.vram, &mut vram_viewer) {
+			(Some(vram), Some(viewer)) => viewer.draw(vram.as_ref()),
+			(Some(_), None) => match VramViewer::open(&sdl.video_subsystem) {
+				Ok(viewer) => vram_viewer = Some(viewer),
+				Err(msg) => {
+					warn!("Couldn't open the VRAM viewer: {msg}");
+					ctx.set_vram(false);
+				}
+			},
+			(None, Some(_)) => vram_viewer = None,
+			(None, None) => {}
 		}
+		true
+	};
+
+	#[cfg(not(feature = "web"))]
+	{
+		let mut frame = frame;
+		while frame() {}
 	}
+	// the browser can't be blocked, it calls back once per display refresh instead
+	#[cfg(feature = "web")]
+	web::run_main_loop(frame);
 }
@@ -0,0 +1,75 @@
+use sdl2::{pixels::Color, rect::Rect, render::{BlendMode, Canvas}, video::Window};
+
+use crate::osd::{draw_text, in_window_coords, GLYPH_SIZE};
+
+pub enum MenuKey {
+  Up, Down, Confirm, Back,
+}
+
+pub enum MenuAction {
+  Confirm(usize),
+  Cancel,
+}
+
+/// Vertical list of choices drawn over the game, navigated with up/down.
+pub struct ListMenu {
+  pub title: String,
+  pub items: Vec<String>,
+  pub selected: usize,
+}
+
+impl ListMenu {
+  // items shown at once, the list scrolls to keep the selection in view
+  const VISIBLE: usize = 12;
+
+  pub fn new(title: impl Into<String>, items: Vec<String>) -> Self {
+    Self { title: title.into(), items, selected: 0 }
+  }
+
+  pub fn input(&mut self, key: MenuKey) -> Option<MenuAction> {
+    let len = self.items.len();
+    match key {
+      MenuKey::Up if len > 0 => self.selected = (self.selected + len - 1) % len,
+      MenuKey::Down if len > 0 => self.selected = (self.selected + 1) % len,
+      MenuKey::Confirm if len > 0 => return Some(MenuAction::Confirm(self.selected)),
+      MenuKey::Back => return Some(MenuAction::Cancel),
+      _ => {}
+    }
+    None
+  }
+
+  pub fn draw(&self, canvas: &mut Canvas<Window>) {
+    const SCALE: i32 = 2;
+    const LINE: i32 = (GLYPH_SIZE + 4) * SCALE;
+
+    in_window_coords(canvas, |canvas| {
+      let (width, height) = canvas.output_size().unwrap_or_default();
+      let first = self.selected.saturating_sub(Self::VISIBLE - 1);
+      let shown = self.items.len().min(Self::VISIBLE);
+
+      let box_height = (shown as i32 + 2) * LINE;
+      let top = ((height as i32 - box_height) / 2).max(0);
+
+      let prev_color = canvas.draw_color();
+      let prev_blend = canvas.blend_mode();
+      canvas.set_blend_mode(BlendMode::Blend);
+      canvas.set_draw_color(Color::RGBA(0, 0, 0, 200));
+      let _ = canvas.fill_rect(Rect::new(0, top, width, box_height as u32));
+      canvas.set_blend_mode(prev_blend);
+      canvas.set_draw_color(prev_color);
+
+      let left = 16;
+      draw_text(canvas, left, top + LINE / 2, SCALE, &self.title, Color::WHITE);
+
+      for (i, item) in self.items.iter().enumerate().skip(first).take(shown) {
+        let y = top + LINE / 2 + (i - first + 1) as i32 * LINE;
+        let (text, color) = if i == self.selected {
+          (format!("> {item}"), Color::YELLOW)
+        } else {
+          (format!("  {item}"), Color::GRAY)
+        };
+        draw_text(canvas, left, y, SCALE, &text, color);
+      }
+    });
+  }
+}
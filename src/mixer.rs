@@ -0,0 +1,66 @@
+/// Converts interleaved samples between the core's channel count and the device's.
+/// Mono is copied to every channel, several channels are averaged down to mono.
+pub struct ChannelMixer {
+  from: usize,
+  to: usize,
+  out: Vec<f32>,
+}
+
+impl ChannelMixer {
+  pub fn new(from: usize, to: usize) -> Self {
+    Self { from: from.max(1), to: to.max(1), out: Vec::new() }
+  }
+
+  /// Channels of the samples it takes.
+  pub fn channels(&self) -> usize { self.from }
+
+  /// Input samples, all channels counted, that make `samples` output samples.
+  pub fn input_samples(&self, samples: usize) -> usize {
+    samples / self.to * self.from
+  }
+
+  pub fn process<'a>(&'a mut self, input: &'a [f32]) -> &'a [f32] {
+    if self.from == self.to { return input; }
+
+    self.out.clear();
+    for frame in input.chunks_exact(self.from) {
+      match (self.from, self.to) {
+        (1, to) => self.out.extend(std::iter::repeat_n(frame[0], to)),
+        (from, 1) => self.out.push(frame.iter().sum::<f32>() / from as f32),
+        // any other pair has no obvious layout, the shared channels are kept and the rest is silent
+        (from, to) => {
+          self.out.extend_from_slice(&frame[..from.min(to)]);
+          self.out.extend(std::iter::repeat_n(0.0, to.saturating_sub(from)));
+        }
+      }
+    }
+    &self.out
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn mono_is_copied_to_every_channel() {
+    let mut mixer = ChannelMixer::new(1, 2);
+    assert_eq!(mixer.process(&[0.1, 0.2, 0.3]), [0.1, 0.1, 0.2, 0.2, 0.3, 0.3]);
+    assert_eq!(mixer.input_samples(6), 3);
+  }
+
+  #[test]
+  fn stereo_is_averaged_frame_by_frame() {
+    let mut mixer = ChannelMixer::new(2, 1);
+    // left and right interleaved, pairs mustn't be mixed across frames
+    assert_eq!(mixer.process(&[1.0, 0.0, 0.5, 0.5, -1.0, 0.0]), [0.5, 0.5, -0.5]);
+    assert_eq!(mixer.input_samples(3), 6);
+  }
+
+  #[test]
+  fn same_channels_pass_through() {
+    let mut mixer = ChannelMixer::new(2, 2);
+    assert_eq!(mixer.process(&[0.1, 0.2]), [0.1, 0.2]);
+    assert_eq!(mixer.input_samples(4), 4);
+  }
+}
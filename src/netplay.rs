@@ -0,0 +1,253 @@
+use std::{collections::HashMap, io::{ErrorKind, Read, Write}, net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs}, time::Duration};
+
+use log::info;
+
+use crate::{emu::Emulator, input::{GameInput, InputKind}, input_display::DeliveredInputs};
+
+// bit order of the input masks sent over the wire
+const INPUTS: [GameInput; 8] = {
+  use GameInput::*;
+  [Up, Down, Left, Right, A, B, Start, Select]
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// message tags, each followed by a fixed size body
+const MSG_HELLO: u8 = 1;
+const MSG_INPUT: u8 = 2;
+const MSG_HASH: u8 = 3;
+// frames between two comparisons of the players' frames
+const HASH_INTERVAL: u32 = 60;
+
+enum Connection {
+  Listening(TcpListener),
+  Connected(TcpStream),
+}
+
+/// Two player lockstep over TCP: every frame both sides send their input mask,
+/// and a frame is only stepped once the inputs of both players for it have arrived.
+/// Local inputs are sent `delay` frames ahead, which hides that much latency.
+pub struct Netplay {
+  conn: Connection,
+  // 0 on the host, 1 on the client
+  local_player: usize,
+  delay: u32,
+
+  local_rom: Option<u32>,
+  remote_rom: Option<u32>,
+  // the next frame to step, and the next one to send the local input for
+  frame: u32,
+  next_send: u32,
+  local_mask: u8,
+  local_inputs: HashMap<u32, u8>,
+  remote_inputs: HashMap<u32, u8>,
+  // masks last given to the core, per player
+  applied: [u8; 2],
+  // frame hashes by frame, until the other side's for the same frame arrives
+  local_hashes: HashMap<u32, u64>,
+  remote_hashes: HashMap<u32, u64>,
+  // reported once per session
+  desynced: bool,
+
+  tx: Vec<u8>,
+  rx: Vec<u8>,
+}
+
+impl Netplay {
+  fn new(conn: Connection, local_player: usize, delay: u32) -> Self {
+    Self {
+      conn, local_player, delay,
+      local_rom: None, remote_rom: None,
+      frame: 0, next_send: 0,
+      local_mask: 0,
+      local_inputs: HashMap::new(), remote_inputs: HashMap::new(),
+      applied: [0; 2],
+      local_hashes: HashMap::new(), remote_hashes: HashMap::new(), desynced: false,
+      tx: Vec::new(), rx: Vec::new(),
+    }
+  }
+
+  /// Waits for a client on `port`, without blocking.
+  pub fn host(port: u16, delay: u32) -> Result<Self, String> {
+    let listener = TcpListener::bind(("0.0.0.0", port)).map_err(|e| e.to_string())?;
+    listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+    info!("Netplay: waiting for player 2 on port {port}");
+    Ok(Self::new(Connection::Listening(listener), 0, delay))
+  }
+
+  pub fn connect(addr: &str, delay: u32) -> Result<Self, String> {
+    let addr: SocketAddr = addr.to_socket_addrs().map_err(|e| e.to_string())?
+      .next().ok_or("no address to connect to")?;
+    let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).map_err(|e| e.to_string())?;
+    Self::setup_stream(&stream)?;
+    info!("Netplay: connected to {addr}");
+    Ok(Self::new(Connection::Connected(stream), 1, delay))
+  }
+
+  fn setup_stream(stream: &TcpStream) -> Result<(), String> {
+    stream.set_nodelay(true).map_err(|e| e.to_string())?;
+    stream.set_nonblocking(true).map_err(|e| e.to_string())
+  }
+
+  pub fn is_connected(&self) -> bool {
+    matches!(self.conn, Connection::Connected(_))
+  }
+
+  /// Starts a session for the ROM just loaded, both sides must have loaded the same one.
+  pub fn start(&mut self, rom_crc: u32) {
+    self.local_rom = Some(rom_crc);
+    self.frame = 0;
+    self.next_send = 0;
+    self.local_inputs.clear();
+    self.applied = [0; 2];
+    self.local_hashes.clear();
+    self.desynced = false;
+
+    self.tx.push(MSG_HELLO);
+    self.tx.extend_from_slice(&rom_crc.to_le_bytes());
+  }
+
+  pub fn local_input(&mut self, input: GameInput, kind: &InputKind) {
+    let Some(bit) = INPUTS.iter().position(|i| *i == input) else { return };
+    match kind {
+      InputKind::Press => self.local_mask |= 1 << bit,
+      InputKind::Release => self.local_mask &= !(1 << bit),
+    }
+  }
+
+  /// Accepts the peer, sends what's queued and reads what arrived. Never blocks.
+  /// Returns a status message worth showing, or an error once the peer is gone.
+  pub fn poll(&mut self) -> Result<Option<String>, String> {
+    let mut status = None;
+
+    if let Connection::Listening(listener) = &self.conn {
+      match listener.accept() {
+        Ok((stream, addr)) => {
+          Self::setup_stream(&stream)?;
+          self.conn = Connection::Connected(stream);
+          status = Some(format!("Netplay: player 2 joined from {addr}"));
+        }
+        Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+      }
+    }
+    let Connection::Connected(stream) = &mut self.conn else { return Ok(status) };
+
+    while !self.tx.is_empty() {
+      match stream.write(&self.tx) {
+        Ok(0) => return Err("connection closed".into()),
+        Ok(n) => { self.tx.drain(..n); }
+        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+        Err(e) => return Err(e.to_string()),
+      }
+    }
+
+    let mut buf = [0; 1024];
+    loop {
+      match stream.read(&mut buf) {
+        Ok(0) => return Err("the other player disconnected".into()),
+        Ok(n) => self.rx.extend_from_slice(&buf[..n]),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+        Err(e) => return Err(e.to_string()),
+      }
+    }
+
+    let hello = self.parse()?;
+    if hello && self.local_rom.is_some() && self.remote_rom != self.local_rom {
+      status = Some("Netplay: the other player loaded a different ROM".into());
+    }
+    if let Some(frame) = self.check_hashes() {
+      status = Some(format!("Netplay: the games went out of sync at frame {frame}"));
+    }
+    Ok(status)
+  }
+
+  // returns whether a hello arrived
+  fn parse(&mut self) -> Result<bool, String> {
+    let mut hello = false;
+    let mut pos = 0;
+    while let Some(&tag) = self.rx.get(pos) {
+      let body = &self.rx[pos + 1..];
+      match tag {
+        MSG_HELLO if body.len() >= 4 => {
+          self.remote_rom = Some(u32::from_le_bytes(body[..4].try_into().unwrap()));
+          // whatever came before belonged to the previous session
+          self.remote_inputs.clear();
+          self.remote_hashes.clear();
+          hello = true;
+          pos += 5;
+        }
+        MSG_INPUT if body.len() >= 5 => {
+          let frame = u32::from_le_bytes(body[..4].try_into().unwrap());
+          self.remote_inputs.insert(frame, body[4]);
+          pos += 6;
+        }
+        MSG_HASH if body.len() >= 12 => {
+          let frame = u32::from_le_bytes(body[..4].try_into().unwrap());
+          self.remote_hashes.insert(frame, u64::from_le_bytes(body[4..12].try_into().unwrap()));
+          pos += 13;
+        }
+        MSG_HELLO | MSG_INPUT | MSG_HASH => break,
+        _ => return Err(format!("unknown netplay message {tag}")),
+      }
+    }
+    self.rx.drain(..pos);
+    Ok(hello)
+  }
+
+  // compares the frames both sides hashed, returns the first one that differs the first time it happens
+  fn check_hashes(&mut self) -> Option<u32> {
+    let mut frames: Vec<u32> = self.remote_hashes.keys().filter(|frame| self.local_hashes.contains_key(frame)).copied().collect();
+    frames.sort_unstable();
+    let mut desync = None;
+    for frame in frames {
+      if self.local_hashes.remove(&frame) != self.remote_hashes.remove(&frame) {
+        desync = desync.or(Some(frame));
+      }
+    }
+    if self.desynced { return None; }
+    self.desynced = desync.is_some();
+    desync
+  }
+
+  fn apply(&mut self, emu: &mut Emulator, inputs: &mut DeliveredInputs, player: usize, mask: u8) {
+    let changed = self.applied[player] ^ mask;
+    for (bit, input) in INPUTS.iter().enumerate().filter(|(bit, _)| changed & (1 << bit) != 0) {
+      let kind = if mask & (1 << bit) != 0 { InputKind::Press } else { InputKind::Release };
+      inputs.send_exact(emu, player, input, kind);
+    }
+    self.applied[player] = mask;
+  }
+
+  /// Steps the next frame if both inputs for it are there, returns whether it did.
+  pub fn step(&mut self, emu: &mut Emulator, inputs: &mut DeliveredInputs) -> bool {
+    if !self.is_connected() || self.local_rom.is_none() || self.remote_rom != self.local_rom {
+      return false;
+    }
+
+    // the local input lands `delay` frames from now
+    while self.next_send <= self.frame + self.delay {
+      self.local_inputs.insert(self.next_send, self.local_mask);
+      self.tx.push(MSG_INPUT);
+      self.tx.extend_from_slice(&self.next_send.to_le_bytes());
+      self.tx.push(self.local_mask);
+      self.next_send += 1;
+    }
+
+    let Some(remote) = self.remote_inputs.remove(&self.frame) else { return false };
+    let local = self.local_inputs.remove(&self.frame).unwrap_or(0);
+
+    self.apply(emu, inputs, self.local_player, local);
+    self.apply(emu, inputs, 1 - self.local_player, remote);
+    emu.step_one_frame();
+    if self.frame.is_multiple_of(HASH_INTERVAL) {
+      let hash = emu.frame_hash();
+      self.local_hashes.insert(self.frame, hash);
+      self.tx.push(MSG_HASH);
+      self.tx.extend_from_slice(&self.frame.to_le_bytes());
+      self.tx.extend_from_slice(&hash.to_le_bytes());
+    }
+    self.frame += 1;
+    true
+  }
+}
@@ -0,0 +1,102 @@
+use std::f32::consts::TAU;
+
+use crate::emu::EmuPixelFormat;
+
+/// Width of the filtered image, for the NES' 256 pixels.
+pub const NTSC_WIDTH: usize = 602;
+// one color subcarrier cycle lasts 1.5 NES pixels, in output samples
+const SUBCARRIER_PERIOD: f32 = 1.5 * NTSC_WIDTH as f32 / 256.0;
+// the subcarrier phase moves by a third of a cycle every line
+const LINE_PHASES: usize = 3;
+// samples averaged to separate luma, about a subcarrier cycle
+const LUMA_WINDOW: isize = 4;
+// chroma has less bandwidth than luma, so it's averaged over longer
+const CHROMA_WINDOW: isize = 8;
+
+/// Simplified composite video: each line is encoded as a luma + modulated chroma signal,
+/// then decoded again with box filters. The imperfect separation gives the color fringing,
+/// dot crawl and softened edges of a real NTSC signal.
+pub struct Ntsc {
+  frame: usize,
+  // (cos, sin) of the subcarrier for every sample, one row per line phase
+  carrier: Vec<(f32, f32)>,
+  signal: Vec<f32>,
+  chroma: Vec<f32>,
+  luma: Vec<f32>,
+  out: Vec<u8>,
+}
+
+fn window_mean(data: &[f32], center: usize, window: isize, value: impl Fn(usize) -> f32) -> f32 {
+  let start = (center as isize - window / 2).max(0) as usize;
+  let end = (center as isize + window / 2).min(data.len() as isize) as usize;
+  (start..end).map(value).sum::<f32>() / (end - start) as f32
+}
+
+impl Ntsc {
+  pub fn new() -> Self {
+    let carrier = (0..LINE_PHASES).flat_map(|line| (0..NTSC_WIDTH).map(move |x| {
+      let phase = x as f32 * TAU / SUBCARRIER_PERIOD + line as f32 * TAU / LINE_PHASES as f32;
+      (phase.cos(), phase.sin())
+    })).collect();
+
+    Self {
+      frame: 0,
+      carrier,
+      signal: vec![0.0; NTSC_WIDTH],
+      chroma: vec![0.0; NTSC_WIDTH],
+      luma: vec![0.0; NTSC_WIDTH],
+      out: Vec::new(),
+    }
+  }
+
+  /// Filters `framebuf`, of pixels in `format`, returning a RGBA32 image NTSC_WIDTH wide with the same height.
+  pub fn apply(&mut self, framebuf: &[u8], pitch: usize, (width, height): (usize, usize), format: EmuPixelFormat) -> &[u8] {
+    let bpp = format.bytes_per_pixel();
+    self.out.resize(NTSC_WIDTH * 4 * height, 0);
+    // the phase pattern alternates between frames, like on the console
+    self.frame = (self.frame + 1) % 2;
+
+    for y in 0..height {
+      let row = &framebuf[y * pitch..y * pitch + width * bpp];
+      let line = (y + self.frame) % LINE_PHASES;
+      let carrier = &self.carrier[line * NTSC_WIDTH..(line + 1) * NTSC_WIDTH];
+
+      for (x, sample) in self.signal.iter_mut().enumerate() {
+        let src = x * width / NTSC_WIDTH * bpp;
+        let [r, g, b] = format.rgb(&row[src..]).map(|c| c as f32 / 255.0);
+
+        let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+        let i = 0.596 * r - 0.274 * g - 0.322 * b;
+        let q = 0.211 * r - 0.523 * g + 0.312 * b;
+        let (cos, sin) = carrier[x];
+        *sample = luma + i * cos + q * sin;
+      }
+
+      let signal = &self.signal;
+      for x in 0..NTSC_WIDTH {
+        self.luma[x] = window_mean(signal, x, LUMA_WINDOW, |k| signal[k]);
+        self.chroma[x] = signal[x] - self.luma[x];
+      }
+
+      let out = &mut self.out[y * NTSC_WIDTH * 4..(y + 1) * NTSC_WIDTH * 4];
+      let chroma = &self.chroma;
+      for (x, rgba) in out.chunks_exact_mut(4).enumerate() {
+        let i = 2.0 * window_mean(chroma, x, CHROMA_WINDOW, |k| chroma[k] * carrier[k].0);
+        let q = 2.0 * window_mean(chroma, x, CHROMA_WINDOW, |k| chroma[k] * carrier[k].1);
+        let luma = self.luma[x];
+
+        let rgb = [
+          luma + 0.956 * i + 0.621 * q,
+          luma - 0.272 * i - 0.647 * q,
+          luma - 1.106 * i + 1.703 * q,
+        ];
+        for (out, value) in rgba.iter_mut().zip(rgb) {
+          *out = (value.clamp(0.0, 1.0) * 255.0) as u8;
+        }
+        rgba[3] = 0xff;
+      }
+    }
+
+    &self.out
+  }
+}
@@ -0,0 +1,158 @@
+use std::{collections::VecDeque, time::{Duration, Instant}};
+
+use font8x8::legacy::BASIC_LEGACY;
+use log::info;
+use sdl2::{pixels::Color, rect::Rect, render::{BlendMode, Canvas}, video::Window};
+
+pub const GLYPH_SIZE: i32 = 8;
+
+fn draw_glyphs(canvas: &mut Canvas<Window>, x: i32, y: i32, scale: i32, text: &str, color: Color) {
+  let mut rects = Vec::new();
+
+  for (i, ch) in text.chars().enumerate() {
+    let glyph = BASIC_LEGACY.get(ch as usize).unwrap_or(&BASIC_LEGACY['?' as usize]);
+    let glyph_x = x + i as i32 * GLYPH_SIZE * scale;
+
+    for (row, bits) in glyph.iter().enumerate() {
+      // the leftmost pixel is the lowest bit
+      for col in (0..8).filter(|col| (bits >> col) & 1 == 1) {
+        rects.push(Rect::new(glyph_x + col * scale, y + row as i32 * scale, scale as u32, scale as u32));
+      }
+    }
+  }
+
+  canvas.set_draw_color(color);
+  let _ = canvas.fill_rects(&rects);
+}
+
+/// Draws text with the bitmap font, with a drop shadow so it stays readable on any background.
+pub fn draw_text(canvas: &mut Canvas<Window>, x: i32, y: i32, scale: i32, text: &str, color: Color) {
+  let prev_color = canvas.draw_color();
+  draw_glyphs(canvas, x + scale, y + scale, scale, text, Color::BLACK);
+  draw_glyphs(canvas, x, y, scale, text, color);
+  canvas.set_draw_color(prev_color);
+}
+
+/// Runs `draw` with the logical size disabled, so overlays are drawn in window pixels over the scaled game.
+pub fn in_window_coords(canvas: &mut Canvas<Window>, draw: impl FnOnce(&mut Canvas<Window>)) {
+  let (width, height) = canvas.logical_size();
+  let _ = canvas.set_logical_size(0, 0);
+  draw(canvas);
+  let _ = canvas.set_logical_size(width, height);
+}
+
+/// Darkens the game image and labels it as paused. Only the presented copy is dimmed.
+pub fn draw_paused(canvas: &mut Canvas<Window>) {
+  const SCALE: i32 = 4;
+  const LABEL: &str = "PAUSED";
+
+  let prev_color = canvas.draw_color();
+  let prev_blend = canvas.blend_mode();
+  canvas.set_blend_mode(BlendMode::Blend);
+  canvas.set_draw_color(Color::RGBA(0, 0, 0, 128));
+  let _ = canvas.fill_rect(None);
+  canvas.set_blend_mode(prev_blend);
+  canvas.set_draw_color(prev_color);
+
+  in_window_coords(canvas, |canvas| {
+    let (width, height) = canvas.output_size().unwrap_or_default();
+    let label_width = LABEL.len() as i32 * GLYPH_SIZE * SCALE;
+    let x = (width as i32 - label_width) / 2;
+    let y = (height as i32 - GLYPH_SIZE * SCALE) / 2;
+    draw_text(canvas, x, y, SCALE, LABEL, Color::WHITE);
+  });
+}
+
+/// Fills the screen shown while no game is loaded.
+pub fn draw_empty(canvas: &mut Canvas<Window>) {
+  const SCALE: i32 = 2;
+  const LABEL: &str = "Drop a ROM here";
+
+  let prev_color = canvas.draw_color();
+  canvas.set_draw_color(Color::RGB(0x20, 0x20, 0x30));
+  let _ = canvas.fill_rect(None);
+  canvas.set_draw_color(prev_color);
+
+  in_window_coords(canvas, |canvas| {
+    let (width, height) = canvas.output_size().unwrap_or_default();
+    let label_width = LABEL.len() as i32 * GLYPH_SIZE * SCALE;
+    let x = (width as i32 - label_width) / 2;
+    let y = (height as i32 - GLYPH_SIZE * SCALE) / 2;
+    draw_text(canvas, x, y, SCALE, LABEL, Color::GRAY);
+  });
+}
+
+pub struct Stats {
+  pub visible: bool,
+  frames: u32,
+  since: Instant,
+  fps: f32,
+}
+impl Default for Stats {
+  fn default() -> Self {
+    Self { visible: false, frames: 0, since: Instant::now(), fps: 0.0 }
+  }
+}
+
+impl Stats {
+  /// Called once per presented frame.
+  pub fn frame(&mut self) {
+    self.frames += 1;
+    let elapsed = self.since.elapsed();
+    if elapsed >= Duration::from_secs(1) {
+      self.fps = self.frames as f32 / elapsed.as_secs_f32();
+      self.frames = 0;
+      self.since = Instant::now();
+    }
+  }
+
+  pub fn draw(&self, canvas: &mut Canvas<Window>, lines: &[String]) {
+    if !self.visible { return; }
+
+    const SCALE: i32 = 2;
+    in_window_coords(canvas, |canvas| {
+      let fps = format!("{:.1} FPS", self.fps);
+      for (i, line) in std::iter::once(&fps).chain(lines).enumerate() {
+        let y = 4 + i as i32 * (GLYPH_SIZE + 2) * SCALE;
+        draw_text(canvas, 4, y, SCALE, line, Color::WHITE);
+      }
+    });
+  }
+}
+
+const MESSAGE_DURATION: Duration = Duration::from_secs(3);
+const MAX_MESSAGES: usize = 4;
+
+/// Short lived messages, stacked in the bottom left corner.
+#[derive(Default)]
+pub struct Osd {
+  messages: VecDeque<(String, Instant)>,
+}
+
+impl Osd {
+  pub fn message(&mut self, text: impl Into<String>) {
+    let text = text.into();
+    info!("{text}");
+
+    if self.messages.len() == MAX_MESSAGES {
+      self.messages.pop_front();
+    }
+    self.messages.push_back((text, Instant::now()));
+  }
+
+  pub fn draw(&mut self, canvas: &mut Canvas<Window>) {
+    self.messages.retain(|(_, since)| since.elapsed() < MESSAGE_DURATION);
+    if self.messages.is_empty() { return; }
+
+    const SCALE: i32 = 2;
+    in_window_coords(canvas, |canvas| {
+      let (_, height) = canvas.output_size().unwrap_or_default();
+      let line_height = (GLYPH_SIZE + 2) * SCALE;
+      let bottom = height as i32 - 4 - line_height * self.messages.len() as i32;
+
+      for (i, (text, _)) in self.messages.iter().enumerate() {
+        draw_text(canvas, 4, bottom + i as i32 * line_height, SCALE, text, Color::WHITE);
+      }
+    });
+  }
+}
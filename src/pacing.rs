@@ -0,0 +1,88 @@
+use std::time::{Duration, Instant};
+
+// Sleeping is only trusted up to this close to the deadline, the rest is spent spinning
+const SPIN_MARGIN: Duration = Duration::from_micros(1500);
+
+/// Paces the loop to a fixed frame duration, with deadlines accumulating from the first one
+/// so that oversleeping on one frame is made up on the next instead of compounding.
+pub struct FramePacer {
+  frame: Duration,
+  next: Instant,
+  // smoothed distance between the deadline and the actual wake up
+  jitter: Duration,
+}
+
+impl FramePacer {
+  pub fn new(frame: Duration) -> Self {
+    Self { frame, next: Instant::now() + frame, jitter: Duration::ZERO }
+  }
+
+  pub fn set_frame(&mut self, frame: Duration) {
+    self.frame = frame;
+    self.next = Instant::now() + frame;
+  }
+
+  pub fn jitter(&self) -> Duration { self.jitter }
+
+  /// Returns the deadline to wait for, and moves on to the next one.
+  /// If we fell more than a frame behind, the schedule restarts from now rather than rushing to catch up.
+  fn advance(&mut self, now: Instant) -> Instant {
+    let deadline = self.next;
+    if now > deadline + self.frame {
+      self.next = now + self.frame;
+      now
+    } else {
+      self.next += self.frame;
+      deadline
+    }
+  }
+
+  pub fn wait(&mut self) {
+    let deadline = self.advance(Instant::now());
+
+    let now = Instant::now();
+    if deadline > now + SPIN_MARGIN {
+      std::thread::sleep(deadline - now - SPIN_MARGIN);
+    }
+    while Instant::now() < deadline {
+      std::hint::spin_loop();
+    }
+
+    let error = Instant::now() - deadline;
+    self.jitter = self.jitter.mul_f32(0.9) + error.mul_f32(0.1);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const FRAME: Duration = Duration::from_millis(16);
+
+  #[test]
+  fn deadlines_accumulate() {
+    let mut pacer = FramePacer::new(FRAME);
+    let start = pacer.next;
+
+    // waking up late on a frame doesn't push back the ones after it
+    assert_eq!(pacer.advance(start), start);
+    assert_eq!(pacer.advance(start + FRAME + Duration::from_millis(3)), start + FRAME);
+    assert_eq!(pacer.advance(start + FRAME * 2), start + FRAME * 2);
+    assert_eq!(pacer.next, start + FRAME * 3);
+  }
+
+  #[test]
+  fn restarts_when_more_than_a_frame_behind() {
+    let mut pacer = FramePacer::new(FRAME);
+    let start = pacer.next;
+
+    // exactly a frame behind is still caught up with
+    assert_eq!(pacer.advance(start + FRAME), start);
+    assert_eq!(pacer.next, start + FRAME);
+
+    let late = start + FRAME * 10;
+    assert_eq!(pacer.advance(late), late);
+    assert_eq!(pacer.next, late + FRAME);
+    assert_eq!(pacer.advance(late + FRAME), late + FRAME);
+  }
+}
@@ -0,0 +1,22 @@
+// Four shades, lightest first, as the Game Boy LCD indexes them
+pub type GbPalette = [[u8; 3]; 4];
+
+pub const GB_PALETTES: [(&str, GbPalette); 4] = [
+  ("Classic green",  [[0x9b, 0xbc, 0x0f], [0x8b, 0xac, 0x0f], [0x30, 0x62, 0x30], [0x0f, 0x38, 0x0f]]),
+  ("Grey",           [[0xff, 0xff, 0xff], [0xaa, 0xaa, 0xaa], [0x55, 0x55, 0x55], [0x00, 0x00, 0x00]]),
+  ("Pocket",         [[0xc4, 0xcf, 0xa1], [0x8b, 0x95, 0x6d], [0x4d, 0x53, 0x3c], [0x1f, 0x1f, 0x1f]]),
+  ("Super Game Boy", [[0xf7, 0xe7, 0xc6], [0xd6, 0x8e, 0x49], [0xa6, 0x37, 0x25], [0x33, 0x1e, 0x50]]),
+];
+
+// For the bars around the image when the window's aspect differs from the game's
+pub const BACKGROUNDS: [(&str, [u8; 3]); 4] = [
+  ("Dark grey", [0x20, 0x20, 0x20]),
+  ("Black",     [0x00, 0x00, 0x00]),
+  ("Grey",      [0x60, 0x60, 0x60]),
+  ("Navy",      [0x10, 0x14, 0x30]),
+];
+
+/// Index of the preset called `name`, the first one if there's none.
+pub fn gb_palette_index(name: &str) -> usize {
+  GB_PALETTES.iter().position(|(preset, _)| preset.eq_ignore_ascii_case(name)).unwrap_or(0)
+}
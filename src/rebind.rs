@@ -0,0 +1,181 @@
+use sdl2::{controller::Axis, event::Event, keyboard::{Keycode, Mod}};
+use log::{error, info};
+
+use crate::{emu_thread::Command, input::{is_modifier, AxisDir, Chord, GameInput, InputKind}, EmuContext};
+
+// what Page Up and Page Down change the dead zone by
+const DEAD_ZONE_STEP: i16 = 1_000;
+
+const REBIND_ORDER: [GameInput; 8] = {
+  use GameInput::*;
+  [Up, Down, Left, Right, A, B, Start, Select]
+};
+
+enum RebindDevice {
+  Keyboard,
+  // the controller name, which is also its mapping profile
+  Controller(u32, String),
+  // one without a controller mapping, they all share the joystick bindings
+  Joystick(u32),
+}
+
+pub struct Rebind {
+  next: usize,
+  // the first device to answer owns the whole rebinding session
+  device: Option<RebindDevice>,
+  // the axis captured last, ignored until it goes back to the center
+  held_axis: Option<Axis>,
+  // the axis moved last, the one whose dead zone can be changed
+  shown_axis: Option<(u32, Axis)>,
+  // a modifier key pressed on its own, bound when it's let go of unless it was part of a chord
+  pending_modifier: Option<Keycode>,
+  // whose bindings change, counted from 0. Player 2 only plays on the keyboard
+  player: usize,
+}
+
+fn prompt(player: usize, input: GameInput) {
+  match player {
+    0 => info!("Rebinding: press a key or button for {input:?} (Escape to cancel)"),
+    _ => info!("Rebinding player 2: press a key for {input:?} (Escape to cancel)"),
+  }
+}
+
+pub fn start(ctx: &mut EmuContext) {
+  // the releases go to the rebinding from now on, what's held would stay held
+  for input in ctx.keys.player2_held() {
+    ctx.emu.send(Command::PlayerInput(1, input, InputKind::Release));
+  }
+  for input in ctx.keys.release_all() {
+    ctx.emu.send(Command::Input(input, InputKind::Release));
+  }
+  ctx.rebind = Some(Rebind { next: 0, device: None, held_axis: None, shown_axis: None, pending_modifier: None, player: 0 });
+  prompt(0, REBIND_ORDER[0]);
+  info!("Tab switches to the other player's keyboard bindings before the first one is bound");
+  info!("Move a stick to see its position, Page Up and Page Down change its dead zone");
+}
+
+// player 2's keys are bound without their modifiers
+fn bind_key(ctx: &mut EmuContext, player: usize, chord: Chord, input: GameInput) {
+  match player {
+    0 => ctx.keys.bind_key(chord, input),
+    _ => ctx.keys.bind_key_player2(chord.key(), input),
+  }
+}
+
+fn advance(ctx: &mut EmuContext) {
+  let Some(rebind) = &mut ctx.rebind else { return };
+  rebind.next += 1;
+
+  if let Some(input) = REBIND_ORDER.get(rebind.next) {
+    prompt(rebind.player, *input);
+  } else {
+    ctx.rebind = None;
+    ctx.config.input = ctx.keys.to_config();
+    match ctx.config.save() {
+      Ok(_) => info!("Bindings saved"),
+      Err(msg) => error!("Couldn't save bindings: {msg}"),
+    }
+  }
+}
+
+fn is_direction(input: GameInput) -> bool {
+  matches!(input, GameInput::Up | GameInput::Down | GameInput::Left | GameInput::Right)
+}
+
+pub fn handle_input(ctx: &mut EmuContext, event: &Event) {
+  let Some(rebind) = &mut ctx.rebind else { return };
+  let input = REBIND_ORDER[rebind.next];
+
+  match (event, &rebind.device) {
+    (Event::KeyDown { keycode: Some(Keycode::Escape), .. }, _) => {
+      // bindings changed so far are kept for this session, but not persisted
+      ctx.rebind = None;
+      info!("Rebinding cancelled");
+    }
+
+    (Event::KeyDown { keycode: Some(Keycode::Tab), repeat: false, .. }, None) if rebind.next == 0 => {
+      rebind.player = 1 - rebind.player;
+      rebind.pending_modifier = None;
+      ctx.osd.message(format!("Rebinding player {}", rebind.player + 1));
+      prompt(rebind.player, input);
+    }
+    // controllers play as player 1
+    (Event::ControllerButtonDown { .. } | Event::ControllerAxisMotion { .. } | Event::JoyButtonDown { .. }, _) if rebind.player != 0 => {}
+
+    (Event::KeyDown { keycode: Some(keycode @ (Keycode::PageUp | Keycode::PageDown)), .. }, _) => {
+      let Some((which, axis)) = rebind.shown_axis else { return };
+      let step = if *keycode == Keycode::PageUp { DEAD_ZONE_STEP } else { -DEAD_ZONE_STEP };
+      let profile = ctx.keys.pad_name(which).cloned().unwrap_or_default();
+      let dead_zone = ctx.keys.dead_zone(which, axis).saturating_add(step);
+      let dead_zone = ctx.keys.set_dead_zone(&profile, axis, dead_zone);
+      ctx.osd.message(format!("{axis:?} dead zone {dead_zone}"));
+    }
+
+    (Event::KeyDown { keycode: Some(keycode), repeat: false, .. }, None | Some(RebindDevice::Keyboard)) if is_modifier(*keycode) => {
+      rebind.pending_modifier = Some(*keycode);
+    }
+    (Event::KeyUp { keycode: Some(keycode), .. }, None | Some(RebindDevice::Keyboard)) if rebind.pending_modifier == Some(*keycode) => {
+      rebind.pending_modifier = None;
+      rebind.device = Some(RebindDevice::Keyboard);
+      let player = rebind.player;
+      bind_key(ctx, player, Chord::from_event(*keycode, Mod::NOMOD), input);
+      advance(ctx);
+    }
+    (Event::KeyDown { keycode: Some(keycode), keymod, repeat: false, .. }, None | Some(RebindDevice::Keyboard)) => {
+      rebind.pending_modifier = None;
+      rebind.device = Some(RebindDevice::Keyboard);
+      let player = rebind.player;
+      bind_key(ctx, player, Chord::from_event(*keycode, *keymod), input);
+      advance(ctx);
+    }
+
+    (Event::ControllerButtonDown { which, button, .. }, None) => {
+      let profile = ctx.keys.pad_name(*which).cloned().unwrap_or_default();
+      ctx.keys.bind_button(&profile, *button, input);
+      rebind.device = Some(RebindDevice::Controller(*which, profile));
+      advance(ctx);
+    }
+    (Event::ControllerButtonDown { which, button, .. }, Some(RebindDevice::Controller(id, profile)))
+    if which == id => {
+      ctx.keys.bind_button(profile, *button, input);
+      advance(ctx);
+    }
+
+    (Event::JoyButtonDown { which, button_idx, .. }, None) if ctx.keys.is_joystick(*which) => {
+      ctx.keys.bind_joy_button(*button_idx, input);
+      rebind.device = Some(RebindDevice::Joystick(*which));
+      advance(ctx);
+    }
+    (Event::JoyButtonDown { which, button_idx, .. }, Some(RebindDevice::Joystick(id))) if which == id => {
+      ctx.keys.bind_joy_button(*button_idx, input);
+      advance(ctx);
+    }
+
+    (Event::ControllerAxisMotion { which, axis, value, .. }, device) => {
+      // the live position, to pick a dead zone that's past the drift but not too far
+      let dead_zone = ctx.keys.dead_zone(*which, *axis);
+      ctx.osd.message(format!("{axis:?} at {value}, dead zone {dead_zone}"));
+      rebind.shown_axis = Some((*which, *axis));
+
+      if rebind.held_axis == Some(*axis) {
+        if value.unsigned_abs() < dead_zone as u16 { rebind.held_axis = None; }
+        return;
+      }
+
+      if value.unsigned_abs() < dead_zone as u16 || !is_direction(input) { return; }
+
+      let profile = match device {
+        None => ctx.keys.pad_name(*which).cloned().unwrap_or_default(),
+        Some(RebindDevice::Controller(id, profile)) if id == which => profile.clone(),
+        _ => return,
+      };
+
+      let axis_dir = AxisDir { axis: *axis, positive: *value > 0 };
+      ctx.keys.bind_axis(&profile, axis_dir, input);
+      rebind.held_axis = Some(*axis);
+      rebind.device = Some(RebindDevice::Controller(*which, profile));
+      advance(ctx);
+    }
+    _ => {}
+  }
+}
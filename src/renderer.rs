@@ -0,0 +1,169 @@
+use std::error::Error;
+
+use sdl2::{pixels::Color, rect::Rect, render::{Canvas, Texture, TextureCreator}, video::{Window, WindowContext}};
+
+use crate::{emu::EmuPixelFormat, osd::draw_empty, scope::Scope};
+
+/// Which backend draws the window, picked at startup with --renderer.
+#[derive(Clone, Copy, Default, Debug)]
+pub enum RendererKind {
+  // SDL's 2d renderer
+  #[default]
+  Sdl,
+}
+impl RendererKind {
+  pub const NAMES: [&str; 1] = ["sdl"];
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "sdl" => Some(Self::Sdl),
+      _ => None,
+    }
+  }
+}
+
+/// How the image is stretched to the window.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ScalingMode {
+  Nearest,
+  Linear,
+}
+
+/// What the main loop needs to show the game. The window and its events stay SDL's whatever the backend.
+pub trait Renderer {
+  /// The driver doing the drawing, for the logs and the stats.
+  fn name(&self) -> &'static str;
+  fn window(&self) -> &Window;
+  fn window_mut(&mut self) -> &mut Window;
+
+  /// Size of what's drawn on, in pixels. Larger than the window's own size on high-DPI displays.
+  fn output_size(&self) -> (u32, u32);
+  /// Sets the size of the area the image and overlays are laid out in, which is scaled to fit the window.
+  fn resize_output(&mut self, width: u32, height: u32);
+  /// Replaces the image with `frame`, `size` pixels in `format` with rows `pitch` bytes apart.
+  fn upload_frame(&mut self, frame: &[u8], pitch: usize, size: (usize, usize), format: EmuPixelFormat, scope: Option<&Scope>) -> Result<(), String>;
+  /// Starts a new frame filled with `background`, showing the last image at `dst` turned by `rotation` degrees around its center.
+  /// With no image, the placeholder for when no game is loaded is shown.
+  // src is the part of the uploaded image shown
+  fn draw_frame(&mut self, src: Rect, dst: Option<Rect>, rotation: u16, background: Color);
+  fn present(&mut self);
+  fn set_scaling_mode(&mut self, mode: ScalingMode);
+  // false when the backend can't change it
+  fn set_vsync(&mut self, vsync: bool) -> bool;
+
+  /// What the overlays and menus are drawn on, on top of the image.
+  fn overlay(&mut self) -> &mut Canvas<Window>;
+}
+
+/// With `software`, drawing happens on the CPU, for machines without a working GPU driver.
+pub fn create(kind: RendererKind, window: Window, software: bool) -> Result<Box<dyn Renderer>, Box<dyn Error>> {
+  match kind {
+    RendererKind::Sdl => Ok(Box::new(SdlRenderer::new(window, software)?)),
+  }
+}
+
+/// Writes the image straight into the locked texture, row by row since the two pitches can differ.
+/// The waveform goes on top while the texture is still locked, as its old content can't be read back later.
+pub fn upload_texture(texture: &mut Texture, framebuf: &[u8], pitch: usize, format: EmuPixelFormat, scope: Option<&Scope>) -> Result<(), String> {
+  let query = texture.query();
+  let row_len = query.width as usize * format.bytes_per_pixel();
+
+  texture.with_lock(None, |dst, dst_pitch| {
+    for (dst_row, src_row) in dst.chunks_mut(dst_pitch).zip(framebuf.chunks(pitch)).take(query.height as usize) {
+      dst_row[..row_len].copy_from_slice(&src_row[..row_len]);
+    }
+    if let Some(scope) = scope {
+      scope.draw(dst, dst_pitch, (query.width as usize, query.height as usize), format);
+    }
+  })
+}
+
+pub struct SdlRenderer {
+  canvas: Canvas<Window>,
+  // lives as long as the program, so the texture can be kept next to the canvas
+  creator: &'static TextureCreator<WindowContext>,
+  texture: Option<Texture<'static>>,
+  scaling: ScalingMode,
+  // in pixels, the layout of the logical size is redone when it changes
+  output: (u32, u32),
+}
+
+impl SdlRenderer {
+  pub fn new(window: Window, software: bool) -> Result<Self, Box<dyn Error>> {
+    let builder = window.into_canvas();
+    let builder = if software { builder.software() } else { builder.accelerated() };
+    let canvas = builder.build()?;
+    let creator = Box::leak(Box::new(canvas.texture_creator()));
+    Ok(Self { canvas, creator, texture: None, scaling: ScalingMode::Nearest, output: (0, 0) })
+  }
+
+  fn apply_scaling(&mut self) {
+    let Some(texture) = &self.texture else { return };
+    let mode = match self.scaling {
+      ScalingMode::Nearest => sdl2::sys::SDL_ScaleMode::SDL_ScaleModeNearest,
+      ScalingMode::Linear => sdl2::sys::SDL_ScaleMode::SDL_ScaleModeLinear,
+    };
+    unsafe { sdl2::sys::SDL_SetTextureScaleMode(texture.raw(), mode) };
+  }
+}
+
+impl Renderer for SdlRenderer {
+  fn name(&self) -> &'static str { self.canvas.info().name }
+  fn window(&self) -> &Window { self.canvas.window() }
+  fn window_mut(&mut self) -> &mut Window { self.canvas.window_mut() }
+
+  fn output_size(&self) -> (u32, u32) {
+    self.canvas.output_size().unwrap_or_else(|_| self.canvas.window().size())
+  }
+
+  fn resize_output(&mut self, width: u32, height: u32) {
+    // moving to a display with another pixel density changes the output but not the window size
+    let output = self.output_size();
+    if self.canvas.logical_size() != (width, height) || self.output != output {
+      self.output = output;
+      let _ = self.canvas.set_logical_size(width, height);
+    }
+  }
+
+  fn upload_frame(&mut self, frame: &[u8], pitch: usize, (width, height): (usize, usize), format: EmuPixelFormat, scope: Option<&Scope>) -> Result<(), String> {
+    // filters can change the size of the image, and a new game its resolution and pixel format
+    let fits = self.texture.as_ref().is_some_and(|texture| {
+      let query = texture.query();
+      (query.width, query.height, query.format) == (width as u32, height as u32, format.sdl_format())
+    });
+    if !fits {
+      let texture = self.creator
+        .create_texture_streaming(format.sdl_format(), width as u32, height as u32)
+        .map_err(|e| e.to_string())?;
+      self.texture = Some(texture);
+      self.apply_scaling();
+    }
+
+    let texture = self.texture.as_mut().unwrap();
+    upload_texture(texture, frame, pitch, format, scope)
+  }
+
+  fn draw_frame(&mut self, src: Rect, dst: Option<Rect>, rotation: u16, background: Color) {
+    // the overlays leave any color set, the bars have to be cleared with this one
+    self.canvas.set_draw_color(background);
+    self.canvas.clear();
+    match (dst, &self.texture) {
+      (Some(dst), Some(texture)) => self.canvas.copy_ex(texture, src, dst, rotation as f64, None, false, false).unwrap(),
+      _ => draw_empty(&mut self.canvas),
+    }
+  }
+
+  fn present(&mut self) { self.canvas.present(); }
+
+  fn set_scaling_mode(&mut self, mode: ScalingMode) {
+    self.scaling = mode;
+    self.apply_scaling();
+  }
+
+  /// Toggles vsync on the existing renderer, so the canvas and its textures don't have to be rebuilt.
+  fn set_vsync(&mut self, vsync: bool) -> bool {
+    unsafe { sdl2::sys::SDL_RenderSetVSync(self.canvas.raw(), vsync as i32) == 0 }
+  }
+
+  fn overlay(&mut self) -> &mut Canvas<Window> { &mut self.canvas }
+}
@@ -0,0 +1,172 @@
+use std::time::Duration;
+
+// Maximum deviation from the nominal rate, small enough to be inaudible
+pub const MAX_RATE_DELTA: f64 = 0.005;
+
+/// Linear interpolation resampler over interleaved samples.
+/// The fractional position is kept across calls, so buffers of any size can be fed in a stream.
+pub struct Resampler {
+  channels: usize,
+  out_rate: u32,
+  // dynamic rate control: the output rate is nudged so the audio queue converges on this latency
+  target_latency: Duration,
+  // input frames consumed for every output frame, before rate control
+  base_ratio: f64,
+  ratio: f64,
+  // position of the next output frame, relative to `last`
+  pos: f64,
+  // last input frame of the previous buffer, interpolated against the first of the next one
+  last: Vec<f32>,
+  out: Vec<f32>,
+}
+
+impl Resampler {
+  pub fn new(in_rate: u32, out_rate: u32, channels: usize, target_latency: Duration) -> Self {
+    let channels = channels.max(1);
+    let ratio = in_rate as f64 / out_rate as f64;
+    Self {
+      channels,
+      out_rate,
+      target_latency,
+      base_ratio: ratio,
+      ratio,
+      pos: 0.0,
+      last: vec![0.0; channels],
+      out: Vec::new(),
+    }
+  }
+
+  pub fn channels(&self) -> usize { self.channels }
+
+  pub fn target_latency(&self) -> Duration { self.target_latency }
+  pub fn set_target_latency(&mut self, latency: Duration) { self.target_latency = latency; }
+
+  /// Number of output frames the queue should hold.
+  pub fn target_frames(&self) -> usize {
+    (self.out_rate as f64 * self.target_latency.as_secs_f64()) as usize
+  }
+
+  /// Input samples, all channels counted, that make `frames` output frames at the nominal rate.
+  pub fn input_samples(&self, frames: usize) -> usize {
+    (frames as f64 * self.base_ratio).ceil() as usize * self.channels
+  }
+
+  /// Adjusts the ratio from how many output frames are still queued:
+  /// a queue below target gets slightly more output, a queue above it slightly less.
+  pub fn update_rate(&mut self, queued_frames: usize) {
+    let target = self.target_frames().max(1) as f64;
+    let fill = (queued_frames as f64 / target).min(2.0);
+    let delta = ((fill - 1.0) * MAX_RATE_DELTA).clamp(-MAX_RATE_DELTA, MAX_RATE_DELTA);
+    self.ratio = self.base_ratio * (1.0 + delta);
+  }
+
+  pub fn process(&mut self, input: &[f32]) -> &[f32] {
+    let channels = self.channels;
+    let frames = input.len() / channels;
+    self.out.clear();
+
+    // frame 0 is the previous buffer's last frame, the input follows
+    fn frame<'a>(i: usize, last: &'a [f32], input: &'a [f32], channels: usize) -> &'a [f32] {
+      if i == 0 { last } else { &input[(i-1)*channels..i*channels] }
+    }
+
+    while self.pos < frames as f64 {
+      let i = self.pos as usize;
+      let t = (self.pos - i as f64) as f32;
+      let a = frame(i, &self.last, input, channels);
+      let b = frame(i+1, &self.last, input, channels);
+
+      for ch in 0..channels {
+        self.out.push(a[ch] + (b[ch] - a[ch]) * t);
+      }
+      self.pos += self.ratio;
+    }
+
+    if frames > 0 {
+      self.pos -= frames as f64;
+      let start = (frames-1) * channels;
+      self.last.copy_from_slice(&input[start..start+channels]);
+    }
+
+    &self.out
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sine(freq: f64, rate: u32, frames: usize) -> Vec<f32> {
+    (0..frames).map(|i| (2.0 * std::f64::consts::PI * freq * i as f64 / rate as f64).sin() as f32).collect()
+  }
+
+  // rising zero crossings per second
+  fn frequency(samples: &[f32], rate: u32) -> f64 {
+    let crossings = samples.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+    crossings as f64 * rate as f64 / samples.len() as f64
+  }
+
+  #[test]
+  fn sine_keeps_frequency_and_amplitude() {
+    let input = sine(440.0, 44100, 44100);
+    let mut resampler = Resampler::new(44100, 48000, 1, Duration::from_millis(50));
+
+    // fed a frame's worth at a time, the position carries over between buffers
+    let mut out = Vec::new();
+    for chunk in input.chunks(735) {
+      out.extend_from_slice(resampler.process(chunk));
+    }
+
+    assert!((out.len() as i64 - 48000).abs() <= 1, "{} frames", out.len());
+    let freq = frequency(&out, 48000);
+    assert!((freq - 440.0).abs() < 2.0, "{freq} Hz");
+    let peak = out.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+    assert!((0.99..=1.0).contains(&peak), "peak {peak}");
+  }
+
+  #[test]
+  fn stereo_channels_stay_apart() {
+    // left a sine, right its opposite
+    let input: Vec<f32> = sine(440.0, 44100, 4410).into_iter().flat_map(|s| [s, -s]).collect();
+    let mut resampler = Resampler::new(44100, 48000, 2, Duration::from_millis(50));
+    let out = resampler.process(&input).to_vec();
+
+    assert_eq!(out.len() % 2, 0);
+    assert!(out.chunks_exact(2).all(|frame| (frame[0] + frame[1]).abs() < 1e-6));
+  }
+
+  #[test]
+  fn rate_stays_within_bounds() {
+    let mut resampler = Resampler::new(44100, 48000, 1, Duration::from_millis(50));
+    let nominal = resampler.ratio;
+    for queued in [0, 1200, 2400, 4800, 100_000] {
+      resampler.update_rate(queued);
+      assert!((resampler.ratio / nominal - 1.0).abs() <= MAX_RATE_DELTA + 1e-12);
+    }
+    // an empty queue is filled faster, a full one slower
+    resampler.update_rate(0);
+    assert!(resampler.ratio < nominal);
+    resampler.update_rate(4800);
+    assert!(resampler.ratio > nominal);
+  }
+
+  #[test]
+  fn queue_stays_bounded() {
+    // the core makes 0.2% more audio than the device plays, which the rate makes up for
+    // with the queue settling a bit above target
+    let mut resampler = Resampler::new(44100, 48000, 1, Duration::from_millis(50));
+    let target = resampler.target_frames();
+    let input = vec![0.0; 737];
+    let mut queued = 0usize;
+
+    // ten minutes of frames, the device plays 800 frames each
+    for frame in 0..36000 {
+      resampler.update_rate(queued);
+      queued += resampler.process(&input).len();
+      queued = queued.saturating_sub(800);
+      if frame > 3600 {
+        assert!((target..2 * target).contains(&queued), "frame {frame}: {queued} queued");
+      }
+    }
+  }
+}
@@ -0,0 +1,85 @@
+use std::collections::VecDeque;
+
+use crate::emu::Emulator;
+
+// frames between two snapshots, rewinding goes back this many frames per shown one
+const SNAPSHOT_INTERVAL: u32 = 4;
+// about 30 seconds of play at 60 fps
+const MAX_SNAPSHOTS: usize = 450;
+
+/// Snapshots of the last seconds of play, stepped back through while the rewind hotkey is held.
+#[derive(Default)]
+pub struct Rewind {
+  snapshots: VecDeque<Vec<u8>>,
+  // since the last snapshot
+  frames: u32,
+  // the core can't save states, there's nothing to keep
+  unsupported: bool,
+}
+
+impl Rewind {
+  /// Called after every stepped frame, keeps a snapshot every few.
+  pub fn frame(&mut self, emu: &Emulator) {
+    if self.unsupported { return; }
+    self.frames += 1;
+    if self.frames < SNAPSHOT_INTERVAL { return; }
+    self.frames = 0;
+
+    match emu.save_state() {
+      Ok(state) => {
+        if self.snapshots.len() == MAX_SNAPSHOTS {
+          self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(state);
+      }
+      Err(_) => {
+        self.snapshots.clear();
+        self.unsupported = true;
+      }
+    }
+  }
+
+  /// Puts the core back to the latest snapshot, which is then forgotten. False when there's none left.
+  pub fn step_back(&mut self, emu: &mut Emulator) -> bool {
+    let Some(state) = self.snapshots.pop_back() else { return false };
+    self.frames = 0;
+    if emu.load_state(&state).is_err() {
+      self.snapshots.clear();
+      return false;
+    }
+    true
+  }
+
+  /// Forgets the snapshots, for a new game.
+  pub fn clear(&mut self) {
+    *self = Self::default();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_core::TestCore;
+
+  #[test]
+  fn keeps_only_the_last_snapshots() {
+    let mut emu: Emulator = Box::new(TestCore::new());
+    let mut rewind = Rewind::default();
+    for _ in 0..(MAX_SNAPSHOTS + 10) * SNAPSHOT_INTERVAL as usize {
+      emu.step_one_frame();
+      emu.discard_samples();
+      rewind.frame(&emu);
+    }
+    assert_eq!(rewind.snapshots.len(), MAX_SNAPSHOTS);
+
+    // back to the frame the latest snapshot was taken on
+    let hash = emu.frame_hash();
+    emu.step_one_frame();
+    assert!(rewind.step_back(&mut emu));
+    assert_eq!(emu.frame_hash(), hash);
+
+    let mut steps = 1;
+    while rewind.step_back(&mut emu) { steps += 1; }
+    assert_eq!(steps, MAX_SNAPSHOTS);
+  }
+}
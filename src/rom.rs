@@ -0,0 +1,218 @@
+use std::{error::Error, fmt, fs, io::{Cursor, Read}, path::{Path, PathBuf}};
+
+use flate2::read::GzDecoder;
+use log::{info, warn};
+use sevenz_rust::{Password, SevenZReader};
+use zip::ZipArchive;
+
+use crate::{cores::{is_rom, CORES}, menu::ListMenu};
+
+const ARCHIVE_EXTENSIONS: [&str; 3] = ["zip", "gz", "7z"];
+const ZIP_MAGIC: [u8; 4] = [b'P', b'K', 0x03, 0x04];
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const SEVENZ_MAGIC: [u8; 6] = [b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c];
+// no supported cartridge comes close, anything larger is a decompression bomb or not a ROM
+const MAX_ROM_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Everything that can be opened, the cores' ROMs or archives holding them.
+pub fn openable_extensions() -> Vec<&'static str> {
+  CORES.iter().flat_map(|core| core.extensions.iter().copied()).chain(ARCHIVE_EXTENSIONS).collect()
+}
+
+// in the cores' order, when an archive holds more than one ROM
+fn extension_rank(name: &str) -> Option<usize> {
+  let ext = Path::new(name).extension()?.to_str()?.to_ascii_lowercase();
+  CORES.iter().flat_map(|core| core.extensions).position(|rom_ext| *rom_ext == ext)
+}
+
+// decompresses at most MAX_ROM_SIZE bytes, erroring out past that
+fn read_bounded(reader: impl Read) -> Result<Vec<u8>, String> {
+  let mut bytes = Vec::new();
+  reader.take(MAX_ROM_SIZE + 1).read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+  if bytes.len() as u64 > MAX_ROM_SIZE {
+    return Err(format!("decompresses to more than {} MiB", MAX_ROM_SIZE / 1024 / 1024));
+  }
+  Ok(bytes)
+}
+
+/// More than one entry of an archive is a valid ROM, and none was asked for.
+#[derive(Debug)]
+pub struct MultipleRoms(pub Vec<String>);
+
+impl fmt::Display for MultipleRoms {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "archive holds {} ROMs", self.0.len())
+  }
+}
+impl Error for MultipleRoms {}
+
+/// On-screen list of the ROMs in an archive, shown when loading it is ambiguous.
+pub struct RomChooser {
+  pub path: PathBuf,
+  pub menu: ListMenu,
+}
+
+impl RomChooser {
+  pub fn new(path: PathBuf, entries: Vec<String>) -> Self {
+    let title = match path.file_name() {
+      Some(name) => format!("Pick a ROM from {}", name.to_string_lossy()),
+      None => "Pick a ROM".to_string(),
+    };
+    Self { path, menu: ListMenu::new(title, entries) }
+  }
+}
+
+fn read_zip(data: &[u8], path: &Path, wanted: Option<&str>) -> Result<Vec<u8>, Box<dyn Error>> {
+  let corrupted = |e| format!("{} is a corrupted zip: {e}", path.display());
+  let mut archive = ZipArchive::new(Cursor::new(data)).map_err(corrupted)?;
+
+  // a stale choice (the archive changed) falls back to looking for ROMs again
+  if let Some(entry) = wanted.and_then(|name| archive.by_name(name).ok()) {
+    let name = entry.name().to_string();
+    let bytes = read_bounded(entry).map_err(|e| format!("{name} in {}: {e}", path.display()))?;
+    if is_rom(&bytes) {
+      info!("Loading {name} from {}", path.display());
+      return Ok(bytes);
+    }
+  }
+
+  let mut by_extension = Vec::new();
+  for i in 0..archive.len() {
+    let entry = archive.by_index(i).map_err(corrupted)?;
+    if entry.is_dir() { continue; }
+    if let Some(rank) = extension_rank(entry.name()) {
+      by_extension.push((rank, i));
+    }
+  }
+  by_extension.sort();
+
+  // entries with a ROM extension first, then whatever looks like a ROM
+  let candidates = by_extension.iter().map(|(_, i)| *i)
+    .chain((0..archive.len()).filter(|i| !by_extension.iter().any(|(_, j)| j == i)));
+
+  let mut roms = Vec::new();
+  for i in candidates {
+    let entry = archive.by_index(i).map_err(corrupted)?;
+    if entry.is_dir() { continue; }
+
+    let name = entry.name().to_string();
+    let bytes = read_bounded(entry).map_err(|e| format!("{name} in {}: {e}", path.display()))?;
+    if is_rom(&bytes) {
+      roms.push((name, bytes));
+    }
+  }
+
+  choose_rom(roms, None, path)
+}
+
+// `roms` are the archive entries that passed the ROM checks, in order of preference
+fn choose_rom(mut roms: Vec<(String, Vec<u8>)>, wanted: Option<&str>, path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+  if let Some(i) = wanted.and_then(|wanted| roms.iter().position(|(name, _)| name == wanted)) {
+    roms.swap(0, i);
+    roms.truncate(1);
+  }
+
+  match roms.len() {
+    0 => Err(format!("No valid ROM in {}", path.display()).into()),
+    1 => {
+      let (name, bytes) = roms.pop().unwrap();
+      info!("Loading {name} from {}", path.display());
+      Ok(bytes)
+    }
+    _ => Err(Box::new(MultipleRoms(roms.into_iter().map(|(name, _)| name).collect()))),
+  }
+}
+
+// every ROM in the archive, by extension preference; errors only when it can't be decoded
+fn read_7z(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, sevenz_rust::Error> {
+  let mut archive = SevenZReader::new(Cursor::new(data), data.len() as u64, Password::empty())?;
+
+  let mut roms = Vec::new();
+  archive.for_each_entries(|entry, reader| {
+    if entry.is_directory() || !entry.has_stream || entry.size() > MAX_ROM_SIZE {
+      return Ok(true);
+    }
+    let bytes = read_bounded(reader).map_err(sevenz_rust::Error::other)?;
+    if is_rom(&bytes) {
+      roms.push((entry.name().to_string(), bytes));
+    }
+    Ok(true)
+  })?;
+
+  roms.sort_by_key(|(name, _)| extension_rank(name).unwrap_or(usize::MAX));
+  Ok(roms)
+}
+
+/// Reads the ROM bytes at `path`, extracting them from an archive or gzip file if needed.
+/// The format is told by the magic bytes, so misnamed files still open.
+/// `entry` names the archive entry to use, when the archive holds several ROMs.
+pub fn read_rom(path: &Path, entry: Option<&str>) -> Result<Vec<u8>, Box<dyn Error>> {
+  let data = fs::read(path)?;
+
+  if data.starts_with(&ZIP_MAGIC) {
+    read_zip(&data, path, entry)
+  } else if data.starts_with(&GZIP_MAGIC) {
+    read_bounded(GzDecoder::new(data.as_slice()))
+      .map_err(|e| format!("Couldn't decompress {}: {e}", path.display()).into())
+  } else if data.starts_with(&SEVENZ_MAGIC) {
+    match read_7z(&data) {
+      Ok(roms) => choose_rom(roms, entry, path),
+      Err(e) => {
+        warn!("Couldn't decode {} as 7z ({e}), trying it as a plain ROM", path.display());
+        Ok(data)
+      }
+    }
+  } else {
+    Ok(data)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Write;
+  use flate2::{write::GzEncoder, Compression};
+
+  fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+  }
+
+  fn read_fixture(name: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+    let path = std::env::temp_dir().join(format!("cmbemu-test-{}-{name}", std::process::id()));
+    fs::write(&path, data).unwrap();
+    let rom = read_rom(&path, None).map_err(|e| e.to_string());
+    fs::remove_file(&path).unwrap();
+    rom
+  }
+
+  #[test]
+  fn reads_gzipped_roms() {
+    // an iNES header and one PRG bank
+    let mut rom = b"NES\x1a\x01\x00".to_vec();
+    rom.resize(16 + 0x4000, 0xea);
+    // the magic bytes tell it's gzip, whatever the name
+    assert_eq!(read_fixture("game.nes.gz", &gzip(&rom)).unwrap(), rom);
+    assert_eq!(read_fixture("game.nes", &gzip(&rom)).unwrap(), rom);
+  }
+
+  #[test]
+  fn plain_files_are_read_as_they_are() {
+    assert_eq!(read_fixture("plain.gb", b"not gzipped").unwrap(), b"not gzipped");
+  }
+
+  #[test]
+  fn decompression_is_bounded() {
+    let bomb = gzip(&vec![0; MAX_ROM_SIZE as usize + 1]);
+    let err = read_fixture("bomb.gz", &bomb).unwrap_err();
+    assert!(err.contains("more than 16 MiB"), "{err}");
+  }
+
+  #[test]
+  fn broken_gzip_is_an_error() {
+    let mut data = gzip(b"some rom bytes");
+    data.truncate(data.len() / 2);
+    assert!(read_fixture("broken.gz", &data).is_err());
+  }
+}
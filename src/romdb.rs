@@ -0,0 +1,89 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use log::warn;
+
+use crate::{hash::fnv1a, storage};
+
+const INES_HEADER_LEN: usize = 16;
+
+/// Identity of the loaded ROM, computed on the decompressed bytes so a game matches whatever it came packed in.
+#[derive(Default)]
+pub struct RomInfo {
+  pub crc32: u32,
+  // what states and the data directory were keyed by before CRC32, only used to find them
+  pub legacy_hash: u64,
+  pub title: String,
+}
+
+impl RomInfo {
+  /// The title is the No-Intro one when known, else the one in the cartridge header, else the file name.
+  pub fn new(bytes: &[u8], path: &Path, titles: &TitleDb, header_title: Option<String>) -> Self {
+    let crc32 = crc32fast::hash(bytes);
+    let title = titles.lookup(bytes, crc32)
+      .or(header_title)
+      .or_else(|| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+      .unwrap_or_default();
+
+    Self { crc32, legacy_hash: fnv1a(bytes), title }
+  }
+}
+
+/// Canonical titles by CRC32, read from the No-Intro (logiqx xml) `.dat` files in the config directory.
+#[derive(Default)]
+pub struct TitleDb {
+  titles: HashMap<u32, String>,
+}
+
+// value of `attr="..."` in an xml tag
+fn attribute<'a>(line: &'a str, attr: &str) -> Option<&'a str> {
+  let start = line.find(&format!(" {attr}=\""))? + attr.len() + 3;
+  let len = line[start..].find('"')?;
+  Some(&line[start..start + len])
+}
+
+fn unescape(text: &str) -> String {
+  text.replace("&quot;", "\"").replace("&apos;", "'")
+    .replace("&lt;", "<").replace("&gt;", ">")
+    .replace("&amp;", "&")
+}
+
+impl TitleDb {
+  pub fn load() -> Self {
+    let mut db = Self::default();
+    let Some(dir) = storage::config_dir() else { return db };
+    let Ok(entries) = fs::read_dir(dir) else { return db };
+
+    for path in entries.flatten().map(|entry| entry.path()) {
+      if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("dat")) {
+        match fs::read_to_string(&path) {
+          Ok(dat) => db.parse(&dat),
+          Err(msg) => warn!("Couldn't read {}: {msg}", path.display()),
+        }
+      }
+    }
+    db
+  }
+
+  fn parse(&mut self, dat: &str) {
+    let mut game = None;
+    for line in dat.lines().map(str::trim_start) {
+      if line.starts_with("<game ") {
+        game = attribute(line, "name").map(unescape);
+      } else if line.starts_with("<rom ") {
+        let crc = attribute(line, "crc").and_then(|crc| u32::from_str_radix(crc, 16).ok());
+        if let (Some(crc), Some(game)) = (crc, &game) {
+          self.titles.insert(crc, game.clone());
+        }
+      }
+    }
+  }
+
+  fn lookup(&self, bytes: &[u8], crc32: u32) -> Option<String> {
+    // No-Intro hashes NES ROMs without their iNES header
+    let headerless = || bytes.starts_with(b"NES\x1a").then(|| crc32fast::hash(&bytes[INES_HEADER_LEN.min(bytes.len())..]));
+
+    self.titles.get(&crc32)
+      .or_else(|| headerless().and_then(|crc| self.titles.get(&crc)))
+      .cloned()
+  }
+}
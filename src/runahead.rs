@@ -0,0 +1,98 @@
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use crate::emu::{EmuError, Emulator};
+
+pub const MAX_RUN_AHEAD: u8 = 2;
+// a state save and load costlier than this would eat too much of the frame
+const MAX_ROUNDTRIP: Duration = Duration::from_millis(2);
+
+/// Run-ahead: after each frame, the state is saved, the core runs a few frames further with the current input
+/// and the state is loaded back. The further frame is shown, cutting the input lag the game itself adds,
+/// while the canonical timeline (and its audio) stays as if it never happened.
+// a frame stepped from a restored state must be the one stepped the first time, or what's shown ahead would be wrong.
+// The core is left at `state`
+fn replays_exactly(emu: &mut Emulator, state: &[u8]) -> Result<bool, EmuError> {
+  let mut step = || {
+    emu.step_one_frame();
+    emu.discard_samples();
+    let hash = emu.frame_hash();
+    emu.load_state(state).map(|_| hash)
+  };
+  Ok(step()? == step()?)
+}
+
+pub struct RunAhead {
+  frames: u8,
+  // false when the core can't do it, or not fast enough
+  supported: bool,
+  frame: Vec<u8>,
+  pitch: usize,
+  // the frame is stale after a state load or a reset, until the next run
+  valid: bool,
+}
+
+impl RunAhead {
+  pub fn new(frames: u8) -> Self {
+    Self { frames: frames.min(MAX_RUN_AHEAD), supported: false, frame: Vec::new(), pitch: 0, valid: false }
+  }
+
+  /// Times a state roundtrip of the loaded core, enabling run-ahead only if it's fast enough.
+  pub fn probe(&mut self, emu: &mut Emulator) {
+    self.valid = false;
+    self.supported = false;
+    if self.frames == 0 { return; }
+
+    let start = Instant::now();
+    let roundtrip = emu.save_state().and_then(|state| emu.load_state(&state).map(|_| state));
+    let elapsed = start.elapsed();
+
+    match roundtrip.and_then(|state| replays_exactly(emu, &state)) {
+      Err(msg) => warn!("Run-ahead disabled: {msg}"),
+      Ok(_) if elapsed > MAX_ROUNDTRIP => {
+        warn!("Run-ahead disabled: saving and loading a state takes {:.1} ms", elapsed.as_secs_f32() * 1000.0);
+      }
+      Ok(false) => warn!("Run-ahead disabled: the core doesn't restore its states exactly"),
+      Ok(true) => {
+        info!("Running {} frame(s) ahead", self.frames);
+        self.supported = true;
+      }
+    }
+  }
+
+  pub fn invalidate(&mut self) {
+    self.valid = false;
+  }
+
+  /// Runs ahead from the frame just stepped, calling `after_step` after each frame like the canonical steps do.
+  /// Audio produced meanwhile is thrown away.
+  pub fn run(&mut self, emu: &mut Emulator, mut after_step: impl FnMut(&mut Emulator)) {
+    if !self.supported { return; }
+    let Ok(state) = emu.save_state() else { return };
+
+    for _ in 0..self.frames {
+      emu.step_one_frame();
+      after_step(emu);
+      emu.discard_samples();
+    }
+    let (framebuf, pitch) = emu.framebuf();
+    self.frame.clear();
+    self.frame.extend_from_slice(framebuf);
+    self.pitch = pitch;
+
+    match emu.load_state(&state) {
+      Ok(_) => self.valid = true,
+      Err(msg) => {
+        warn!("Run-ahead disabled, couldn't restore the state: {msg}");
+        self.supported = false;
+        self.valid = false;
+      }
+    }
+  }
+
+  /// The frame to show instead of the core's, when there's one.
+  pub fn framebuf(&self) -> Option<(&[u8], usize)> {
+    self.valid.then_some((self.frame.as_slice(), self.pitch))
+  }
+}
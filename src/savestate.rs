@@ -0,0 +1,223 @@
+use std::{fs, io::{Read, Write}, path::Path};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use crate::{emu::EmuError, storage};
+
+const MAGIC: &[u8; 4] = b"CMBS";
+pub const VERSION: u16 = 2;
+// identified the ROM by a hash the frontend doesn't compute anymore
+const FNV_VERSION: u16 = 1;
+// headerless states written before the header existed
+pub const LEGACY_VERSION: u16 = 0;
+const HEADER_LEN: usize = MAGIC.len() + 2 + 1 + 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum CoreId {
+  Nes = 1,
+  Gameboy = 2,
+}
+impl CoreId {
+  fn from_u8(id: u8) -> Option<Self> {
+    match id {
+      1 => Some(CoreId::Nes),
+      2 => Some(CoreId::Gameboy),
+      _ => None,
+    }
+  }
+
+  pub fn dir_name(&self) -> &'static str {
+    match self {
+      CoreId::Nes => "nes",
+      CoreId::Gameboy => "gb",
+    }
+  }
+
+  pub fn from_dir_name(name: &str) -> Option<Self> {
+    [CoreId::Nes, CoreId::Gameboy].into_iter().find(|core| core.dir_name() == name)
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      CoreId::Nes => "NES",
+      CoreId::Gameboy => "Game Boy",
+    }
+  }
+}
+
+/// Identifies what a state belongs to; written before the serialized state, and checked on load.
+pub struct StateHeader {
+  pub core: CoreId,
+  pub rom_crc: u32,
+}
+
+pub struct StateFile {
+  pub version: u16,
+  pub payload: Vec<u8>,
+}
+
+impl StateHeader {
+  fn to_bytes(&self) -> [u8; HEADER_LEN] {
+    let mut bytes = [0; HEADER_LEN];
+    bytes[0..4].copy_from_slice(MAGIC);
+    bytes[4..6].copy_from_slice(&VERSION.to_le_bytes());
+    bytes[6] = self.core as u8;
+    bytes[7..15].copy_from_slice(&(self.rom_crc as u64).to_le_bytes());
+    bytes
+  }
+
+  /// Checks the header at the start of `data` against this one, returning the state version.
+  fn verify(&self, data: &[u8]) -> Result<u16, String> {
+    let version = u16::from_le_bytes([data[4], data[5]]);
+    if version > VERSION {
+      return Err(format!("state was created by a newer build (format version {version})"));
+    }
+
+    let core = CoreId::from_u8(data[6]).ok_or("state was created for an unknown core")?;
+    if core != self.core {
+      return Err(format!("state was created for the {} core", core.name()));
+    }
+
+    // older hashes can't be checked, those states were found in the ROM's own directory anyway
+    let rom_hash = u64::from_le_bytes(data[7..15].try_into().unwrap());
+    if version > FNV_VERSION && rom_hash != self.rom_crc as u64 {
+      return Err("state was created for a different ROM".into());
+    }
+
+    Ok(version)
+  }
+}
+
+fn is_state_file(data: &[u8]) -> bool {
+  data.starts_with(MAGIC)
+}
+
+fn read_header_bytes(path: &Path) -> Option<[u8; HEADER_LEN]> {
+  let mut bytes = [0; HEADER_LEN];
+  fs::File::open(path).and_then(|mut file| file.read_exact(&mut bytes)).ok()?;
+  is_state_file(&bytes).then_some(bytes)
+}
+
+/// Whether the file at `path` starts with a state header.
+pub fn is_state_at(path: &Path) -> bool {
+  read_header_bytes(path).is_some()
+}
+
+/// Reads only the header of the state at `path`, when it has one that identifies the ROM.
+pub fn peek_header(path: &Path) -> Option<StateHeader> {
+  let bytes = read_header_bytes(path)?;
+  let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+  if version <= FNV_VERSION { return None; }
+
+  let core = CoreId::from_u8(bytes[6])?;
+  let rom_crc = u64::from_le_bytes(bytes[7..15].try_into().unwrap()) as u32;
+  Some(StateHeader { core, rom_crc })
+}
+
+/// Writes the header followed by the compressed payload.
+pub fn write_file(
+  path: &Path,
+  header: &StateHeader,
+  payload: impl FnOnce(&mut dyn Write) -> Result<(), EmuError>,
+) -> Result<(), EmuError> {
+  let mut encoder = GzEncoder::new(header.to_bytes().to_vec(), Compression::fast());
+  payload(&mut encoder)?;
+  let data = encoder.finish()?;
+  Ok(storage::write(path, &data)?)
+}
+
+/// Reads a state file, verifying its header against `expected`.
+/// Legacy files without a header are returned untouched, with `LEGACY_VERSION`.
+pub fn read_file(path: &Path, expected: &StateHeader) -> Result<StateFile, EmuError> {
+  let data = fs::read(path)?;
+
+  if !is_state_file(&data) {
+    return Ok(StateFile { version: LEGACY_VERSION, payload: data });
+  }
+  if data.len() < HEADER_LEN {
+    return Err(EmuError::Serde("state file is truncated".into()));
+  }
+
+  let version = expected.verify(&data).map_err(EmuError::Incompatible)?;
+  let mut payload = Vec::new();
+  GzDecoder::new(&data[HEADER_LEN..])
+    .read_to_end(&mut payload)
+    .map_err(|e| EmuError::Serde(format!("state file is corrupted: {e}")))?;
+
+  migrate(version, payload)
+    .map(|payload| StateFile { version: VERSION, payload })
+    .map_err(EmuError::Incompatible)
+}
+
+// Brings an older payload up to the current format, one version at a time.
+fn migrate(version: u16, payload: Vec<u8>) -> Result<Vec<u8>, String> {
+  match version {
+    // only the header changed
+    FNV_VERSION | VERSION => Ok(payload),
+    _ => Err(format!("state format version {version} can't be migrated")),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::path::PathBuf;
+
+  fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("cmbemu-test-{}-{name}", std::process::id()))
+  }
+
+  const HEADER: StateHeader = StateHeader { core: CoreId::Nes, rom_crc: 0xdead_beef };
+
+  #[test]
+  fn round_trips_compressed() {
+    let path = temp_path("round-trip.state");
+    // states are mostly zeroed RAM, which compresses well
+    let state: Vec<u8> = (0..0x10000u32).map(|i| if i % 64 == 0 { i as u8 } else { 0 }).collect();
+
+    write_file(&path, &HEADER, |out| Ok(out.write_all(&state)?)).unwrap();
+    let size = fs::metadata(&path).unwrap().len() as usize;
+    let read = read_file(&path, &HEADER).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(read.version, VERSION);
+    assert_eq!(read.payload, state);
+    assert!(size < state.len() / 10, "{size} bytes");
+  }
+
+  #[test]
+  fn headerless_files_are_legacy() {
+    let path = temp_path("legacy.state");
+    fs::write(&path, b"(cpu: (pc: 0))").unwrap();
+    let read = read_file(&path, &HEADER).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(read.version, LEGACY_VERSION);
+    assert_eq!(read.payload, b"(cpu: (pc: 0))");
+  }
+
+  #[test]
+  fn rejects_other_roms_and_cores() {
+    let path = temp_path("other.state");
+    write_file(&path, &HEADER, |out| Ok(out.write_all(&[1, 2, 3])?)).unwrap();
+    let other_rom = read_file(&path, &StateHeader { core: CoreId::Nes, rom_crc: 1 });
+    let other_core = read_file(&path, &StateHeader { core: CoreId::Gameboy, rom_crc: HEADER.rom_crc });
+    fs::remove_file(&path).unwrap();
+
+    assert!(matches!(other_rom, Err(EmuError::Incompatible(_))));
+    assert!(matches!(other_core, Err(EmuError::Incompatible(_))));
+  }
+
+  #[test]
+  fn write_leaves_no_temporary_file() {
+    let path = temp_path("replaced.state");
+    write_file(&path, &HEADER, |out| Ok(out.write_all(&[1])?)).unwrap();
+    write_file(&path, &HEADER, |out| Ok(out.write_all(&[2])?)).unwrap();
+
+    let mut tmp_path = path.clone().into_os_string();
+    tmp_path.push(".tmp");
+    assert!(!Path::new(&tmp_path).exists());
+    assert_eq!(read_file(&path, &HEADER).unwrap().payload, [2]);
+    fs::remove_file(&path).unwrap();
+  }
+}
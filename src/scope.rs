@@ -0,0 +1,65 @@
+use std::collections::VecDeque;
+
+use crate::emu::EmuPixelFormat;
+
+// sample frames kept, per channel
+const SCOPE_LEN: usize = 1024;
+// of the image height
+const STRIP_FRACTION: usize = 5;
+const CHANNEL_COLORS: [[u8; 3]; 2] = [[0x40, 0xff, 0x40], [0xff, 0x60, 0x40]];
+
+/// Waveform of the latest audio, drawn as a strip along the bottom of the image.
+#[derive(Default)]
+pub struct Scope {
+  // interleaved, as the core outputs them
+  samples: VecDeque<f32>,
+  channels: usize,
+}
+
+impl Scope {
+  pub fn push(&mut self, samples: &[f32], channels: usize) {
+    let channels = channels.max(1);
+    if channels != self.channels {
+      self.samples.clear();
+      self.channels = channels;
+    }
+
+    self.samples.extend(samples);
+    let excess = self.samples.len().saturating_sub(SCOPE_LEN * channels);
+    self.samples.drain(..excess);
+  }
+
+  /// Draws over the bottom of an image in `format`: the strip is darkened, then each channel is plotted in its color.
+  pub fn draw(&self, image: &mut [u8], pitch: usize, (width, height): (usize, usize), format: EmuPixelFormat) {
+    let bpp = format.bytes_per_pixel();
+    let strip_height = height / STRIP_FRACTION;
+    if strip_height < 2 || width == 0 { return; }
+    let top = height - strip_height;
+
+    for row in image.chunks_mut(pitch).skip(top).take(strip_height) {
+      for px in row[..width * bpp].chunks_exact_mut(bpp) {
+        format.set_rgb(px, format.rgb(px).map(|c| c / 3));
+      }
+    }
+
+    let frames = self.samples.len() / self.channels.max(1);
+    if frames == 0 { return; }
+    let half = (strip_height - 1) as f32 / 2.0;
+    let to_y = |sample: f32| top + (half - sample.clamp(-1.0, 1.0) * half).round() as usize;
+
+    for ch in 0..self.channels {
+      let color = CHANNEL_COLORS[ch % CHANNEL_COLORS.len()];
+      let mut prev = None;
+      for x in 0..width {
+        let frame = x * frames / width;
+        let y = to_y(self.samples[frame * self.channels + ch]);
+        // a vertical span up to the previous point, so steep edges stay connected
+        let (from, to) = prev.map_or((y, y), |prev: usize| (prev.min(y), prev.max(y)));
+        for y in from..=to {
+          format.set_rgb(&mut image[y * pitch + x * bpp..], color);
+        }
+        prev = Some(y);
+      }
+    }
+  }
+}
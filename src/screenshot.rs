@@ -0,0 +1,44 @@
+use std::{io, path::Path};
+
+use crate::{emu::EmuPixelFormat, storage};
+
+const HEADER_SIZE: usize = 14 + 40;
+
+/// Writes the visible part of a frame as a 24 bit BMP, as the core made it: the filters and overlays aren't in it.
+pub fn save(path: &Path, framebuf: &[u8], pitch: usize, (left, top, width, height): (usize, usize, usize, usize), format: EmuPixelFormat) -> io::Result<()> {
+  let bpp = format.bytes_per_pixel();
+  // rows are padded to 4 bytes
+  let row_size = (width * 3).next_multiple_of(4);
+  let image_size = row_size * height;
+
+  let mut bytes = Vec::with_capacity(HEADER_SIZE + image_size);
+  bytes.extend_from_slice(b"BM");
+  bytes.extend_from_slice(&((HEADER_SIZE + image_size) as u32).to_le_bytes());
+  bytes.extend_from_slice(&[0; 4]);
+  bytes.extend_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+
+  bytes.extend_from_slice(&40u32.to_le_bytes());
+  bytes.extend_from_slice(&(width as i32).to_le_bytes());
+  bytes.extend_from_slice(&(height as i32).to_le_bytes());
+  // one plane, 24 bits per pixel, uncompressed
+  bytes.extend_from_slice(&1u16.to_le_bytes());
+  bytes.extend_from_slice(&24u16.to_le_bytes());
+  bytes.extend_from_slice(&0u32.to_le_bytes());
+  bytes.extend_from_slice(&(image_size as u32).to_le_bytes());
+  // about 72 DPI, and no palette
+  bytes.extend_from_slice(&2835i32.to_le_bytes());
+  bytes.extend_from_slice(&2835i32.to_le_bytes());
+  bytes.extend_from_slice(&[0; 8]);
+
+  // bottom row first, in BGR order
+  for y in (top..top + height).rev() {
+    let row = &framebuf[y * pitch + left * bpp..];
+    for px in row.chunks_exact(bpp).take(width) {
+      let [r, g, b] = format.rgb(px);
+      bytes.extend_from_slice(&[b, g, r]);
+    }
+    bytes.extend(std::iter::repeat_n(0, row_size - width * 3));
+  }
+
+  storage::write(path, &bytes)
+}
@@ -1,39 +1,139 @@
-use std::error::Error;
-use sdl2::{controller::GameController, render::Canvas, video::Window, AudioSubsystem, EventPump, GameControllerSubsystem, Sdl, VideoSubsystem};
+use std::{error::Error, fs};
+use log::{info, warn};
+use sdl2::{controller::GameController, hint, joystick::Joystick, pixels::PixelFormatEnum, surface::Surface, video::{DisplayMode, Window}, AudioSubsystem, EventPump, GameControllerSubsystem, JoystickSubsystem, Sdl, VideoSubsystem};
+
+use crate::{renderer::{self, Renderer, RendererKind}, storage};
+
+// 32x32 RGBA pixels, raw
+const ICON: &[u8; 32 * 32 * 4] = include_bytes!("../assets/icon.rgba");
+
+fn set_icon(window: &mut Window) -> Result<(), String> {
+  let mut pixels = ICON.to_vec();
+  let icon = Surface::from_data(&mut pixels, 32, 32, 32 * 4, PixelFormatEnum::RGBA32)?;
+  window.set_icon(icon);
+  Ok(())
+}
+
+/// Adds the mappings of the community gamecontrollerdb.txt in the config dir, for pads SDL doesn't know.
+/// They're added a line at a time, so a bad one only skips itself.
+fn load_controller_db(subsystem: &GameControllerSubsystem) {
+  let Some(path) = storage::config_dir().map(|dir| dir.join("gamecontrollerdb.txt")) else { return };
+  let Ok(db) = fs::read_to_string(&path) else { return };
+
+  // the file lists every platform, only this one's apply
+  let platform = format!("platform:{},", sdl2::get_platform());
+  let mut loaded = 0;
+  for (i, line) in db.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') { continue; }
+    if line.contains("platform:") && !line.contains(&platform) { continue; }
+
+    match subsystem.add_mapping(line) {
+      Ok(_) => loaded += 1,
+      Err(e) => warn!("{} line {}: {e}", path.display(), i + 1),
+    }
+  }
+  info!("Loaded {loaded} controller mappings from {}", path.display());
+}
 
 #[allow(unused)]
 pub struct Sdl2Context {
   pub ctx: Sdl,
   pub video_subsystem: VideoSubsystem,
   pub audio_subsystem: AudioSubsystem,
-  pub canvas: Canvas<Window>,
+  pub renderer: Box<dyn Renderer>,
   pub events: EventPump,
   pub controller_subsystem: GameControllerSubsystem,
   pub controllers: Vec<GameController>,
+  // for the devices without a controller mapping
+  pub joystick_subsystem: JoystickSubsystem,
+  pub joysticks: Vec<Joystick>,
 }
 
 impl Sdl2Context {
-  pub fn new(name: &str, width: u32, height: u32) -> Result<Self, Box<dyn Error>> {
+  /// With `software`, the software renderer is used right away instead of only when an accelerated one can't be made.
+  pub fn new(name: &str, width: u32, height: u32, kind: RendererKind, software: bool) -> Result<Self, Box<dyn Error>> {
+    // what audio mixers and taskbars show instead of the binary name
+    hint::set("SDL_APP_NAME", name);
+    hint::set("SDL_AUDIO_DEVICE_APP_NAME", name);
+    // sizes window in points on scaled Windows displays too, like everywhere else
+    hint::set("SDL_WINDOWS_DPI_AWARENESS", "permonitorv2");
+    hint::set("SDL_WINDOWS_DPI_SCALING", "1");
     let ctx = sdl2::init()?;
     let video_subsystem= ctx.video()?;
     let audio_subsystem = ctx.audio()?;
-    let window = video_subsystem.window(name, width, height)
+    let build_window = || -> Result<Window, Box<dyn Error>> {
+      let mut window = video_subsystem.window(name, width, height)
         .position_centered()
         .resizable()
+        .allow_highdpi()
         .build()?;
-    let canvas = window
-        .into_canvas()
-        .accelerated()
-        // .present_vsync()
-        .build()?;
+      let _ = set_icon(&mut window).inspect_err(|msg| warn!("Couldn't set the window icon: {msg}"));
+      Ok(window)
+    };
+
+    // a failed renderer takes its window with it, the retry needs a new one
+    let renderer = match software {
+      true => renderer::create(kind, build_window()?, true)?,
+      false => match renderer::create(kind, build_window()?, false) {
+        Ok(renderer) => renderer,
+        Err(e) => {
+          warn!("Couldn't create an accelerated renderer, falling back to software: {e}");
+          renderer::create(kind, build_window()?, true)?
+        }
+      }
+    };
+    info!("Using the {} renderer", renderer.name());
 
     let controller_subsystem = ctx.game_controller()?;
+    // before any controller is opened, mappings apply when one is
+    load_controller_db(&controller_subsystem);
     let controllers = Vec::new();
-    
+    let joystick_subsystem = ctx.joystick()?;
+    let joysticks = Vec::new();
+
     let events = ctx.event_pump()?;
 
     Ok(
-      Self { ctx, video_subsystem, audio_subsystem, canvas, events, controller_subsystem, controllers }
+      Self { ctx, video_subsystem, audio_subsystem, renderer, events, controller_subsystem, controllers, joystick_subsystem, joysticks }
     )
   }
+
+  /// Refresh rate of the display the window is currently on.
+  pub fn refresh_rate(&self) -> Option<i32> {
+    let display = self.renderer.window().display_index().ok()?;
+    self.video_subsystem.current_display_mode(display).ok()
+      .map(|mode| mode.refresh_rate)
+      .filter(|rate| *rate > 0)
+  }
+
+  /// The display mode at the desktop's size whose refresh is closest to `fps`, or to one of its multiples.
+  pub fn closest_display_mode(&self, fps: f32) -> Option<DisplayMode> {
+    let display = self.renderer.window().display_index().ok()?;
+    let desktop = self.video_subsystem.desktop_display_mode(display).ok()?;
+    let count = self.video_subsystem.num_display_modes(display).ok()?;
+    let distance = |mode: &DisplayMode| {
+      let hz = mode.refresh_rate as f32;
+      (hz / (hz / fps).round().max(1.0) - fps).abs()
+    };
+
+    (0..count)
+      .filter_map(|i| self.video_subsystem.display_mode(display, i).ok())
+      .filter(|mode| (mode.w, mode.h) == (desktop.w, desktop.h) && mode.refresh_rate > 0)
+      .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+  }
+
+  /// Size of the desktop the window is currently on, in the same units as the window's size.
+  pub fn desktop_size(&self) -> Option<(u32, u32)> {
+    let display = self.renderer.window().display_index().ok()?;
+    self.video_subsystem.display_bounds(display).ok()
+      .map(|bounds| (bounds.width(), bounds.height()))
+  }
+
+  /// Pixels drawn per unit of window size, 2 on a display scaled to 200%.
+  pub fn dpi_scale(&self) -> f32 {
+    let (width, _) = self.renderer.window().size();
+    let (output_width, _) = self.renderer.output_size();
+    if width == 0 { 1.0 } else { output_width as f32 / width as f32 }
+  }
 }
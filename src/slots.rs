@@ -0,0 +1,147 @@
+use std::{fs, io, path::Path, time::SystemTime};
+
+use sdl2::{pixels::{Color, PixelFormatEnum}, rect::Rect, render::Canvas, video::Window};
+
+use crate::{emu::EmuPixelFormat, osd::{draw_text, in_window_coords, GLYPH_SIZE}, storage};
+
+pub const SLOTS: u8 = 10;
+
+/// Downscaled copy of the screen at the time a state was saved.
+pub struct Thumbnail {
+  width: u32,
+  height: u32,
+  // RGBA32
+  data: Vec<u8>,
+}
+
+impl Thumbnail {
+  /// Keeps every other pixel of every other row of the visible region, for a quarter of its resolution.
+  /// Thumbnails are always stored as RGBA32, whatever the core draws in.
+  pub fn from_framebuf(framebuf: &[u8], pitch: usize, (left, top, width, height): (usize, usize, usize, usize), format: EmuPixelFormat) -> Self {
+    let (thumb_width, thumb_height) = (width / 2, height / 2);
+    let bpp = format.bytes_per_pixel();
+    let mut data = vec![0; thumb_width * thumb_height * 4];
+
+    for (y, out) in data.chunks_exact_mut(thumb_width * 4).enumerate() {
+      let row = &framebuf[(top + y * 2) * pitch + left * bpp..];
+      for (x, px) in out.chunks_exact_mut(4).enumerate() {
+        EmuPixelFormat::Rgba32.set_rgb(px, format.rgb(&row[x * 2 * bpp..]));
+      }
+    }
+
+    Self { width: thumb_width as u32, height: thumb_height as u32, data }
+  }
+
+  pub fn save(&self, path: &Path) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(4 + self.data.len());
+    bytes.extend_from_slice(&(self.width as u16).to_le_bytes());
+    bytes.extend_from_slice(&(self.height as u16).to_le_bytes());
+    bytes.extend_from_slice(&self.data);
+    storage::write(path, &bytes)
+  }
+
+  pub fn load(path: &Path) -> io::Result<Self> {
+    let bytes = fs::read(path)?;
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid thumbnail");
+
+    let header = bytes.get(0..4).ok_or_else(invalid)?;
+    let width  = u16::from_le_bytes([header[0], header[1]]) as u32;
+    let height = u16::from_le_bytes([header[2], header[3]]) as u32;
+    let data = bytes[4..].to_vec();
+
+    // a foreign or corrupt file can claim any size
+    let size = (width as usize).checked_mul(height as usize).and_then(|pixels| pixels.checked_mul(4));
+    if size != Some(data.len()) {
+      return Err(invalid());
+    }
+    Ok(Self { width, height, data })
+  }
+}
+
+/// Overlay shown while the load key is held, previewing the selected slot before loading it.
+pub struct StatePicker {
+  thumbnail: Option<Thumbnail>,
+  saved_at: Option<SystemTime>,
+}
+
+fn format_age(time: SystemTime) -> String {
+  let secs = time.elapsed().map(|age| age.as_secs()).unwrap_or(0);
+  match secs {
+    0..60 => format!("{secs}s ago"),
+    60..3600 => format!("{}m ago", secs / 60),
+    3600..86400 => format!("{}h ago", secs / 3600),
+    _ => format!("{}d ago", secs / 86400),
+  }
+}
+
+impl StatePicker {
+  pub fn new(state_path: &Path, thumb_path: &Path) -> Self {
+    let saved_at = fs::metadata(state_path).and_then(|meta| meta.modified()).ok();
+    // a missing thumbnail just shows the placeholder
+    let thumbnail = saved_at.and_then(|_| Thumbnail::load(thumb_path).ok());
+    Self { thumbnail, saved_at }
+  }
+
+  pub fn draw(&self, canvas: &mut Canvas<Window>, slot: u8) {
+    const SCALE: i32 = 2;
+    const MARGIN: i32 = 8;
+
+    in_window_coords(canvas, |canvas| {
+      let (width, _) = canvas.output_size().unwrap_or_default();
+      let (thumb_width, thumb_height) = self.thumbnail.as_ref()
+        .map(|thumb| (thumb.width, thumb.height))
+        .unwrap_or((128, 120));
+
+      let rect = Rect::new(width as i32 - thumb_width as i32 * SCALE - MARGIN, MARGIN,
+        thumb_width * SCALE as u32, thumb_height * SCALE as u32);
+
+      let creator = canvas.texture_creator();
+      let drawn = self.thumbnail.as_ref().is_some_and(|thumb| {
+        creator.create_texture_static(PixelFormatEnum::RGBA32, thumb.width, thumb.height).ok()
+          .and_then(|mut texture| {
+            texture.update(None, &thumb.data, thumb.width as usize * 4).ok()?;
+            canvas.copy(&texture, None, rect).ok()
+          })
+          .is_some()
+      });
+
+      if !drawn {
+        let prev_color = canvas.draw_color();
+        canvas.set_draw_color(Color::RGB(40, 40, 40));
+        let _ = canvas.fill_rect(rect);
+        canvas.set_draw_color(prev_color);
+
+        let label = if self.saved_at.is_some() { "NO PREVIEW" } else { "EMPTY" };
+        draw_text(canvas, rect.x() + MARGIN, rect.y() + MARGIN, SCALE, label, Color::GRAY);
+      }
+
+      let info = match self.saved_at {
+        Some(time) => format!("Slot {slot} - {}", format_age(time)),
+        None => format!("Slot {slot}"),
+      };
+      let text_y = rect.bottom() + 4;
+      draw_text(canvas, rect.x(), text_y, SCALE, &info, Color::WHITE);
+      draw_text(canvas, rect.x(), text_y + (GLYPH_SIZE + 2) * SCALE, SCALE, "<  >", Color::GRAY);
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn thumbnails_of_any_claimed_size_are_checked() {
+    let path = std::env::temp_dir().join(format!("cmbemu-test-{}-slot.thumb", std::process::id()));
+    let thumbnail = Thumbnail { width: 2, height: 1, data: vec![7; 8] };
+    thumbnail.save(&path).unwrap();
+    let loaded = Thumbnail::load(&path).unwrap();
+    assert_eq!((loaded.width, loaded.height, loaded.data), (2, 1, vec![7; 8]));
+
+    // the largest size the header can hold, with no pixels behind it
+    fs::write(&path, [0xff; 4]).unwrap();
+    let err = Thumbnail::load(&path).err().unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+  }
+}
@@ -0,0 +1,41 @@
+use std::{fs, io, path::{Path, PathBuf}};
+
+// Where the config and the saves live. Natively it's the user's config and data dirs.
+// The web build has an in-memory file system instead, mirrored to the browser's localStorage
+// (see web/index.html), which also puts back the saved files before the program starts.
+
+#[cfg(not(feature = "web"))]
+pub fn config_dir() -> Option<PathBuf> {
+  dirs::config_dir().map(|dir| dir.join("cmbemu"))
+}
+
+#[cfg(not(feature = "web"))]
+pub fn data_dir() -> Option<PathBuf> {
+  dirs::data_dir().map(|dir| dir.join("cmbemu"))
+}
+
+#[cfg(feature = "web")]
+pub fn config_dir() -> Option<PathBuf> {
+  Some(PathBuf::from("/cmbemu/config"))
+}
+
+#[cfg(feature = "web")]
+pub fn data_dir() -> Option<PathBuf> {
+  Some(PathBuf::from("/cmbemu/data"))
+}
+
+/// Writes a whole file, creating its folder if needed.
+/// Goes through a temporary file, so an interrupted write never truncates a good one.
+pub fn write(path: &Path, data: &[u8]) -> io::Result<()> {
+  if let Some(dir) = path.parent() {
+    fs::create_dir_all(dir)?;
+  }
+  let mut tmp_path = path.as_os_str().to_owned();
+  tmp_path.push(".tmp");
+  fs::write(&tmp_path, data)?;
+  fs::rename(tmp_path, path)?;
+
+  #[cfg(feature = "web")]
+  crate::web::persist(path);
+  Ok(())
+}
@@ -0,0 +1,116 @@
+use std::{alloc::{GlobalAlloc, Layout, System}, cell::Cell, sync::{Arc, Mutex}};
+
+use crate::{emu::{AudioSource, EmuCaps, EmuError, EmuInterface}, input::{GameInput, InputKind}, savestate::CoreId};
+
+// A made up core for the tests. Its frames and audio follow from the frames stepped and the buttons held,
+// so the frontend's logic can be checked without the real cores.
+
+pub const WIDTH: usize = 16;
+pub const HEIGHT: usize = 8;
+// with padding at the end of every row, which hashes must skip
+pub const PITCH: usize = WIDTH * 4 + 8;
+pub const SAMPLES_PER_FRAME: usize = 735;
+
+// counts the allocations of the test binary, per thread as the tests run in parallel
+struct CountingAlloc;
+
+thread_local! {
+  static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+    System.alloc(layout)
+  }
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+    System.realloc(ptr, layout, new_size)
+  }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+/// How many times `f` allocated or grew memory, on this thread.
+pub fn allocations(f: impl FnOnce()) -> usize {
+  let before = ALLOCATIONS.with(Cell::get);
+  f();
+  ALLOCATIONS.with(Cell::get) - before
+}
+
+#[derive(Default)]
+pub struct TestCore {
+  pub frame: u32,
+  // a bit per GameInput, per player
+  pub held: [u16; 2],
+  // every input given, in order, as (player, input, pressed). Shared, to be read once the core is boxed
+  pub events: Arc<Mutex<Vec<(usize, GameInput, bool)>>>,
+  framebuf: Vec<u8>,
+  samples: Vec<f32>,
+}
+
+impl TestCore {
+  pub fn new() -> Self {
+    Self { framebuf: vec![0; PITCH * HEIGHT], ..Default::default() }
+  }
+}
+
+impl EmuInterface for TestCore {
+  fn step_one_frame(&mut self) {
+    self.frame += 1;
+    for y in 0..HEIGHT {
+      for x in 0..WIDTH * 4 {
+        self.framebuf[y * PITCH + x] = (self.frame as usize + x * y) as u8 ^ self.held[0] as u8;
+      }
+      // garbage in the padding, which mustn't show in the hash
+      self.framebuf[y * PITCH + WIDTH * 4..(y + 1) * PITCH].fill(self.frame as u8);
+    }
+    self.samples.extend((0..SAMPLES_PER_FRAME).map(|i| (self.frame as usize + i) as f32 / 1000.0));
+  }
+
+  fn framebuf(&mut self) -> (&[u8], usize) { (&self.framebuf, PITCH) }
+  fn samples(&mut self, out: &mut Vec<f32>) { out.append(&mut self.samples); }
+  fn discard_samples(&mut self) { self.samples.clear(); }
+  fn resolution(&self) -> (usize, usize) { (WIDTH, HEIGHT) }
+  fn fps(&self) -> f32 { 60.0 }
+  fn native_audio(&self) -> Option<AudioSource> { Some(AudioSource { rate: 44100, channels: 1 }) }
+
+  fn input_event(&mut self, button: &GameInput, kind: InputKind) {
+    self.player_input_event(0, button, kind);
+  }
+  fn player_input_event(&mut self, player: usize, button: &GameInput, kind: InputKind) {
+    let pressed = matches!(kind, InputKind::Press);
+    match pressed {
+      true => self.held[player] |= 1 << *button as u16,
+      false => self.held[player] &= !(1 << *button as u16),
+    }
+    self.events.lock().unwrap().push((player, *button, pressed));
+  }
+
+  fn reset(&mut self) { self.frame = 0; }
+  fn core_id(&self) -> CoreId { CoreId::Nes }
+  fn capabilities(&self) -> EmuCaps {
+    EmuCaps { audio: true, save_states: true, second_player: true, ..Default::default() }
+  }
+
+  // like the real cores, a loaded state brings back the joypad as it was saved
+  fn save_state(&self) -> Result<Vec<u8>, EmuError> {
+    let mut state = self.frame.to_le_bytes().to_vec();
+    state.extend(self.held.iter().flat_map(|held| held.to_le_bytes()));
+    state.extend_from_slice(&self.framebuf);
+    Ok(state)
+  }
+  fn load_state(&mut self, data: &[u8]) -> Result<(), EmuError> {
+    if data.len() != 8 + PITCH * HEIGHT {
+      return Err(EmuError::Serde("wrong size".into()));
+    }
+    self.frame = u32::from_le_bytes(data[0..4].try_into().unwrap());
+    self.held = [u16::from_le_bytes([data[4], data[5]]), u16::from_le_bytes([data[6], data[7]])];
+    self.framebuf.copy_from_slice(&data[8..]);
+    Ok(())
+  }
+}
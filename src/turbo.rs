@@ -0,0 +1,107 @@
+use crate::{emu::Emulator, input::{GameInput, InputKind}, input_display::DeliveredInputs};
+
+struct TurboButton {
+  input: GameInput,
+  turbo_held: bool,
+  // the same button held through its normal binding, which turbo must not release
+  normal_held: bool,
+  // what turbo last sent to the core
+  pressed: bool,
+  frames: u32,
+}
+impl TurboButton {
+  fn new(input: GameInput) -> Self {
+    Self { input, turbo_held: false, normal_held: false, pressed: false, frames: 0 }
+  }
+
+  fn set_pressed(&mut self, emu: &mut Emulator, inputs: &mut DeliveredInputs, pressed: bool) {
+    self.pressed = pressed;
+    if pressed {
+      inputs.send(emu, 0, &self.input, InputKind::Press);
+    } else if !self.normal_held {
+      inputs.send(emu, 0, &self.input, InputKind::Release);
+    }
+  }
+}
+
+/// Auto-fire for A and B, toggled on emulated frames rather than wall clock so it stays deterministic.
+pub struct Turbo {
+  buttons: [TurboButton; 2],
+  // frames between each press/release toggle
+  interval: u32,
+}
+impl Default for Turbo {
+  fn default() -> Self {
+    Self {
+      buttons: [TurboButton::new(GameInput::A), TurboButton::new(GameInput::B)],
+      interval: 2,
+    }
+  }
+}
+
+impl Turbo {
+  /// A full press and release cycle happens `rate` times a second.
+  pub fn set_rate(&mut self, fps: f32, rate: u32) {
+    let interval = fps / (2.0 * rate.max(1) as f32);
+    self.interval = (interval.round() as u32).max(1);
+  }
+
+  fn button(&mut self, input: GameInput) -> Option<&mut TurboButton> {
+    self.buttons.iter_mut().find(|b| b.input == input)
+  }
+
+  pub fn turbo_event(&mut self, emu: &mut Emulator, inputs: &mut DeliveredInputs, input: GameInput, kind: InputKind) {
+    let Some(button) = self.button(input) else { return };
+
+    match kind {
+      InputKind::Press => {
+        if button.turbo_held { return; }
+        button.turbo_held = true;
+        button.frames = 0;
+        button.set_pressed(emu, inputs, true);
+      }
+      InputKind::Release => {
+        button.turbo_held = false;
+        // always end on a release, so the button isn't left stuck
+        button.set_pressed(emu, inputs, false);
+      }
+    }
+  }
+
+  pub fn normal_event(&mut self, input: GameInput, kind: &InputKind) {
+    if let Some(button) = self.button(input) {
+      button.normal_held = matches!(kind, InputKind::Press);
+    }
+  }
+
+  /// Called once before every emulated frame.
+  pub fn tick(&mut self, emu: &mut Emulator, inputs: &mut DeliveredInputs) {
+    for button in self.buttons.iter_mut().filter(|b| b.turbo_held) {
+      button.frames += 1;
+      if button.frames >= self.interval {
+        button.frames = 0;
+        let pressed = !button.pressed;
+        button.set_pressed(emu, inputs, pressed);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn interval_is_half_a_cycle_in_frames() {
+    let mut turbo = Turbo::default();
+    turbo.set_rate(60.0, 15);
+    assert_eq!(turbo.interval, 2);
+    turbo.set_rate(50.0, 10);
+    assert_eq!(turbo.interval, 3);
+    // never less than a frame, and a rate of 0 is taken as 1
+    turbo.set_rate(60.0, 1000);
+    assert_eq!(turbo.interval, 1);
+    turbo.set_rate(60.0, 0);
+    assert_eq!(turbo.interval, 30);
+  }
+}
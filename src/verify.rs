@@ -0,0 +1,102 @@
+use log::error;
+
+use crate::{cli::Verify, emu::Emulator, hash::chain_hash, open_rom, romdb::TitleDb, savestate::StateHeader};
+
+// steps `frames` frames, hashing each, with the audio either taken like unmuted playback does or discarded like muted
+fn run_frames(emu: &mut Emulator, frames: u32, take_audio: bool) -> u64 {
+  let mut samples = Vec::new();
+  let mut digest = None;
+  for _ in 0..frames {
+    emu.step_one_frame();
+    if take_audio {
+      samples.clear();
+      emu.samples(&mut samples);
+    } else {
+      emu.discard_samples();
+    }
+    digest = Some(chain_hash(digest, emu.frame_hash()));
+  }
+  digest.unwrap_or_default()
+}
+
+/// Runs the ROM for the given number of frames without a window or audio device,
+/// hashing every frame. Returns the process exit code.
+/// The core is stepped exactly once per frame, so the digest only depends on the ROM and the frame count.
+/// The run is done twice, muted and unmuted, as muting must never change the emulation.
+/// With --state, both runs start from that state instead of power on.
+pub fn run(verify: &Verify) -> i32 {
+  let expected = match &verify.expected {
+    Ok(expected) => *expected,
+    Err(hash) => {
+      error!("'{hash}' isn't a frame hash, --verify expects one as printed by a run without it");
+      return 2;
+    }
+  };
+  let open = || {
+    let (mut emu, rom, _) = open_rom(&verify.rom, None, &TitleDb::default())
+      .map_err(|msg| format!("Couldn't load {}: {msg}", verify.rom.display()))?;
+    if let Some(state) = &verify.state {
+      let header = StateHeader { core: emu.core_id(), rom_crc: rom.crc32 };
+      emu.load(state, &header).map_err(|msg| format!("Couldn't load state {}: {msg}", state.display()))?;
+    }
+    Ok::<_, String>(emu)
+  };
+  let (mut muted, mut unmuted) = match (open(), open()) {
+    (Ok(muted), Ok(unmuted)) => (muted, unmuted),
+    (Err(msg), _) | (_, Err(msg)) => {
+      error!("{msg}");
+      return 2;
+    }
+  };
+
+  let digest = run_frames(&mut muted, verify.frames, false);
+  let unmuted_digest = run_frames(&mut unmuted, verify.frames, true);
+  println!("{digest:016x}");
+
+  if unmuted_digest != digest {
+    error!("Muted and unmuted runs diverged: {digest:016x} against {unmuted_digest:016x}");
+    return 1;
+  }
+  match expected {
+    Some(expected) if expected != digest => {
+      error!("Frame hash mismatch: expected {expected:016x}, got {digest:016x}");
+      1
+    }
+    _ => 0,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_core::TestCore;
+
+  #[test]
+  fn runs_are_deterministic() {
+    let run = || run_frames(&mut (Box::new(TestCore::new()) as Emulator), 120, true);
+    assert_eq!(run(), run());
+    assert_ne!(run(), run_frames(&mut (Box::new(TestCore::new()) as Emulator), 121, true));
+  }
+
+  #[test]
+  fn muting_doesnt_change_the_emulation() {
+    let mut emu: Emulator = Box::new(TestCore::new());
+    for _ in 0..30 { emu.step_one_frame(); }
+    let state = emu.save_state().unwrap();
+
+    let from_state = || {
+      let mut emu: Emulator = Box::new(TestCore::new());
+      emu.load_state(&state).unwrap();
+      emu
+    };
+    let muted = run_frames(&mut from_state(), 600, false);
+    let unmuted = run_frames(&mut from_state(), 600, true);
+    assert_eq!(muted, unmuted);
+  }
+
+  #[test]
+  fn malformed_hash_fails_the_run() {
+    let verify = Verify { rom: "game.nes".into(), frames: 1, expected: Err("0xabg".into()), state: None };
+    assert_ne!(run(&verify), 0);
+  }
+}
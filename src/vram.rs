@@ -0,0 +1,165 @@
+use sdl2::{hint, pixels::{Color, PixelFormatEnum}, rect::Rect, render::{Canvas, TextureCreator}, video::{Window, WindowContext}, VideoSubsystem};
+
+use crate::{emu::EmuPixelFormat, renderer::upload_texture};
+
+pub const TILE_SIZE: usize = 8;
+// tiles per row in the tile sheet
+const SHEET_COLUMNS: usize = 16;
+// between the tile sheet and each map
+const GAP: usize = 8;
+
+/// A tile map cell: which tile, in which palette.
+#[derive(Clone, Copy, Default)]
+pub struct MapCell {
+  pub tile: usize,
+  pub palette: usize,
+}
+
+/// The part of a map on screen, in pixels. It wraps around the map edges.
+#[derive(Clone, Copy)]
+pub struct View {
+  pub x: usize,
+  pub y: usize,
+  pub width: usize,
+  pub height: usize,
+}
+
+pub struct TileMap {
+  // in tiles
+  pub width: usize,
+  pub height: usize,
+  pub cells: Vec<MapCell>,
+  pub view: Option<View>,
+}
+
+/// Core-agnostic snapshot of video memory, for the VRAM viewer.
+/// Both supported cores draw from 8x8 tiles of 2-bit color indices.
+#[derive(Default)]
+pub struct DebugVram {
+  pub tiles: Vec<[u8; TILE_SIZE * TILE_SIZE]>,
+  // four colors each, the tile sheet uses the first one
+  pub palettes: Vec<[[u8; 3]; 4]>,
+  pub maps: Vec<TileMap>,
+}
+
+impl DebugVram {
+  /// Decodes a planar 2bpp tile row: bit 7 of each plane is the leftmost pixel.
+  pub fn decode_row(tile: &mut [u8; TILE_SIZE * TILE_SIZE], row: usize, lo: u8, hi: u8) {
+    for col in 0..TILE_SIZE {
+      let bit = 7 - col;
+      tile[row * TILE_SIZE + col] = ((lo >> bit) & 1) | (((hi >> bit) & 1) << 1);
+    }
+  }
+
+  fn sheet_size(&self) -> (usize, usize) {
+    let rows = self.tiles.len().div_ceil(SHEET_COLUMNS);
+    (SHEET_COLUMNS * TILE_SIZE, rows * TILE_SIZE)
+  }
+
+  // left edge of each map in the image
+  fn map_offsets(&self) -> Vec<usize> {
+    let mut x = self.sheet_size().0 + GAP;
+    self.maps.iter().map(|map| {
+      let offset = x;
+      x += map.width * TILE_SIZE + GAP;
+      offset
+    }).collect()
+  }
+
+  /// Size of the image `render` draws: the tile sheet, then the maps side by side.
+  pub fn size(&self) -> (usize, usize) {
+    let (sheet_width, sheet_height) = self.sheet_size();
+    let width = sheet_width + self.maps.iter().map(|map| GAP + map.width * TILE_SIZE).sum::<usize>();
+    let height = self.maps.iter().map(|map| map.height * TILE_SIZE).fold(sheet_height, usize::max);
+    (width, height)
+  }
+
+  fn draw_tile(&self, out: &mut [u8], pitch: usize, x: usize, y: usize, tile: usize, palette: usize) {
+    let (Some(tile), Some(palette)) = (self.tiles.get(tile), self.palettes.get(palette)) else { return };
+    for (i, color) in tile.iter().enumerate() {
+      let offset = (y + i / TILE_SIZE) * pitch + (x + i % TILE_SIZE) * 4;
+      let [r, g, b] = palette[*color as usize & 3];
+      out[offset..offset + 4].copy_from_slice(&[r, g, b, 0xff]);
+    }
+  }
+
+  /// Draws everything into a RGBA32 image of `size()`.
+  pub fn render(&self) -> Vec<u8> {
+    let (width, height) = self.size();
+    let pitch = width * 4;
+    let mut out = vec![0; pitch * height];
+
+    for i in 0..self.tiles.len() {
+      self.draw_tile(&mut out, pitch, (i % SHEET_COLUMNS) * TILE_SIZE, (i / SHEET_COLUMNS) * TILE_SIZE, i, 0);
+    }
+    for (map, left) in self.maps.iter().zip(self.map_offsets()) {
+      for (i, cell) in map.cells.iter().enumerate() {
+        self.draw_tile(&mut out, pitch, left + (i % map.width) * TILE_SIZE, (i / map.width) * TILE_SIZE, cell.tile, cell.palette);
+      }
+    }
+    out
+  }
+
+  /// Outlines of the on screen areas, split where they wrap around their map.
+  pub fn view_rects(&self) -> Vec<Rect> {
+    let mut rects = Vec::new();
+    for (map, left) in self.maps.iter().zip(self.map_offsets()) {
+      let Some(view) = map.view else { continue };
+      let (map_width, map_height) = (map.width * TILE_SIZE, map.height * TILE_SIZE);
+
+      // each axis is at most two spans: up to the edge, then from the other side
+      let spans = |start: usize, len: usize, max: usize| {
+        let start = start % max;
+        let first = len.min(max - start);
+        [(start, first), (0, len - first)].into_iter().filter(|(_, len)| *len > 0).collect::<Vec<_>>()
+      };
+      for (x, w) in spans(view.x, view.width, map_width) {
+        for &(y, h) in &spans(view.y, view.height, map_height) {
+          rects.push(Rect::new((left + x) as i32, y as i32, w as u32, h as u32));
+        }
+      }
+    }
+    rects
+  }
+}
+
+/// Secondary window showing the core's tiles and maps, scaled to fit with nearest neighbour.
+pub struct VramViewer {
+  canvas: Canvas<Window>,
+  creator: TextureCreator<WindowContext>,
+}
+
+impl VramViewer {
+  pub fn open(video: &VideoSubsystem) -> Result<Self, String> {
+    let window = video.window("CMB Emu - VRAM", 648 * 2, 480 * 2)
+      .resizable()
+      .build()
+      .map_err(|e| e.to_string())?;
+    let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let creator = canvas.texture_creator();
+    Ok(Self { canvas, creator })
+  }
+
+  pub fn draw(&mut self, vram: Option<&DebugVram>) {
+    self.canvas.set_draw_color(Color::RGB(0x20, 0x20, 0x20));
+    self.canvas.clear();
+
+    if let Some(vram) = vram.filter(|vram| !vram.tiles.is_empty()) {
+      let (width, height) = vram.size();
+      let _ = self.canvas.set_logical_size(width as u32, height as u32);
+
+      // a texture can't be kept next to the creator it borrows, so it's made anew every time.
+      // the scale quality hint is read when a texture is created, so it's set right before
+      hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+      let texture = self.creator.create_texture_streaming(PixelFormatEnum::RGBA32, width as u32, height as u32);
+      if let Ok(mut texture) = texture {
+        let _ = upload_texture(&mut texture, &vram.render(), width * 4, EmuPixelFormat::Rgba32, None);
+        let _ = self.canvas.copy(&texture, None, None);
+      }
+
+      self.canvas.set_draw_color(Color::RED);
+      let _ = self.canvas.draw_rects(&vram.view_rects());
+    }
+    self.canvas.present();
+  }
+}
@@ -0,0 +1,70 @@
+use std::{ffi::{c_char, c_int, c_void, CStr, CString}, fs, path::{Path, PathBuf}, sync::Mutex};
+
+use log::error;
+
+// Glue for the browser build, the page side is in web/index.html
+
+extern "C" {
+  fn emscripten_set_main_loop_arg(func: extern "C" fn(*mut c_void), arg: *mut c_void, fps: c_int, simulate_infinite_loop: c_int);
+  fn emscripten_cancel_main_loop();
+  fn emscripten_run_script(script: *const c_char);
+}
+
+// where uploaded ROMs are put, in the in-memory file system
+const UPLOAD_DIR: &str = "/uploads";
+
+// the last ROM the page handed over, for the main loop to pick up
+static UPLOADED: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+fn run_script(script: String) {
+  match CString::new(script) {
+    Ok(script) => unsafe { emscripten_run_script(script.as_ptr()) },
+    Err(e) => error!("Couldn't run script: {e}"),
+  }
+}
+
+/// Hands `frame` to the browser, which calls it once per display refresh until it returns false.
+/// Never returns: main() is unwound without dropping anything, so `frame` has to own all it uses.
+pub fn run_main_loop<F: FnMut() -> bool + 'static>(frame: F) -> ! {
+  extern "C" fn call<F: FnMut() -> bool>(arg: *mut c_void) {
+    let frame = unsafe { &mut *(arg as *mut F) };
+    if !frame() {
+      unsafe { emscripten_cancel_main_loop() };
+    }
+  }
+
+  let frame = Box::into_raw(Box::new(frame));
+  unsafe { emscripten_set_main_loop_arg(call::<F>, frame as *mut c_void, 0, 1) };
+  unreachable!("emscripten_set_main_loop_arg returned")
+}
+
+/// Called by the page with the bytes of a ROM the user uploaded or dropped.
+#[no_mangle]
+pub extern "C" fn cmbemu_open_rom(name: *const c_char, data: *const u8, len: usize) {
+  let name = unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned();
+  let data = unsafe { std::slice::from_raw_parts(data, len) };
+
+  let file_name = Path::new(&name).file_name().map_or("rom".into(), |name| name.to_os_string());
+  let path = Path::new(UPLOAD_DIR).join(file_name);
+  match fs::create_dir_all(UPLOAD_DIR).and_then(|_| fs::write(&path, data)) {
+    Ok(()) => *UPLOADED.lock().unwrap() = Some(path),
+    Err(e) => error!("Couldn't keep the uploaded ROM {name}: {e}"),
+  }
+}
+
+/// The ROM uploaded since the last call, if any.
+pub fn take_uploaded_rom() -> Option<PathBuf> {
+  UPLOADED.lock().unwrap().take()
+}
+
+/// Opens the page's file picker, what's picked comes back through `cmbemu_open_rom`.
+pub fn pick_rom(extensions: &[&str]) {
+  let accept = extensions.iter().map(|ext| format!(".{ext}")).collect::<Vec<_>>().join(",");
+  run_script(format!("Module.cmbemuPickRom({accept:?})"));
+}
+
+/// Copies a file that was just written to localStorage, so it survives a reload.
+pub fn persist(path: &Path) {
+  // the debug form of a plain path is also a valid JS string literal
+  run_script(format!("Module.cmbemuPersist({:?})", path.display().to_string()));
+}